@@ -1,15 +1,23 @@
-use std::{collections::HashMap, fs::{self, File, OpenOptions}, io::{self, Seek, SeekFrom, Read, Write}, path::Path};
+use std::{collections::HashMap, fs::{self, File, OpenOptions}, io::{self, Seek, SeekFrom, Read, Write}, path::Path, sync::{Mutex, MutexGuard}};
 
 use chrono::Utc;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::{directory::DirectoryMetadata, file::FileMetadata};
+use crate::{
+    cache::{BlockCache, CacheStats, DEFAULT_CACHE_BLOCKS},
+    directory::DirectoryMetadata,
+    error::{DiscoError, Result},
+    file::FileMetadata,
+    lock::{ImageLock, LockMode},
+    path::VfsPath,
+};
 
 pub const BLOCK_SIZE: usize = 4096; // Tamanho de cada bloco (4 KB)
 pub const TOTAL_BLOCKS: usize = 1024; // Número total de blocos no disco
 pub const MAGIC_NUMBER: u32 = 0xDEADBEEF; // Identificador para validação do sistema de arquivos
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct MetadataStore {
     files: HashMap<String, FileMetadata>,
 }
@@ -21,7 +29,7 @@ impl MetadataStore {
         }
     }
 
-    pub fn load_from_file(path: &str) -> io::Result<Self> {
+    pub fn load_from_file(path: &str) -> Result<Self> {
         let mut file = File::open(path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
@@ -29,7 +37,7 @@ impl MetadataStore {
         Ok(metadata_store)
     }
 
-    pub fn save_to_file(&self, path: &str) -> io::Result<()> {
+    pub fn save_to_file(&self, path: &str) -> Result<()> {
         let contents = serde_json::to_string_pretty(self)?;
         let mut file = File::create(path)?;
         file.write_all(contents.as_bytes())?;
@@ -51,22 +59,65 @@ impl MetadataStore {
     pub fn remove_file_metadata(&mut self, name: &str) {
         self.files.remove(name);
     }
+
+    /// Caminhos de todos os arquivos registrados
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Move os arquivos registrados abaixo de um diretório para um novo caminho
+    pub fn rename_files_under(&mut self, from: &str, to: &str) {
+        let prefix = format!("{}/", from.trim_end_matches('/'));
+        let paths: Vec<String> = self
+            .files
+            .keys()
+            .filter(|path| path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for path in paths {
+            if let Some(mut metadata) = self.files.remove(&path) {
+                metadata.path = format!("{}/{}", to.trim_end_matches('/'), &path[prefix.len()..]);
+                self.files.insert(metadata.path.clone(), metadata);
+            }
+        }
+    }
+
+    /// Remove e retorna todos os arquivos registrados abaixo de um diretório
+    pub fn remove_files_under(&mut self, directory_path: &str) -> Vec<FileMetadata> {
+        let prefix = format!("{}/", directory_path.trim_end_matches('/'));
+        let paths: Vec<String> = self
+            .files
+            .keys()
+            .filter(|path| path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        paths
+            .iter()
+            .filter_map(|path| self.files.remove(path))
+            .collect()
+    }
 }
 
-pub fn create_file_metadata(
-    file_name: &str,
-    directory_path: &str,
-    permissions: &str,
-    size: u64,
-) -> FileMetadata {
+pub fn create_file_metadata(file_path: &VfsPath, permissions: &str, size: u64) -> FileMetadata {
     let now = Utc::now().to_rfc3339();
     FileMetadata {
-        path: format!("{}/{}", directory_path.trim_end_matches('/'), file_name), // Remove barras duplicadas
+        path: file_path.to_string(),
         permissions: permissions.to_string(),
         created_at: now.clone(),
         modified_at: now,
         size,
         block_indices: vec![],
+        archive: None,
+        versions: Vec::new(),
+        next_version: 0,
     }
 }
 
@@ -77,21 +128,39 @@ pub fn update_file_metadata(metadata: &mut FileMetadata, size: u64) {
 }
 
 #[allow(dead_code)]
-pub fn load_directory_metadata(path: &str) -> io::Result<DirectoryMetadata> {
+pub fn load_directory_metadata(path: &str) -> Result<DirectoryMetadata> {
     let json = fs::read_to_string(path)?;
     let directory: DirectoryMetadata = serde_json::from_str(&json)?;
     Ok(directory)
 }
 
 /// Estrutura para o gerenciador de blocos
+///
+/// Os blocos são lidos e escritos com E/S posicional, sem mover a posição do
+/// arquivo, então leituras e escritas de blocos diferentes podem acontecer em
+/// paralelo por `&self`. O mapa de blocos tem uma trava, mantida enquanto ele é
+/// alterado e gravado, e o cache de blocos tem outra.
+///
+/// As escritas de blocos ficam no cache até o bloco ser descartado dele, até o
+/// `sync` ou até o gerenciador ser descartado; o mapa de blocos é gravado na hora.
 pub struct BlockManager {
     file: File,
-    free_blocks: Vec<bool>, // Mapa de blocos livres (true = livre, false = ocupado)
+    block_refs: Mutex<Vec<u8>>, // Referências por bloco (0 = livre); blocos compartilhados têm mais de uma
+    cache: Mutex<BlockCache>,
+    read_only: bool,     // Imagem aberta sem permissão de escrita
+    _lock: ImageLock,    // Exclusiva para escrita, compartilhada para leitura
 }
 
+/// Maior número de referências que um bloco compartilhado pode ter
+pub const MAX_BLOCK_REFS: u8 = u8::MAX;
+
 impl BlockManager {
     /// Inicializa o sistema de persistência
-    pub fn initialize(disk_path: &str) -> io::Result<Self> {
+    ///
+    /// A imagem fica travada para os outros processos até o gerenciador ser
+    /// descartado; se outro processo a usa, falha com `DiscoError::Busy`.
+    pub fn initialize(disk_path: &str) -> Result<Self> {
+        let lock = ImageLock::acquire(Path::new(disk_path), LockMode::Exclusive)?;
         let file = if Path::new(disk_path).exists() {
             // Se o arquivo já existir, abre-o
             OpenOptions::new().read(true).write(true).open(disk_path)?
@@ -101,113 +170,369 @@ impl BlockManager {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(true)
                 .open(disk_path)?;
             file.set_len((BLOCK_SIZE * TOTAL_BLOCKS) as u64)?;
             BlockManager::format(&mut file)?;
             file
         };
 
-        let free_blocks = BlockManager::load_free_blocks(&file)?;
+        let block_refs = BlockManager::load_block_refs(&file)?;
+
+        Ok(BlockManager {
+            file,
+            block_refs: Mutex::new(block_refs),
+            cache: Mutex::new(BlockCache::new(DEFAULT_CACHE_BLOCKS)),
+            read_only: false,
+            _lock: lock,
+        })
+    }
+
+    /// Abre uma imagem existente sem permissão de escrita
+    ///
+    /// Leituras funcionam normalmente; qualquer alteração de blocos ou do mapa
+    /// falha com `DiscoError::ReadOnly`. Outros leitores podem abrir a mesma
+    /// imagem, mas não um processo que a altere.
+    pub fn open_read_only(disk_path: &str) -> Result<Self> {
+        if !Path::new(disk_path).exists() {
+            return Err(DiscoError::NotFound(format!(
+                "Image '{}' not found",
+                disk_path
+            )));
+        }
+        let lock = ImageLock::acquire(Path::new(disk_path), LockMode::Shared)?;
+        let file = OpenOptions::new().read(true).open(disk_path)?;
+        let block_refs = BlockManager::load_block_refs(&file)?;
+
+        Ok(BlockManager {
+            file,
+            block_refs: Mutex::new(block_refs),
+            cache: Mutex::new(BlockCache::new(DEFAULT_CACHE_BLOCKS)),
+            read_only: true,
+            _lock: lock,
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn lock_cache(&self) -> MutexGuard<'_, BlockCache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Muda o tamanho do cache, em blocos; 0 grava e lê tudo direto na imagem
+    pub fn set_cache_capacity(&self, blocks: usize) -> Result<()> {
+        let mut cache = self.lock_cache();
+        let evicted = cache.set_capacity(blocks);
+        self.write_evicted(evicted)
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.lock_cache().stats()
+    }
+
+    /// Grava na imagem os blocos alterados que estão no cache
+    pub fn sync(&self) -> Result<()> {
+        let mut cache = self.lock_cache();
+        let dirty = cache.dirty_blocks();
+        if !dirty.is_empty() {
+            debug!("Gravando {} blocos alterados", dirty.len());
+        }
+        for (index, data) in dirty {
+            self.write_to_disk(index, &data)?;
+            cache.mark_clean(index);
+        }
+        Ok(())
+    }
+
+    /// Grava os blocos alterados descartados do cache; chamado com o cache travado,
+    /// para que ninguém leia da imagem um bloco que ainda não chegou nela
+    fn write_evicted(&self, evicted: Vec<(usize, Vec<u8>)>) -> Result<()> {
+        for (index, data) in evicted {
+            self.write_to_disk(index, &data)?;
+        }
+        Ok(())
+    }
+
+    fn block_offset(index: usize) -> u64 {
+        (4 + TOTAL_BLOCKS + index * BLOCK_SIZE) as u64 // Pula o cabeçalho e o mapa de blocos
+    }
 
-        Ok(BlockManager { file, free_blocks })
+    fn write_to_disk(&self, index: usize, data: &[u8]) -> Result<()> {
+        write_all_at(&self.file, data, BlockManager::block_offset(index))?;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(DiscoError::ReadOnly(
+                "Image is opened read-only".to_string(),
+            ));
+        }
+        Ok(())
     }
 
     /// Formata o disco virtual com estrutura inicial
-    pub fn format(file: &mut File) -> io::Result<()> {
+    pub fn format(file: &mut File) -> Result<()> {
         // Escreve o magic number para validar o sistema de arquivos
         file.seek(SeekFrom::Start(0))?;
         file.write_all(&MAGIC_NUMBER.to_le_bytes())?;
 
         // Inicializa os blocos como livres
-        let free_blocks = vec![true; TOTAL_BLOCKS];
-        BlockManager::save_free_blocks(file, &free_blocks)?;
+        let block_refs = vec![0; TOTAL_BLOCKS];
+        BlockManager::save_block_refs(file, &block_refs)?;
 
         Ok(())
     }
 
-    /// Carrega o mapa de blocos livres do disco
-    pub fn load_free_blocks(mut file: &File) -> io::Result<Vec<bool>> {
+    /// Carrega o mapa de blocos do disco
+    ///
+    /// Cada byte do mapa vale 1 para um bloco livre, 0 para um bloco com uma
+    /// única referência e N >= 2 para um bloco compartilhado por N arquivos.
+    pub fn load_block_refs(file: &File) -> Result<Vec<u8>> {
         let mut buffer = vec![0u8; TOTAL_BLOCKS];
-        file.seek(SeekFrom::Start(4))?; // 4 bytes reservados para o magic number
-        file.read_exact(&mut buffer)?;
-
-        Ok(buffer.iter().map(|&b| b == 1).collect())
+        read_exact_at(file, &mut buffer, 4)?; // 4 bytes reservados para o magic number
+
+        Ok(buffer
+            .iter()
+            .map(|&b| match b {
+                0 => 1,
+                1 => 0,
+                n => n,
+            })
+            .collect())
     }
 
-    /// Salva o mapa de blocos livres no disco
-    pub fn save_free_blocks(file: &mut File, free_blocks: &[bool]) -> io::Result<()> {
-        let buffer: Vec<u8> = free_blocks.iter().map(|&b| if b { 1 } else { 0 }).collect();
-        file.seek(SeekFrom::Start(4))?; // 4 bytes reservados para o magic number
-        file.write_all(&buffer)?;
+    /// Salva o mapa de blocos no disco
+    pub fn save_block_refs(file: &File, block_refs: &[u8]) -> Result<()> {
+        let buffer: Vec<u8> = block_refs
+            .iter()
+            .map(|&refs| match refs {
+                0 => 1,
+                1 => 0,
+                n => n,
+            })
+            .collect();
+        write_all_at(file, &buffer, 4)?; // 4 bytes reservados para o magic number
 
         Ok(())
     }
 
+    fn check_index(index: usize) -> Result<()> {
+        if index >= TOTAL_BLOCKS {
+            return Err(DiscoError::InvalidArgument(
+                "Invalid block index".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Trava o mapa de blocos; uma thread que falhou com ele travado não o
+    /// deixa inconsistente, porque cada alteração é gravada inteira
+    fn lock_block_refs(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.block_refs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
     /// Aloca um bloco livre e retorna seu índice
-    pub fn allocate_block(&mut self) -> io::Result<usize> {
-        if let Some(index) = self.free_blocks.iter().position(|&b| b) {
-            self.free_blocks[index] = false;
-            BlockManager::save_free_blocks(&mut self.file, &self.free_blocks)?;
+    pub fn allocate_block(&self) -> Result<usize> {
+        self.check_writable()?;
+        let mut block_refs = self.lock_block_refs();
+        if let Some(index) = block_refs.iter().position(|&refs| refs == 0) {
+            block_refs[index] = 1;
+            BlockManager::save_block_refs(&self.file, &block_refs)?;
             Ok(index)
         } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "No free blocks available",
-            ))
+            Err(DiscoError::NoSpace("No free blocks available".to_string()))
         }
     }
 
-    /// Libera um bloco pelo índice
-    pub fn free_block(&mut self, index: usize) -> io::Result<()> {
-        if index >= TOTAL_BLOCKS {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid block index",
-            ));
+    /// Acrescenta uma referência a um bloco já alocado (cópia sem duplicar dados)
+    pub fn share_block(&self, index: usize) -> Result<()> {
+        self.check_writable()?;
+        BlockManager::check_index(index)?;
+        let mut block_refs = self.lock_block_refs();
+        match block_refs[index] {
+            0 => Err(DiscoError::InvalidArgument(
+                "Cannot share a free block".to_string(),
+            )),
+            MAX_BLOCK_REFS => Err(DiscoError::NoSpace(
+                "Block reference limit reached".to_string(),
+            )),
+            _ => {
+                block_refs[index] += 1;
+                BlockManager::save_block_refs(&self.file, &block_refs)
+            }
         }
+    }
+
+    /// Número de referências de um bloco (0 = livre)
+    pub fn block_ref_count(&self, index: usize) -> Result<u8> {
+        BlockManager::check_index(index)?;
+        Ok(self.lock_block_refs()[index])
+    }
+
+    /// Cópia do mapa de referências de todos os blocos, na ordem dos índices
+    pub fn block_refs(&self) -> Vec<u8> {
+        self.lock_block_refs().clone()
+    }
 
-        self.free_blocks[index] = true;
-        BlockManager::save_free_blocks(&mut self.file, &self.free_blocks)?;
+    /// Lê o magic number gravado no início da imagem
+    pub fn read_magic(&self) -> Result<u32> {
+        let mut buffer = [0u8; 4];
+        read_exact_at(&self.file, &mut buffer, 0)?;
+        Ok(u32::from_le_bytes(buffer))
+    }
+
+    /// Libera uma referência ao bloco; ele só volta a ficar livre na última
+    pub fn free_block(&self, index: usize) -> Result<()> {
+        self.check_writable()?;
+        BlockManager::check_index(index)?;
+
+        let mut block_refs = self.lock_block_refs();
+        block_refs[index] = block_refs[index].saturating_sub(1);
+        BlockManager::save_block_refs(&self.file, &block_refs)?;
+        if block_refs[index] == 0 {
+            // O conteúdo de um bloco livre não importa mais
+            self.lock_cache().remove(index);
+        }
 
         Ok(())
     }
 
     /// Escreve dados em um bloco
-    pub fn write_block(&mut self, index: usize, data: &[u8]) -> io::Result<()> {
+    pub fn write_block(&self, index: usize, data: &[u8]) -> Result<()> {
+        self.check_writable()?;
         if index >= TOTAL_BLOCKS {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid block index",
+            return Err(DiscoError::InvalidArgument(
+                "Invalid block index".to_string(),
             ));
         }
         if data.len() > BLOCK_SIZE {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Data exceeds block size",
+            return Err(DiscoError::InvalidArgument(
+                "Data exceeds block size".to_string(),
             ));
         }
 
-        let offset = 4 + TOTAL_BLOCKS + index * BLOCK_SIZE; // Pula o cabeçalho e o mapa de blocos
-        self.file.seek(SeekFrom::Start(offset as u64))?;
-        self.file.write_all(data)?;
-
-        Ok(())
+        // O bloco inteiro fica no cache, com o resto preenchido com zeros
+        let mut block = data.to_vec();
+        block.resize(BLOCK_SIZE, 0);
+        let mut cache = self.lock_cache();
+        let evicted = cache.insert_dirty(index, block);
+        self.write_evicted(evicted)
     }
 
     /// Lê dados de um bloco
-    pub fn read_block(&mut self, index: usize) -> io::Result<Vec<u8>> {
+    pub fn read_block(&self, index: usize) -> Result<Vec<u8>> {
         if index >= TOTAL_BLOCKS {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid block index",
+            return Err(DiscoError::InvalidArgument(
+                "Invalid block index".to_string(),
             ));
         }
 
-        let offset = 4 + TOTAL_BLOCKS + index * BLOCK_SIZE; // Pula o cabeçalho e o mapa de blocos
-        self.file.seek(SeekFrom::Start(offset as u64))?;
+        let generation = {
+            let mut cache = self.lock_cache();
+            if let Some(data) = cache.get(index) {
+                return Ok(data);
+            }
+            cache.generation()
+        };
+
+        // A leitura da imagem não trava o cache, para não atrasar as outras threads
         let mut buffer = vec![0u8; BLOCK_SIZE];
-        self.file.read_exact(&mut buffer)?;
+        read_exact_at(&self.file, &mut buffer, BlockManager::block_offset(index))?;
+        let mut cache = self.lock_cache();
+        let evicted = cache.insert_clean(index, buffer.clone(), generation, false);
+        self.write_evicted(evicted)?;
 
         Ok(buffer)
     }
-}
\ No newline at end of file
+
+    /// Carrega no cache os blocos que ainda não estão nele, lendo cada sequência
+    /// de índices consecutivos de uma vez
+    ///
+    /// Lê no máximo metade do cache, para não descartar o que acabou de ser lido.
+    /// Uma falha não é repassada: a leitura do próprio bloco a encontrará.
+    pub fn read_ahead(&self, indices: &[usize]) {
+        let (missing, generation) = {
+            let cache = self.lock_cache();
+            let limit = indices.len().min(cache.capacity() / 2);
+            let missing: Vec<usize> = indices[..limit]
+                .iter()
+                .copied()
+                .filter(|&index| index < TOTAL_BLOCKS && !cache.contains(index))
+                .collect();
+            (missing, cache.generation())
+        };
+
+        for run in missing.chunk_by(|a, b| a + 1 == *b) {
+            let mut buffer = vec![0u8; run.len() * BLOCK_SIZE];
+            let offset = BlockManager::block_offset(run[0]);
+            if let Err(e) = read_exact_at(&self.file, &mut buffer, offset) {
+                debug!("Leitura antecipada dos blocos {:?} falhou: {}", run, e);
+                return;
+            }
+            let mut cache = self.lock_cache();
+            for (&index, data) in run.iter().zip(buffer.chunks(BLOCK_SIZE)) {
+                let evicted = cache.insert_clean(index, data.to_vec(), generation, true);
+                if let Err(e) = self.write_evicted(evicted) {
+                    warn!("Falha ao gravar um bloco descartado do cache: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for BlockManager {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            warn!("Blocos alterados não foram gravados na imagem: {}", e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, data, offset)
+}
+
+// No Windows, `seek_read`/`seek_write` também não dependem de uma posição compartilhada
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buffer = &mut buffer[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match file.seek_write(data, offset)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => {
+                data = &data[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+/// Blocos mantidos em memória por padrão (256 KB)
+pub const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+/// Blocos lidos antecipadamente numa leitura sequencial
+pub const READ_AHEAD_BLOCKS: usize = 8;
+
+/// Contadores e ocupação do cache de blocos
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub capacity: usize, // Em blocos; 0 desliga o cache
+    pub cached: usize,
+    pub dirty: usize, // Alterados em memória e ainda não gravados na imagem
+    pub hits: u64,
+    pub misses: u64,
+    pub read_ahead: u64, // Blocos carregados antes de serem pedidos
+}
+
+struct Entry {
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+/// Cache LRU de blocos, sem E/S própria
+///
+/// Quem usa o cache grava os blocos alterados que ele devolve ao descartar uma
+/// entrada e os de `dirty_blocks`, marcando-os depois com `mark_clean`.
+pub(crate) struct BlockCache {
+    capacity: usize,
+    entries: HashMap<usize, Entry>,
+    by_use: BTreeMap<u64, usize>, // Do uso mais antigo ao mais recente
+    clock: u64,
+    generation: u64, // Muda a cada escrita, para descartar leituras que ficaram velhas
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+            generation: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        self.entries.contains_key(&index)
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            capacity: self.capacity,
+            cached: self.entries.len(),
+            dirty: self.entries.values().filter(|entry| entry.dirty).count(),
+            ..self.stats
+        }
+    }
+
+    /// Dados do bloco, contando um acerto ou uma falta
+    pub(crate) fn get(&mut self, index: usize) -> Option<Vec<u8>> {
+        if !self.entries.contains_key(&index) {
+            self.stats.misses += 1;
+            return None;
+        }
+        self.stats.hits += 1;
+        self.touch(index);
+        self.entries.get(&index).map(|entry| entry.data.clone())
+    }
+
+    fn touch(&mut self, index: usize) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&index) {
+            self.by_use.remove(&entry.last_used);
+            entry.last_used = self.clock;
+            self.by_use.insert(self.clock, index);
+        }
+    }
+
+    /// Guarda um bloco lido da imagem, se nada foi escrito desde `generation`
+    pub(crate) fn insert_clean(
+        &mut self,
+        index: usize,
+        data: Vec<u8>,
+        generation: u64,
+        read_ahead: bool,
+    ) -> Vec<(usize, Vec<u8>)> {
+        if self.capacity == 0 || generation != self.generation || self.contains(index) {
+            return Vec::new();
+        }
+        if read_ahead {
+            self.stats.read_ahead += 1;
+        }
+        self.insert(index, data, false)
+    }
+
+    /// Guarda um bloco alterado, que só vai para a imagem ao ser descartado ou no `sync`
+    pub(crate) fn insert_dirty(&mut self, index: usize, data: Vec<u8>) -> Vec<(usize, Vec<u8>)> {
+        self.generation += 1;
+        self.insert(index, data, true)
+    }
+
+    fn insert(&mut self, index: usize, data: Vec<u8>, dirty: bool) -> Vec<(usize, Vec<u8>)> {
+        self.remove(index);
+        self.clock += 1;
+        self.entries.insert(
+            index,
+            Entry {
+                data,
+                dirty,
+                last_used: self.clock,
+            },
+        );
+        self.by_use.insert(self.clock, index);
+        self.evict()
+    }
+
+    /// Descarta um bloco sem gravá-lo (o bloco foi liberado)
+    pub(crate) fn remove(&mut self, index: usize) {
+        if let Some(entry) = self.entries.remove(&index) {
+            self.by_use.remove(&entry.last_used);
+        }
+    }
+
+    /// Muda a capacidade, devolvendo os blocos alterados que não cabem mais
+    pub(crate) fn set_capacity(&mut self, capacity: usize) -> Vec<(usize, Vec<u8>)> {
+        self.capacity = capacity;
+        self.evict()
+    }
+
+    /// Descarta os blocos usados há mais tempo até caber na capacidade
+    fn evict(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let mut evicted = Vec::new();
+        while self.entries.len() > self.capacity {
+            let Some((_, index)) = self.by_use.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&index) {
+                if entry.dirty {
+                    evicted.push((index, entry.data));
+                }
+            }
+        }
+        evicted
+    }
+
+    /// Cópia dos blocos alterados, em ordem de índice
+    pub(crate) fn dirty_blocks(&self) -> Vec<(usize, Vec<u8>)> {
+        let mut dirty: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(&index, entry)| (index, entry.data.clone()))
+            .collect();
+        dirty.sort_by_key(|&(index, _)| index);
+        dirty
+    }
+
+    /// Marca um bloco alterado como já gravado na imagem
+    pub(crate) fn mark_clean(&mut self, index: usize) {
+        if let Some(entry) = self.entries.get_mut(&index) {
+            entry.dirty = false;
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{
+    lock::break_stale_lock,
+    script::{parse_assignment, Variables},
+    shell::{command_help, commands_usage, CommandHelp, OutputFormat, COMMANDS},
+    vfs::{OpenMode, Vfs},
+};
+
+pub const DEFAULT_IMAGE: &str = "vfs_disk.bin"; // Imagem usada quando `--image` não é informado
+
+// Códigos de saída, de acordo com o tipo do erro
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1; // Erros sem código próprio
+pub const EXIT_USAGE: i32 = 2; // Argumentos ou caminhos inválidos
+pub const EXIT_NOT_FOUND: i32 = 3;
+pub const EXIT_ALREADY_EXISTS: i32 = 4;
+pub const EXIT_READ_ONLY: i32 = 5; // Somente leitura ou sem permissão
+pub const EXIT_NOT_EMPTY: i32 = 6;
+pub const EXIT_WRONG_KIND: i32 = 7; // Arquivo onde se esperava diretório, ou o contrário
+pub const EXIT_CORRUPT: i32 = 8; // Imagem ou estado ilegível
+pub const EXIT_NO_SPACE: i32 = 9; // Sem blocos livres
+pub const EXIT_BUSY: i32 = 10; // Imagem ou ponto de montagem em uso, ou operação entre imagens
+
+/// Comandos tratados pela própria CLI, além dos de `execute`
+pub const CLI_COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        name: "shell",
+        aliases: &[],
+        usage: "shell [<image>]",
+        summary: "Abre o shell interativo sobre a imagem",
+    },
+    CommandHelp {
+        name: "run",
+        aliases: &[],
+        usage: "run [<script> | -] [NAME=value...]",
+        summary: "Executa um script de comandos (ou a entrada padrão), parando na primeira falha",
+    },
+    CommandHelp {
+        name: "help",
+        aliases: &[],
+        usage: "help [<command>]",
+        summary: "Exibe esta ajuda ou a ajuda de um comando",
+    },
+];
+
+/// Código de saída correspondente ao tipo do erro
+pub fn exit_code(error: &io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::InvalidInput => EXIT_USAGE,
+        io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+        io::ErrorKind::AlreadyExists => EXIT_ALREADY_EXISTS,
+        io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied => EXIT_READ_ONLY,
+        io::ErrorKind::DirectoryNotEmpty => EXIT_NOT_EMPTY,
+        io::ErrorKind::IsADirectory | io::ErrorKind::NotADirectory => EXIT_WRONG_KIND,
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => EXIT_CORRUPT,
+        io::ErrorKind::StorageFull => EXIT_NO_SPACE,
+        io::ErrorKind::ResourceBusy | io::ErrorKind::CrossesDevices => EXIT_BUSY,
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Logger da CLI: escreve no stderr, com o nível no início de cada linha
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Nível de log para a quantidade de `-v`: nenhum desliga o log
+pub fn log_level(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Liga o log da biblioteca no stderr, conforme a quantidade de `-v`
+pub fn init_logging(verbosity: u8) {
+    // Só falha se outro logger já foi instalado, e nesse caso ele é mantido
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(log_level(verbosity));
+}
+
+/// Ajuda de um comando da CLI ou de `execute`
+pub fn help_for(name: &str) -> Option<&'static CommandHelp> {
+    CLI_COMMANDS
+        .iter()
+        .find(|command| command.matches(name))
+        .or_else(|| command_help(name))
+}
+
+/// Texto de ajuda completo da CLI
+pub fn usage() -> String {
+    format!(
+        "Uso: disco [-v] [--image <file>] [--state-dir <directory>] [--read-only] [--break-lock] [--format text|json] <command> [args...]
+
+Opções globais:
+  --image <file>           Imagem de disco (padrão: {image})
+  --state-dir <directory>  Onde gravar a hierarquia e o diretório atual
+                           (padrão: o diretório da imagem)
+  --read-only              Abre a imagem somente para leitura: comandos que
+                           alteram o disco falham e o estado não é gravado
+  --break-lock             Limpa o PID deixado na trava por um processo que
+                           não existe mais; uma trava em uso nunca é removida
+  --format text|json       Formato da saída; em JSON, cada comando emite um
+                           objeto, inclusive os erros (padrão: text)
+  -v, --verbose            Exibe o log da biblioteca no stderr; repita para
+                           mais detalhes (-vv depuração, -vvv dados dos blocos)
+  -h, --help               Exibe esta ajuda
+
+Comandos:
+{commands}
+
+Use `disco help <command>` ou `disco <command> --help` para a ajuda de um comando.
+
+Códigos de saída:
+  {EXIT_SUCCESS} sucesso, {EXIT_FAILURE} erro, {EXIT_USAGE} uso ou caminho inválido, {EXIT_NOT_FOUND} não encontrado,
+  {EXIT_ALREADY_EXISTS} já existe, {EXIT_READ_ONLY} somente leitura, {EXIT_NOT_EMPTY} diretório não vazio,
+  {EXIT_WRONG_KIND} tipo de entrada errado, {EXIT_CORRUPT} imagem ou estado corrompido,
+  {EXIT_NO_SPACE} sem espaço no disco, {EXIT_BUSY} em uso ou entre imagens montadas",
+        image = DEFAULT_IMAGE,
+        commands = commands_usage(COMMANDS.iter().chain(CLI_COMMANDS)),
+    )
+}
+
+/// O que a CLI deve fazer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Help(Option<String>),
+    Shell,
+    Run {
+        script: Option<String>, // `None` ou `-`: entrada padrão
+        variables: Variables,
+    },
+    Execute(Vec<String>),
+}
+
+/// Linha de comando interpretada: opções globais e o comando
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cli {
+    pub image: String,
+    pub state_dir: Option<PathBuf>,
+    pub read_only: bool,
+    pub break_lock: bool,
+    pub format: OutputFormat,
+    pub verbosity: u8, // Quantidade de `-v`
+    pub command: Command,
+}
+
+impl Cli {
+    /// Interpreta os argumentos (sem o nome do programa)
+    ///
+    /// As opções globais vêm antes do comando; depois dele, tudo é argumento do
+    /// comando, o que permite gravar dados que começam com `-`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> io::Result<Self> {
+        let mut args = args.into_iter();
+        let mut image = None;
+        let mut state_dir = None;
+        let mut read_only = false;
+        let mut break_lock = false;
+        let mut format = OutputFormat::default();
+        let mut verbosity = 0u8;
+
+        let command = loop {
+            let Some(arg) = args.next() else {
+                break None;
+            };
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => {
+                    (option.to_string(), Some(value.to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline_value.clone().or_else(|| args.next()).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Missing value for {}", option),
+                    )
+                })
+            };
+            match option.as_str() {
+                "--image" => image = Some(value()?),
+                "--state-dir" => state_dir = Some(PathBuf::from(value()?)),
+                "--read-only" => read_only = true,
+                "--break-lock" => break_lock = true,
+                "--format" => format = OutputFormat::parse(&value()?)?,
+                "-v" | "--verbose" => verbosity = verbosity.saturating_add(1),
+                "-h" | "--help" => break Some("help".to_string()),
+                _ if arg.len() > 2
+                    && arg.starts_with('-')
+                    && arg[1..].chars().all(|c| c == 'v') =>
+                {
+                    verbosity = verbosity.saturating_add((arg.len() - 1) as u8)
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unknown option: {}", arg),
+                    ))
+                }
+                _ => break Some(arg),
+            }
+        };
+
+        let rest: Vec<String> = args.collect();
+        let command = match command.as_deref() {
+            None => Command::Help(None),
+            Some("help") => Command::Help(rest.first().cloned()),
+            Some(name) if matches!(rest.first().map(String::as_str), Some("-h" | "--help")) => {
+                Command::Help(Some(name.to_string()))
+            }
+            Some("shell") => {
+                // A imagem também pode vir como argumento, como antes das opções globais
+                if let Some(shell_image) = rest.first() {
+                    image = Some(shell_image.clone());
+                }
+                Command::Shell
+            }
+            Some("run") => {
+                let script = rest
+                    .first()
+                    .filter(|arg| parse_assignment(arg).is_none())
+                    .cloned();
+                let mut variables = Variables::new();
+                for arg in &rest[usize::from(script.is_some())..] {
+                    let (name, value) = parse_assignment(arg).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Expected NAME=value, got '{}'", arg),
+                        )
+                    })?;
+                    variables.insert(name.to_string(), value.to_string());
+                }
+                Command::Run { script, variables }
+            }
+            Some(_) => Command::Execute(command.into_iter().chain(rest).collect()),
+        };
+
+        Ok(Cli {
+            image: image.unwrap_or_else(|| DEFAULT_IMAGE.to_string()),
+            state_dir,
+            read_only,
+            break_lock,
+            format,
+            verbosity,
+            command,
+        })
+    }
+
+    /// Abre a imagem com o diretório de estado e o modo escolhidos
+    ///
+    /// Com `--break-lock`, o PID deixado na trava por um processo que não existe
+    /// mais é limpo antes; uma trava em uso faz a abertura falhar com `Busy`.
+    pub fn open(&self) -> io::Result<Vfs> {
+        if self.break_lock {
+            break_stale_lock(Path::new(&self.image))?;
+        }
+        let mode = if self.read_only {
+            OpenMode::ReadOnly
+        } else {
+            OpenMode::ReadWrite
+        };
+        match &self.state_dir {
+            Some(state_dir) => Vfs::open_with_mode(&self.image, state_dir.clone(), mode),
+            None if self.read_only => Vfs::open_read_only(&self.image),
+            None => Vfs::open(&self.image),
+        }
+    }
+}
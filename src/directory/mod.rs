@@ -1,179 +1,908 @@
-use std::{collections::HashMap, fs, io};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::PathBuf,
+};
 
 use chrono::Utc;
+use log::{debug, info, trace};
 use serde::{Deserialize, Serialize};
 
-use crate::{block::MetadataStore, file::FileMetadata};
+use crate::{
+    block::{BlockManager, MetadataStore, BLOCK_SIZE},
+    error::{DiscoError, Result},
+    file::FileMetadata,
+    migration::{load_hierarchy_data, MigrationReport},
+    path::{validate_name, VfsPath},
+    tar::read_archive_extent,
+};
+
+/// Identificador de um diretório dentro da `DirectoryTree`
+pub type DirectoryId = u64;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DirectoryMetadata {
     pub name: String,
     pub created_at: String,
     pub modified_at: String,
-    pub files: HashMap<String, FileMetadata>, // Arquivos no diretório
-    pub subdirectories: HashMap<String, DirectoryMetadata>, // Subdiretórios
-    pub parent: Option<Box<DirectoryMetadata>>, // Referência ao diretório pai
+    pub files: BTreeSet<String>, // Nomes dos arquivos; os metadados ficam no MetadataStore
+    pub subdirectories: HashMap<String, DirectoryId>, // Subdiretórios, por ID
+    pub parent: Option<DirectoryId>, // ID do diretório pai (None na raiz)
+    #[serde(default)]
+    pub read_only: bool, // Diretórios de um tar montado não aceitam escrita
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<MountSource>, // Imagem montada neste diretório
+}
+
+/// Imagem montada em um diretório, como é gravada na hierarquia
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MountSource {
+    pub image: PathBuf,
+    pub state_dir: PathBuf, // Onde ficam a hierarquia e o diretório atual da imagem
 }
 
 impl DirectoryMetadata {
-    pub fn new(name: &str, parent: Option<Box<DirectoryMetadata>>) -> Self {
+    pub fn new(name: &str, parent: Option<DirectoryId>) -> Self {
         Self {
             name: name.to_string(),
             created_at: Utc::now().to_rfc3339(),
             modified_at: Utc::now().to_rfc3339(),
-            files: HashMap::new(),
+            files: BTreeSet::new(),
             subdirectories: HashMap::new(),
             parent,
+            read_only: false,
+            mount: None,
         }
     }
 }
 
-pub fn save_directory_metadata(directory: &DirectoryMetadata, path: &str) -> io::Result<()> {
-    let json = serde_json::to_string_pretty(directory)?;
+/// Árvore de diretórios guardada em uma arena indexada por ID
+///
+/// Cada diretório aparece uma única vez e referencia pai e filhos pelo ID, então
+/// navegação e alterações sempre agem sobre a árvore viva e a serialização cresce
+/// linearmente com o número de diretórios.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectoryTree {
+    root: DirectoryId,
+    next_id: DirectoryId,
+    directories: BTreeMap<DirectoryId, DirectoryMetadata>,
+}
+
+impl Default for DirectoryTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DirectoryTree {
+    /// Cria uma árvore contendo apenas a raiz (`/`)
+    pub fn new() -> Self {
+        let mut directories = BTreeMap::new();
+        directories.insert(0, DirectoryMetadata::new("/", None));
+        DirectoryTree {
+            root: 0,
+            next_id: 1,
+            directories,
+        }
+    }
+
+    pub fn root(&self) -> DirectoryId {
+        self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.directories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directories.is_empty()
+    }
+
+    pub fn get(&self, id: DirectoryId) -> Option<&DirectoryMetadata> {
+        self.directories.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: DirectoryId) -> Option<&mut DirectoryMetadata> {
+        self.directories.get_mut(&id)
+    }
+
+    /// Como `get`, mas com erro para IDs inexistentes
+    pub fn directory(&self, id: DirectoryId) -> Result<&DirectoryMetadata> {
+        self.get(id)
+            .ok_or_else(|| DiscoError::NotFound("Directory not found".to_string()))
+    }
+
+    /// Como `get_mut`, mas com erro para IDs inexistentes
+    pub fn directory_mut(&mut self, id: DirectoryId) -> Result<&mut DirectoryMetadata> {
+        self.get_mut(id)
+            .ok_or_else(|| DiscoError::NotFound("Directory not found".to_string()))
+    }
+
+    /// Caminho absoluto de um diretório, seguindo os IDs dos pais
+    pub fn path_of(&self, id: DirectoryId) -> VfsPath {
+        let mut names = Vec::new();
+        let mut current = self.get(id);
+        while let Some(directory) = current {
+            if directory.parent.is_some() {
+                names.push(directory.name.as_str());
+            }
+            current = directory.parent.and_then(|parent| self.get(parent));
+        }
+
+        names
+            .into_iter()
+            .rev()
+            .fold(VfsPath::root(), |path, name| path.child_unchecked(name))
+    }
+
+    /// Monta a árvore a partir de uma arena já existente (usado na migração)
+    pub(crate) fn from_parts(
+        root: DirectoryId,
+        next_id: DirectoryId,
+        directories: BTreeMap<DirectoryId, DirectoryMetadata>,
+    ) -> Self {
+        DirectoryTree {
+            root,
+            next_id,
+            directories,
+        }
+    }
+
+    /// Localiza um diretório a partir de um caminho absoluto
+    pub fn lookup(&self, path: &VfsPath) -> Option<DirectoryId> {
+        path.components().try_fold(self.root, |id, part| {
+            self.get(id)?.subdirectories.get(part).copied()
+        })
+    }
+
+    /// Primeiro ponto de montagem ao longo de um caminho absoluto
+    ///
+    /// Retorna o diretório de montagem e o restante do caminho, relativo à raiz
+    /// da imagem montada (a própria raiz, se `path` for o ponto de montagem).
+    pub fn mount_point(&self, path: &VfsPath) -> Option<(DirectoryId, VfsPath)> {
+        let mut id = self.root;
+        let mut components = path.components();
+        while let Some(name) = components.next() {
+            id = *self.get(id)?.subdirectories.get(name)?;
+            if self.get(id)?.mount.is_some() {
+                let rest =
+                    components.fold(VfsPath::root(), |rest, name| rest.child_unchecked(name));
+                return Some((id, rest));
+            }
+        }
+        None
+    }
+
+    /// Diretórios com uma imagem montada, em ordem de ID
+    pub fn mount_points(&self) -> impl Iterator<Item = (DirectoryId, &MountSource)> {
+        self.directories
+            .iter()
+            .filter_map(|(&id, directory)| Some((id, directory.mount.as_ref()?)))
+    }
+
+    /// IDs de um diretório e de todos os seus descendentes (pré-ordem)
+    pub fn subtree(&self, id: DirectoryId) -> Vec<DirectoryId> {
+        let mut ids = Vec::new();
+        let mut pending = vec![id];
+        while let Some(current) = pending.pop() {
+            if let Some(directory) = self.get(current) {
+                ids.push(current);
+                pending.extend(directory.subdirectories.values().copied());
+            }
+        }
+        ids
+    }
+
+    /// Insere um diretório vazio sob `parent`, sem validações
+    pub(crate) fn add_directory(&mut self, parent: DirectoryId, name: &str) -> DirectoryId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.directories
+            .insert(id, DirectoryMetadata::new(name, Some(parent)));
+        if let Some(parent_directory) = self.get_mut(parent) {
+            parent_directory
+                .subdirectories
+                .insert(name.to_string(), id);
+        }
+        id
+    }
+
+    /// Desliga um diretório do pai e remove toda a sua subárvore da arena
+    pub(crate) fn remove_subtree(&mut self, id: DirectoryId) -> Vec<DirectoryMetadata> {
+        let ids = self.subtree(id);
+        if let Some(directory) = self.get(id) {
+            let name = directory.name.clone();
+            if let Some(parent) = directory.parent.and_then(|parent| self.get_mut(parent)) {
+                parent.subdirectories.remove(&name);
+            }
+        }
+        ids.iter()
+            .filter_map(|id| self.directories.remove(id))
+            .collect()
+    }
+}
+
+/// Erro retornado ao tentar alterar um diretório somente leitura
+pub fn read_only_error() -> DiscoError {
+    DiscoError::ReadOnly("Directory is read-only".to_string())
+}
+
+pub fn save_directory_metadata(tree: &DirectoryTree, path: &str) -> Result<()> {
+    let json = serde_json::to_string_pretty(tree)?;
     fs::write(path, json)?;
     Ok(())
 }
 
-pub fn load_hierarchy(path: &str) -> io::Result<(DirectoryMetadata, MetadataStore)> {
+/// Carrega a hierarquia; formatos antigos são migrados e os conflitos relatados
+pub fn load_hierarchy(path: &str) -> Result<(DirectoryTree, MetadataStore)> {
+    let (tree, metadata_store, _) = load_hierarchy_with_report(path)?;
+    Ok((tree, metadata_store))
+}
+
+/// Como `load_hierarchy`, devolvendo também o relatório quando houve migração
+pub fn load_hierarchy_with_report(
+    path: &str,
+) -> Result<(DirectoryTree, MetadataStore, Option<MigrationReport>)> {
     let data = fs::read_to_string(path)?;
-    let (root_directory, metadata_store): (DirectoryMetadata, MetadataStore) =
-        serde_json::from_str(&data)?;
-    Ok((root_directory, metadata_store))
+    let (tree, metadata_store, report) = load_hierarchy_data(&data)?;
+    if let Some(report) = &report {
+        report.log();
+    }
+    Ok((tree, metadata_store, report))
 }
 
 pub fn save_hierarchy(
-    root_directory: &DirectoryMetadata,
+    tree: &DirectoryTree,
     metadata_store: &MetadataStore,
     path: &str,
-) -> io::Result<()> {
-    let data = serde_json::to_string_pretty(&(root_directory, metadata_store))?;
+) -> Result<()> {
+    let data = serde_json::to_string_pretty(&(tree, metadata_store))?;
     fs::write(path, data)?;
     Ok(())
 }
 
-pub fn create_directory(name: &str, parent_directory: &mut DirectoryMetadata) -> io::Result<()> {
+pub fn create_directory(
+    name: &str,
+    tree: &mut DirectoryTree,
+    parent: DirectoryId,
+) -> Result<DirectoryId> {
+    validate_name(name)?;
+    let parent_directory = tree.directory(parent)?;
+    if parent_directory.read_only {
+        return Err(read_only_error());
+    }
     if parent_directory.subdirectories.contains_key(name) {
-        return Err(io::Error::new(
-            io::ErrorKind::AlreadyExists,
-            "Directory already exists",
+        return Err(DiscoError::AlreadyExists(
+            "Directory already exists".to_string(),
         ));
     }
 
-    let now = Utc::now().to_rfc3339();
-    let new_directory = DirectoryMetadata {
-        parent: Some(Box::new(parent_directory.clone())),
-        name: name.to_string(),
-        created_at: now.clone(),
-        modified_at: now,
-        files: HashMap::new(),
-        subdirectories: HashMap::new(),
-    };
-
-    parent_directory
-        .subdirectories
-        .insert(name.to_string(), new_directory);
+    let id = tree.add_directory(parent, name);
 
     // Atualizar o timestamp do diretório pai
-    update_directory_modified_time(parent_directory);
+    update_directory_modified_time(tree.directory_mut(parent)?);
 
-    Ok(())
+    Ok(id)
 }
 
-pub fn list_directory(directory: &DirectoryMetadata) {
-    println!("Conteúdo do diretório '{}':", directory.name);
+pub fn list_directory(tree: &DirectoryTree, directory: DirectoryId) {
+    let Some(directory_metadata) = tree.get(directory) else {
+        return;
+    };
+    println!("Conteúdo do diretório '{}':", tree.path_of(directory));
 
-    for file in directory.files.keys() {
+    for file in &directory_metadata.files {
         println!("Arquivo: {}", file);
     }
 
-    for subdir in directory.subdirectories.keys() {
+    for subdir in directory_metadata.subdirectories.keys() {
         println!("Subdiretório: {}", subdir);
     }
 }
 
-pub fn remove_directory(name: &str, parent_directory: &mut DirectoryMetadata) -> io::Result<()> {
-    if let Some(directory) = parent_directory.subdirectories.get(name) {
+pub fn remove_directory(name: &str, tree: &mut DirectoryTree, parent: DirectoryId) -> Result<()> {
+    validate_name(name)?;
+    let parent_directory = tree.directory(parent)?;
+    if parent_directory.read_only {
+        return Err(read_only_error());
+    }
+    if let Some(&id) = parent_directory.subdirectories.get(name) {
+        let directory = tree.directory(id)?;
         if !directory.files.is_empty() || !directory.subdirectories.is_empty() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Directory is not empty",
-            ));
+            return Err(DiscoError::NotEmpty("Directory is not empty".to_string()));
         }
 
-        parent_directory.subdirectories.remove(name);
-        println!("Diretório '{}' removido com sucesso.", name);
+        tree.remove_subtree(id);
+        update_directory_modified_time(tree.directory_mut(parent)?);
+        info!("Diretório '{}' removido com sucesso.", name);
         Ok(())
     } else {
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Directory not found",
-        ))
+        Err(DiscoError::NotFound("Directory not found".to_string()))
     }
 }
 
+/// Muda o diretório atual dentro de uma única árvore
+///
+/// Pontos de montagem não são atravessados aqui: a árvore não conhece as imagens
+/// montadas. `Vfs::cd` é quem segue os caminhos para dentro delas.
 pub fn change_directory(
-    current_directory: &mut DirectoryMetadata,
-    root_directory: &DirectoryMetadata,
+    tree: &DirectoryTree,
+    current_directory: &mut DirectoryId,
     path: &str,
-) -> io::Result<()> {
-    if path == "/" {
-        *current_directory = root_directory.clone();
-        return Ok(());
+) -> Result<()> {
+    let target_path = resolve_path(tree, *current_directory, path)?;
+
+    match tree.lookup(&target_path) {
+        Some(target) => {
+            *current_directory = target;
+            info!("Diretório atual: {}", target_path);
+            Ok(())
+        }
+        None => Err(DiscoError::NotFound(format!(
+            "Directory '{}' not found",
+            target_path
+        ))),
     }
+}
+
+/// Caminho absoluto de um diretório da árvore
+pub fn directory_path(tree: &DirectoryTree, directory: DirectoryId) -> VfsPath {
+    tree.path_of(directory)
+}
 
-    let mut target = if path.starts_with('/') {
-        root_directory.clone()
+/// Resolve um caminho (absoluto ou relativo ao diretório atual) de forma normalizada
+///
+/// A resolução é puramente textual; um caminho que entra em um ponto de montagem
+/// é separado depois, com `DirectoryTree::mount_point`.
+pub fn resolve_path(
+    tree: &DirectoryTree,
+    current_directory: DirectoryId,
+    path: &str,
+) -> Result<VfsPath> {
+    tree.path_of(current_directory).join(path)
+}
+
+/// Localiza um diretório da árvore a partir de um caminho absoluto
+pub fn find_directory(tree: &DirectoryTree, path: &VfsPath) -> Option<DirectoryId> {
+    tree.lookup(path)
+}
+
+/// Entrada localizada na árvore: diretório pai, nome e ID (se for diretório)
+struct LocatedEntry {
+    parent: DirectoryId,
+    name: String,
+    directory: Option<DirectoryId>,
+}
+
+/// Localiza uma entrada (arquivo ou diretório) pelo caminho absoluto
+fn locate_entry(tree: &DirectoryTree, path: &VfsPath) -> Result<LocatedEntry> {
+    let (Some(parent_path), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(DiscoError::InvalidPath(
+            "Path does not name an entry".to_string(),
+        ));
+    };
+    let parent = tree
+        .lookup(&parent_path)
+        .ok_or_else(|| DiscoError::NotFound("Source not found".to_string()))?;
+    let parent_directory = tree.directory(parent)?;
+    let directory = if let Some(&id) = parent_directory.subdirectories.get(name) {
+        Some(id)
+    } else if parent_directory.files.contains(name) {
+        None
     } else {
-        current_directory.clone()
+        return Err(DiscoError::NotFound("Source not found".to_string()));
     };
+    Ok(LocatedEntry {
+        parent,
+        name: name.to_string(),
+        directory,
+    })
+}
+
+/// Renomeia ou move um arquivo ou diretório (caminhos absolutos)
+///
+/// Se o destino for um diretório existente, a entrada é movida para dentro dele.
+/// Um arquivo de destino é substituído e seus blocos liberados; um diretório de
+/// destino só é substituído se estiver vazio. Todas as validações acontecem antes
+/// de qualquer alteração, então a árvore nunca fica em um estado intermediário.
+pub fn rename_path(
+    source: &VfsPath,
+    destination: &VfsPath,
+    tree: &mut DirectoryTree,
+    metadata_store: &mut MetadataStore,
+    block_manager: &BlockManager,
+) -> Result<()> {
+    let invalid = |message: &str| DiscoError::InvalidArgument(message.to_string());
+
+    let source_entry = locate_entry(tree, source).map_err(|e| match e {
+        DiscoError::InvalidPath(_) => invalid("Cannot move the root directory"),
+        e => e,
+    })?;
+    if tree.directory(source_entry.parent)?.read_only {
+        return Err(read_only_error());
+    }
+
+    // Mover para um diretório existente coloca a entrada dentro dele
+    let mut destination_path = destination.clone();
+    if destination_path != *source && tree.lookup(&destination_path).is_some() {
+        destination_path = destination_path.child_unchecked(&source_entry.name);
+    }
+
+    if destination_path == *source {
+        return Ok(());
+    }
+    if source_entry.directory.is_some() && destination_path.starts_with(source) {
+        return Err(invalid("Cannot move a directory into its own descendant"));
+    }
+
+    let (Some(destination_parent_path), Some(destination_name)) =
+        (destination_path.parent(), destination_path.file_name().map(str::to_string))
+    else {
+        return Err(invalid("Invalid destination"));
+    };
+    let destination_parent = tree
+        .lookup(&destination_parent_path)
+        .ok_or_else(|| DiscoError::NotFound("Destination directory not found".to_string()))?;
+    let destination_directory = tree.directory(destination_parent)?;
+    if destination_directory.read_only {
+        return Err(read_only_error());
+    }
+
+    let mut replaced_directory = None;
+    let mut replaced_file = None;
+    if let Some(&existing) = destination_directory.subdirectories.get(&destination_name) {
+        if source_entry.directory.is_none() {
+            return Err(DiscoError::IsADirectory(
+                "Cannot overwrite a directory with a file".to_string(),
+            ));
+        }
+        let existing_directory = tree.directory(existing)?;
+        if !existing_directory.files.is_empty() || !existing_directory.subdirectories.is_empty() {
+            return Err(DiscoError::NotEmpty(
+                "Destination directory is not empty".to_string(),
+            ));
+        }
+        replaced_directory = Some(existing);
+    } else if destination_directory.files.contains(&destination_name) {
+        if source_entry.directory.is_some() {
+            return Err(DiscoError::NotADirectory(
+                "Cannot overwrite a file with a directory".to_string(),
+            ));
+        }
+        replaced_file = metadata_store
+            .get_file_metadata(&destination_path.to_string())
+            .cloned();
+    }
+
+    // A partir daqui nenhuma validação pode falhar: aplica a mudança
+    if let Some(existing) = replaced_directory {
+        tree.remove_subtree(existing);
+    }
+    if let Some(replaced) = &replaced_file {
+        tree.directory_mut(destination_parent)?
+            .files
+            .remove(&destination_name);
+        metadata_store.remove_file_metadata(&replaced.path);
+    }
+
+    let source_parent = tree.directory_mut(source_entry.parent)?;
+    source_parent.subdirectories.remove(&source_entry.name);
+    source_parent.files.remove(&source_entry.name);
+    update_directory_modified_time(source_parent);
+
+    if let Some(id) = source_entry.directory {
+        let directory = tree.directory_mut(id)?;
+        directory.name = destination_name.clone();
+        directory.parent = Some(destination_parent);
+        tree.directory_mut(destination_parent)?
+            .subdirectories
+            .insert(destination_name, id);
+        metadata_store.rename_files_under(&source.to_string(), &destination_path.to_string());
+    } else {
+        if let Some(mut moved) = metadata_store.get_file_metadata(&source.to_string()).cloned() {
+            metadata_store.remove_file_metadata(&moved.path);
+            moved.path = destination_path.to_string();
+            metadata_store.add_file(&destination_path.to_string(), moved);
+        }
+        tree.directory_mut(destination_parent)?
+            .files
+            .insert(destination_name);
+    }
+    update_directory_modified_time(tree.directory_mut(destination_parent)?);
+
+    if let Some(replaced) = replaced_file {
+        for block_index in replaced.referenced_blocks() {
+            block_manager.free_block(block_index)?;
+        }
+    }
+
+    info!("'{}' movido para '{}'", source, destination_path);
+    Ok(())
+}
 
-    for part in path.split('/').filter(|s| !s.is_empty()) {
-        if part == ".." {
-            if let Some(parent) = &target.parent {
-                target = *parent.clone(); // Navegar para o diretório pai
-            } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "No parent directory",
-                ));
+/// Grava dados em blocos recém-alocados, liberando-os se algo falhar
+pub(crate) fn write_new_blocks(data: &[u8], block_manager: &BlockManager) -> Result<Vec<usize>> {
+    let mut blocks = Vec::new();
+    for chunk in data.chunks(BLOCK_SIZE) {
+        let result = block_manager
+            .allocate_block()
+            .and_then(|index| block_manager.write_block(index, chunk).map(|_| index));
+        match result {
+            Ok(index) => {
+                debug!("Bloco alocado: {} ({} bytes)", index, chunk.len());
+                trace!("Dados do bloco {}: {:?}", index, chunk);
+                blocks.push(index);
             }
-        } else if let Some(subdir) = target.subdirectories.get(part) {
-            target = subdir.clone();
+            Err(e) => {
+                release_blocks(&blocks, block_manager);
+                return Err(e);
+            }
+        }
+    }
+    Ok(blocks)
+}
+
+/// Devolve blocos obtidos por uma cópia que não chegou ao fim
+pub(crate) fn release_blocks(blocks: &[usize], block_manager: &BlockManager) {
+    for &block_index in blocks {
+        let _ = block_manager.free_block(block_index);
+    }
+}
+
+/// Acrescenta uma referência a cada bloco; se algum falhar, desfaz as anteriores
+pub(crate) fn share_blocks(blocks: &[usize], block_manager: &BlockManager) -> Result<()> {
+    for (shared, &block_index) in blocks.iter().enumerate() {
+        if let Err(e) = block_manager.share_block(block_index) {
+            release_blocks(&blocks[..shared], block_manager);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Obtém os blocos da cópia de um arquivo, duplicando ou compartilhando os dados
+fn copy_file_blocks(
+    metadata: &FileMetadata,
+    block_manager: &BlockManager,
+    reflink: bool,
+) -> Result<Vec<usize>> {
+    if let Some(extent) = &metadata.archive {
+        // Arquivos de um tar montado não têm blocos: os dados são importados
+        let data = read_archive_extent(extent, metadata.size)?;
+        return write_new_blocks(&data, block_manager);
+    }
+
+    let mut blocks = Vec::new();
+    for &block_index in &metadata.block_indices {
+        let result = if reflink {
+            block_manager.share_block(block_index).map(|_| block_index)
         } else {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("Directory '{}' not found", part),
+            block_manager
+                .read_block(block_index)
+                .and_then(|data| write_new_blocks(&data, block_manager))
+                .map(|new_blocks| new_blocks[0])
+        };
+        match result {
+            Ok(index) => blocks.push(index),
+            Err(e) => {
+                release_blocks(&blocks, block_manager);
+                return Err(e);
+            }
+        }
+    }
+    Ok(blocks)
+}
+
+/// Cria os metadados da cópia de um arquivo
+fn copy_file_metadata(
+    source: &VfsPath,
+    path: &str,
+    metadata_store: &MetadataStore,
+    block_manager: &BlockManager,
+    reflink: bool,
+) -> Result<FileMetadata> {
+    let source = metadata_store
+        .get_file_metadata(&source.to_string())
+        .ok_or_else(|| DiscoError::NotFound("File metadata not found".to_string()))?;
+    let now = Utc::now().to_rfc3339();
+    Ok(FileMetadata {
+        path: path.to_string(),
+        permissions: source.permissions.clone(),
+        created_at: now.clone(),
+        modified_at: now,
+        size: source.size,
+        block_indices: copy_file_blocks(source, block_manager, reflink)?,
+        archive: None,
+        versions: Vec::new(), // A cópia começa sem histórico
+        next_version: 0,
+    })
+}
+
+/// Subárvore copiada, ainda fora da arena
+struct CopiedDirectory {
+    name: String,
+    files: BTreeSet<String>,
+    subdirectories: Vec<CopiedDirectory>,
+}
+
+/// Copia recursivamente uma subárvore, acumulando os arquivos criados
+#[allow(clippy::too_many_arguments)]
+fn copy_subtree(
+    tree: &DirectoryTree,
+    source: DirectoryId,
+    name: &str,
+    source_path: &VfsPath,
+    path: &VfsPath,
+    metadata_store: &MetadataStore,
+    block_manager: &BlockManager,
+    reflink: bool,
+    created_files: &mut Vec<FileMetadata>,
+) -> Result<CopiedDirectory> {
+    let source = tree.directory(source)?;
+    let mut copy = CopiedDirectory {
+        name: name.to_string(),
+        files: BTreeSet::new(),
+        subdirectories: Vec::new(),
+    };
+
+    for file_name in &source.files {
+        let file_copy = copy_file_metadata(
+            &source_path.child_unchecked(file_name),
+            &path.child_unchecked(file_name).to_string(),
+            metadata_store,
+            block_manager,
+            reflink,
+        )?;
+        created_files.push(file_copy);
+        copy.files.insert(file_name.clone());
+    }
+
+    for (subdirectory_name, &subdirectory) in &source.subdirectories {
+        let subdirectory_copy = copy_subtree(
+            tree,
+            subdirectory,
+            subdirectory_name,
+            &source_path.child_unchecked(subdirectory_name),
+            &path.child_unchecked(subdirectory_name),
+            metadata_store,
+            block_manager,
+            reflink,
+            created_files,
+        )?;
+        copy.subdirectories.push(subdirectory_copy);
+    }
+
+    Ok(copy)
+}
+
+/// Insere na arena uma subárvore copiada
+fn insert_copied_directory(tree: &mut DirectoryTree, parent: DirectoryId, copy: CopiedDirectory) {
+    let id = tree.add_directory(parent, &copy.name);
+    if let Some(directory) = tree.get_mut(id) {
+        directory.files = copy.files;
+    }
+    for subdirectory in copy.subdirectories {
+        insert_copied_directory(tree, id, subdirectory);
+    }
+}
+
+/// Copia um arquivo ou, com `recursive`, uma subárvore (caminhos absolutos)
+///
+/// Sem `reflink` os dados são duplicados em blocos novos. Com `reflink` a cópia
+/// passa a referenciar os mesmos blocos da origem; como a escrita sempre grava
+/// em blocos novos, cada lado só ganha blocos próprios quando for modificado.
+pub fn copy_path(
+    source: &VfsPath,
+    destination: &VfsPath,
+    tree: &mut DirectoryTree,
+    metadata_store: &mut MetadataStore,
+    block_manager: &BlockManager,
+    recursive: bool,
+    reflink: bool,
+) -> Result<()> {
+    let invalid = |message: &str| DiscoError::InvalidArgument(message.to_string());
+
+    let source_entry = locate_entry(tree, source).map_err(|e| match e {
+        DiscoError::InvalidPath(_) => invalid("Cannot copy the root directory"),
+        e => e,
+    })?;
+    if source_entry.directory.is_some() && !recursive {
+        return Err(DiscoError::IsADirectory(
+            "Source is a directory (use -r)".to_string(),
+        ));
+    }
+
+    // Copiar para um diretório existente coloca a cópia dentro dele
+    let mut destination_path = destination.clone();
+    if tree.lookup(&destination_path).is_some() {
+        destination_path = destination_path.child_unchecked(&source_entry.name);
+    }
+    if destination_path == *source {
+        return Err(invalid("Source and destination are the same"));
+    }
+    if source_entry.directory.is_some() && destination_path.starts_with(source) {
+        return Err(invalid("Cannot copy a directory into itself"));
+    }
+
+    let (Some(destination_parent_path), Some(destination_name)) =
+        (destination_path.parent(), destination_path.file_name().map(str::to_string))
+    else {
+        return Err(invalid("Invalid destination"));
+    };
+    let destination_parent = tree
+        .lookup(&destination_parent_path)
+        .ok_or_else(|| DiscoError::NotFound("Destination directory not found".to_string()))?;
+    let destination_directory = tree.directory(destination_parent)?;
+    if destination_directory.read_only {
+        return Err(read_only_error());
+    }
+
+    let mut replaced_file = None;
+    if destination_directory
+        .subdirectories
+        .contains_key(&destination_name)
+    {
+        return Err(DiscoError::AlreadyExists(
+            "Destination directory already exists".to_string(),
+        ));
+    } else if destination_directory.files.contains(&destination_name) {
+        if source_entry.directory.is_some() {
+            return Err(DiscoError::NotADirectory(
+                "Cannot overwrite a file with a directory".to_string(),
             ));
         }
+        replaced_file = metadata_store
+            .get_file_metadata(&destination_path.to_string())
+            .cloned();
     }
 
-    *current_directory = target;
-    println!("Diretório atual: {}", current_directory.name);
+    let mut created_files = Vec::new();
+    let copied_directory = if let Some(source_directory) = source_entry.directory {
+        let result = copy_subtree(
+            tree,
+            source_directory,
+            &destination_name,
+            source,
+            &destination_path,
+            metadata_store,
+            block_manager,
+            reflink,
+            &mut created_files,
+        );
+        match result {
+            Ok(directory) => Some(directory),
+            Err(e) => {
+                for file in &created_files {
+                    release_blocks(&file.block_indices, block_manager);
+                }
+                return Err(e);
+            }
+        }
+    } else {
+        let file_copy = copy_file_metadata(
+            source,
+            &destination_path.to_string(),
+            metadata_store,
+            block_manager,
+            reflink,
+        )?;
+        created_files.push(file_copy);
+        None
+    };
+
+    // A cópia está pronta: registra tudo de uma vez
+    if let Some(replaced) = &replaced_file {
+        tree.directory_mut(destination_parent)?
+            .files
+            .remove(&destination_name);
+        metadata_store.remove_file_metadata(&replaced.path);
+    }
+    for file in &created_files {
+        metadata_store.add_file(&file.path, file.clone());
+    }
+    if let Some(directory) = copied_directory {
+        insert_copied_directory(tree, destination_parent, directory);
+    } else {
+        tree.directory_mut(destination_parent)?
+            .files
+            .insert(destination_name);
+    }
+    update_directory_modified_time(tree.directory_mut(destination_parent)?);
+
+    if let Some(replaced) = replaced_file {
+        for block_index in replaced.referenced_blocks() {
+            block_manager.free_block(block_index)?;
+        }
+    }
+
+    info!("'{}' copiado para '{}'", source, destination_path);
     Ok(())
 }
 
-#[allow(dead_code)]
-pub fn resolve_path(current_directory: &DirectoryMetadata, path: &str) -> String {
-    if path.starts_with('/') {
-        path.to_string() // Caminho absoluto
+/// Resumo do que foi removido por uma remoção recursiva
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RemovalReport {
+    pub files: usize,
+    pub directories: usize,
+    pub bytes: u64,          // Soma dos tamanhos dos arquivos removidos
+    pub blocks_freed: usize, // Blocos que voltaram a ficar livres
+}
+
+/// Remove um arquivo ou uma subárvore inteira, liberando todos os blocos
+pub fn remove_path_recursive(
+    path: &VfsPath,
+    tree: &mut DirectoryTree,
+    metadata_store: &mut MetadataStore,
+    block_manager: &BlockManager,
+) -> Result<RemovalReport> {
+    let entry = locate_entry(tree, path).map_err(|e| match e {
+        DiscoError::InvalidPath(_) => {
+            DiscoError::InvalidArgument("Cannot remove the root directory".to_string())
+        }
+        e => e,
+    })?;
+    if tree.directory(entry.parent)?.read_only {
+        return Err(read_only_error());
+    }
+
+    let mut report = RemovalReport::default();
+    let mut removed_files = Vec::new();
+    if let Some(directory) = entry.directory {
+        report.directories = tree.remove_subtree(directory).len();
+        removed_files = metadata_store.remove_files_under(&path.to_string());
     } else {
-        format!("{}/{}", current_directory.name.trim_end_matches('/'), path) // Caminho relativo
+        tree.directory_mut(entry.parent)?.files.remove(&entry.name);
+        let key = path.to_string();
+        if let Some(metadata) = metadata_store.get_file_metadata(&key).cloned() {
+            metadata_store.remove_file_metadata(&key);
+            removed_files.push(metadata);
+        }
+    }
+    update_directory_modified_time(tree.directory_mut(entry.parent)?);
+
+    for metadata in &removed_files {
+        report.files += 1;
+        report.bytes += metadata.size;
+        for block_index in metadata.referenced_blocks() {
+            let last_reference = block_manager.block_ref_count(block_index)? == 1;
+            block_manager.free_block(block_index)?;
+            if last_reference {
+                report.blocks_freed += 1;
+            }
+        }
     }
+
+    info!(
+        "'{}' removido: {} arquivo(s), {} diretório(s), {} bytes ({} blocos liberados)",
+        path, report.files, report.directories, report.bytes, report.blocks_freed
+    );
+    Ok(report)
 }
 
-#[allow(dead_code)]
 pub fn update_directory_modified_time(directory: &mut DirectoryMetadata) {
     directory.modified_at = Utc::now().to_rfc3339();
 }
 
-pub fn save_current_directory(current_directory: &DirectoryMetadata, path: &str) -> io::Result<()> {
-    let json = serde_json::to_string_pretty(current_directory)?;
+/// Salva o diretório atual como caminho absoluto dentro da árvore
+pub fn save_current_directory(
+    tree: &DirectoryTree,
+    current_directory: DirectoryId,
+    path: &str,
+) -> Result<()> {
+    let json = serde_json::to_string_pretty(&tree.path_of(current_directory))?;
     fs::write(path, json)?;
     Ok(())
 }
 
-pub fn load_current_directory(path: &str) -> io::Result<DirectoryMetadata> {
+/// Carrega o diretório atual e o localiza na árvore viva
+///
+/// Aceita também o ID numérico salvo por versões anteriores. Caminhos que não
+/// existem mais na árvore resultam em `NotFound`.
+pub fn load_current_directory(tree: &DirectoryTree, path: &str) -> Result<DirectoryId> {
     let json = fs::read_to_string(path)?;
-    let directory: DirectoryMetadata = serde_json::from_str(&json)?;
-    Ok(directory)
-}
\ No newline at end of file
+    let directory = match serde_json::from_str::<serde_json::Value>(&json)? {
+        serde_json::Value::Number(id) => id.as_u64().filter(|id| tree.get(*id).is_some()),
+        value => tree.lookup(&serde_json::from_value::<VfsPath>(value)?),
+    };
+    directory.ok_or_else(|| DiscoError::NotFound("Current directory no longer exists".to_string()))
+}
@@ -0,0 +1,131 @@
+use std::{error::Error, fmt, io};
+
+/// Erro da biblioteca, com um caso para cada tipo de falha
+///
+/// Os casos levam a mensagem em inglês exibida ao usuário. Na conversão para
+/// `io::Error` o `DiscoError` vai junto, como erro interno, e pode ser
+/// recuperado com `DiscoError::from_io`.
+#[derive(Debug)]
+pub enum DiscoError {
+    NotFound(String),
+    AlreadyExists(String),
+    NotEmpty(String),
+    NotADirectory(String),
+    IsADirectory(String),
+    NoSpace(String),          // Sem blocos livres ou sem referências disponíveis
+    PermissionDenied(String), // Operação não permitida
+    ReadOnly(String),         // Diretório ou arquivo somente leitura
+    Busy(String),             // Ponto de montagem ou imagem em uso
+    CrossDevice(String),      // Operação entre imagens montadas diferentes
+    Corrupt {
+        message: String,
+        source: Option<Box<dyn Error + Send + Sync>>,
+    },
+    InvalidPath(String),
+    InvalidArgument(String),
+    Io(io::Error), // Falha do sistema de arquivos real
+}
+
+/// Resultado das operações da biblioteca
+pub type Result<T> = std::result::Result<T, DiscoError>;
+
+impl DiscoError {
+    /// Dados ilegíveis, guardando o erro que os revelou
+    pub fn corrupt(message: &str, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        DiscoError::Corrupt {
+            message: message.to_string(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// Tipo de `io::Error` equivalente
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            DiscoError::NotFound(_) => io::ErrorKind::NotFound,
+            DiscoError::AlreadyExists(_) => io::ErrorKind::AlreadyExists,
+            DiscoError::NotEmpty(_) => io::ErrorKind::DirectoryNotEmpty,
+            DiscoError::NotADirectory(_) => io::ErrorKind::NotADirectory,
+            DiscoError::IsADirectory(_) => io::ErrorKind::IsADirectory,
+            DiscoError::NoSpace(_) => io::ErrorKind::StorageFull,
+            DiscoError::PermissionDenied(_) => io::ErrorKind::PermissionDenied,
+            DiscoError::ReadOnly(_) => io::ErrorKind::ReadOnlyFilesystem,
+            DiscoError::Busy(_) => io::ErrorKind::ResourceBusy,
+            DiscoError::CrossDevice(_) => io::ErrorKind::CrossesDevices,
+            DiscoError::Corrupt { .. } => io::ErrorKind::InvalidData,
+            DiscoError::InvalidPath(_) | DiscoError::InvalidArgument(_) => {
+                io::ErrorKind::InvalidInput
+            }
+            DiscoError::Io(error) => error.kind(),
+        }
+    }
+
+    /// O `DiscoError` guardado em um `io::Error` produzido pela biblioteca
+    pub fn from_io(error: &io::Error) -> Option<&DiscoError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for DiscoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoError::NotFound(message)
+            | DiscoError::AlreadyExists(message)
+            | DiscoError::NotEmpty(message)
+            | DiscoError::NotADirectory(message)
+            | DiscoError::IsADirectory(message)
+            | DiscoError::NoSpace(message)
+            | DiscoError::PermissionDenied(message)
+            | DiscoError::ReadOnly(message)
+            | DiscoError::Busy(message)
+            | DiscoError::CrossDevice(message)
+            | DiscoError::Corrupt { message, .. }
+            | DiscoError::InvalidPath(message)
+            | DiscoError::InvalidArgument(message) => f.write_str(message),
+            DiscoError::Io(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for DiscoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DiscoError::Corrupt {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            DiscoError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DiscoError {
+    fn from(error: io::Error) -> Self {
+        // Um `DiscoError` que passou por `io::Error` volta ao caso original
+        match error.downcast::<DiscoError>() {
+            Ok(error) => error,
+            Err(error) => DiscoError::Io(error),
+        }
+    }
+}
+
+impl From<DiscoError> for io::Error {
+    fn from(error: DiscoError) -> Self {
+        match error {
+            DiscoError::Io(error) => error,
+            error => io::Error::new(error.kind(), error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for DiscoError {
+    fn from(error: serde_json::Error) -> Self {
+        if error.is_io() {
+            return DiscoError::Io(error.into());
+        }
+        DiscoError::Corrupt {
+            message: format!("Invalid JSON data: {}", error),
+            source: Some(Box::new(error)),
+        }
+    }
+}
@@ -1,11 +1,10 @@
-use std::io;
-
 use chrono::Utc;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::{block::{create_file_metadata, BlockManager, MetadataStore, BLOCK_SIZE}, directory::{resolve_path, update_directory_modified_time, DirectoryMetadata}};
+use crate::{block::{create_file_metadata, BlockManager, MetadataStore}, cache::READ_AHEAD_BLOCKS, directory::{read_only_error, resolve_path, update_directory_modified_time, write_new_blocks, DirectoryId, DirectoryTree}, error::{DiscoError, Result}, path::{validate_name, VfsPath}, tar::{read_archive_extent, ArchiveExtent}, versioning::{replace_contents, FileVersion, RetentionPolicy}};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FileMetadata {
     pub path: String,
     pub permissions: String,
@@ -13,16 +12,44 @@ pub struct FileMetadata {
     pub modified_at: String,
     pub size: u64,
     pub block_indices: Vec<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ArchiveExtent>, // Conteúdo servido de um tar montado
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<FileVersion>, // Conteúdos anteriores, do mais antigo ao mais recente
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub next_version: u32, // Número da próxima versão; nunca diminui, mesmo com as antigas expiradas
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+impl FileMetadata {
+    /// Blocos do conteúdo atual e das versões anteriores, uma vez por referência
+    pub fn referenced_blocks(&self) -> impl Iterator<Item = usize> + '_ {
+        self.block_indices
+            .iter()
+            .chain(self.versions.iter().flat_map(|version| &version.block_indices))
+            .copied()
+    }
+
+    pub fn version(&self, number: u32) -> Result<&FileVersion> {
+        self.versions
+            .iter()
+            .find(|version| version.number == number)
+            .ok_or_else(|| DiscoError::NotFound(format!("Version {} not found", number)))
+    }
 }
 
 #[allow(dead_code)]
 pub fn create_file(
     path: &str,
     metadata_store: &mut MetadataStore,
-    current_directory: &DirectoryMetadata,
+    tree: &DirectoryTree,
+    current_directory: DirectoryId,
     permissions: &str,
-) -> io::Result<()> {
-    let resolved_path = resolve_path(current_directory, path);
+) -> Result<()> {
+    let resolved_path = resolve_path(tree, current_directory, path)?.to_string();
     let metadata = FileMetadata {
         path: resolved_path.clone(),
         permissions: permissions.to_string(),
@@ -30,70 +57,98 @@ pub fn create_file(
         modified_at: Utc::now().to_rfc3339(),
         size: 0,
         block_indices: vec![],
+        archive: None,
+        versions: Vec::new(),
+        next_version: 0,
     };
 
     metadata_store.add_file(&resolved_path, metadata);
 
-    println!("Arquivo '{}' criado.", resolved_path);
+    info!("Arquivo '{}' criado.", resolved_path);
     Ok(())
 }
 
 pub fn create_file_in_directory(
     file_name: &str,
-    directory: &mut DirectoryMetadata,
+    tree: &mut DirectoryTree,
+    directory_id: DirectoryId,
     metadata_store: &mut MetadataStore,
     permissions: &str,
-) -> io::Result<()> {
+) -> Result<()> {
+    validate_name(file_name)?;
+    let file_path = tree.path_of(directory_id).child(file_name)?;
+    let directory = tree.directory_mut(directory_id)?;
+    if directory.read_only {
+        return Err(read_only_error());
+    }
+
     // Verificar se o arquivo já existe no diretório atual
-    if directory.files.contains_key(file_name) {
-        return Err(io::Error::new(
-            io::ErrorKind::AlreadyExists,
-            "File already exists in this directory",
+    if directory.files.contains(file_name) {
+        return Err(DiscoError::AlreadyExists(
+            "File already exists in this directory".to_string(),
         ));
     }
 
     // Criar metadados do arquivo
-    let metadata = create_file_metadata(file_name, &directory.name, permissions, 0);
-
-    // Inserir o arquivo nos metadados do diretório
-    directory
-        .files
-        .insert(file_name.to_string(), metadata.clone());
+    let metadata = create_file_metadata(&file_path, permissions, 0);
 
-    // Atualizar o armazenamento global de metadados
+    // O diretório guarda só a referência; os metadados ficam no MetadataStore
+    directory.files.insert(file_name.to_string());
     metadata_store.add_file(&metadata.path, metadata.clone());
-    println!("Arquivo registrado no MetadataStore: {}", metadata.path); // Debug
+    debug!("Arquivo registrado no MetadataStore: {}", metadata.path);
 
     // Atualizar o tempo do diretório modificado
     update_directory_modified_time(directory);
 
-    println!(
+    info!(
         "Arquivo '{}' criado no diretório '{}'",
-        file_name, directory.name
+        file_name,
+        file_path.parent().unwrap_or_default()
     );
     Ok(())
 }
 
+/// Remove um arquivo do diretório, liberando os blocos dele e os das versões anteriores
 pub fn remove_file_from_directory(
     file_name: &str,
-    directory: &mut DirectoryMetadata,
+    tree: &mut DirectoryTree,
+    directory_id: DirectoryId,
     metadata_store: &mut MetadataStore,
-) -> io::Result<()> {
-    if directory.files.remove(file_name).is_none() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "File not found in this directory",
+    block_manager: &BlockManager,
+) -> Result<()> {
+    validate_name(file_name)?;
+    let file_path = tree.path_of(directory_id).child(file_name)?;
+    let key = file_path.to_string();
+    let directory = tree.directory_mut(directory_id)?;
+    if directory.read_only {
+        return Err(read_only_error());
+    }
+    if !directory.files.contains(file_name) {
+        return Err(DiscoError::NotFound(
+            "File not found in this directory".to_string(),
         ));
     }
+    let metadata = metadata_store.get_file_metadata(&key).cloned();
+    if metadata.as_ref().is_some_and(|metadata| metadata.archive.is_some()) {
+        return Err(read_only_error());
+    }
 
-    metadata_store.remove_file_metadata(file_name);
+    directory.files.remove(file_name);
+    metadata_store.remove_file_metadata(&key);
 
     // Atualizar o timestamp do diretório
     update_directory_modified_time(directory);
 
-    println!(
+    if let Some(metadata) = metadata {
+        for block_index in metadata.referenced_blocks() {
+            block_manager.free_block(block_index)?;
+        }
+    }
+
+    info!(
         "Arquivo '{}' removido do diretório '{}'",
-        file_name, directory.name
+        file_name,
+        file_path.parent().unwrap_or_default()
     );
     Ok(())
 }
@@ -101,32 +156,47 @@ pub fn remove_file_from_directory(
 pub fn read_file(
     path: &str,
     metadata_store: &MetadataStore,
-    block_manager: &mut BlockManager,
-) -> io::Result<String> {
+    block_manager: &BlockManager,
+) -> Result<String> {
+    let path = VfsPath::parse(path)?.to_string();
     let metadata = metadata_store
-        .get_file_metadata(path)
-        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))?;
+        .get_file_metadata(&path)
+        .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?;
 
-    println!(
+    debug!(
         "Blocos alocados para o arquivo '{}': {:?}",
         path, metadata.block_indices
-    ); // Depuração
+    );
+    read_contents(metadata, block_manager)
+}
 
+/// Conteúdo de um arquivo a partir dos seus metadados
+pub(crate) fn read_contents(
+    metadata: &FileMetadata,
+    block_manager: &BlockManager,
+) -> Result<String> {
     let mut content = Vec::new();
 
-    for &block_index in &metadata.block_indices {
+    if let Some(extent) = &metadata.archive {
+        // Arquivos de um tar montado são lidos direto do arquivo de origem
+        content = read_archive_extent(extent, metadata.size)?;
+    }
+
+    let blocks = &metadata.block_indices;
+    for (position, &block_index) in blocks.iter().enumerate() {
+        // A cada janela, os próximos blocos são carregados juntos no cache
+        if position % READ_AHEAD_BLOCKS == 0 && position + 1 < blocks.len() {
+            let end = (position + READ_AHEAD_BLOCKS).min(blocks.len());
+            block_manager.read_ahead(&blocks[position + 1..end]);
+        }
         let block_data = block_manager.read_block(block_index)?;
         content.extend(block_data);
     }
 
     content.truncate(metadata.size as usize);
 
-    let content_str = String::from_utf8(content).map_err(|_| {
-        io::Error::new(
-            io::ErrorKind::InvalidData,
-            "File contains invalid UTF-8 data",
-        )
-    })?;
+    let content_str = String::from_utf8(content)
+        .map_err(|e| DiscoError::corrupt("File contains invalid UTF-8 data", e))?;
 
     Ok(content_str)
 }
@@ -135,34 +205,49 @@ pub fn write_to_file(
     path: &str,
     data: &str,
     metadata_store: &mut MetadataStore,
-    block_manager: &mut BlockManager,
-    current_directory: &DirectoryMetadata,
-) -> io::Result<()> {
-    let resolved_path = resolve_path(current_directory, path);
+    block_manager: &BlockManager,
+    tree: &DirectoryTree,
+    current_directory: DirectoryId,
+) -> Result<()> {
+    let resolved_path = resolve_path(tree, current_directory, path)?;
+    write_file_contents(&resolved_path, data, metadata_store, block_manager, None)
+}
+
+/// Substitui o conteúdo do arquivo em `path`, já resolvido
+///
+/// Com uma política de versões, o conteúdo anterior fica guardado como versão
+/// em vez de ter os blocos liberados.
+pub fn write_file_contents(
+    path: &VfsPath,
+    data: &str,
+    metadata_store: &mut MetadataStore,
+    block_manager: &BlockManager,
+    policy: Option<&RetentionPolicy>,
+) -> Result<()> {
+    let resolved_path = path.to_string();
     let metadata = metadata_store
         .get_file_metadata(&resolved_path)
-        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found"))?;
+        .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?;
 
-    let mut updated_metadata = metadata.clone();
-    let mut remaining_data = data.as_bytes();
-    while !remaining_data.is_empty() {
-        let block_index = block_manager.allocate_block()?;
-        let chunk = if remaining_data.len() > BLOCK_SIZE {
-            &remaining_data[..BLOCK_SIZE]
-        } else {
-            remaining_data
-        };
-        block_manager.write_block(block_index, chunk)?;
-        println!("Bloco alocado: {}, Dados: {:?}", block_index, chunk); // Debug
-        updated_metadata.block_indices.push(block_index); // Atualiza blocos alocados
-        remaining_data = &remaining_data[chunk.len()..];
+    if metadata.archive.is_some() {
+        return Err(read_only_error());
     }
 
-    updated_metadata.size = data.len() as u64; // Atualiza o tamanho do arquivo
-    updated_metadata.modified_at = Utc::now().to_rfc3339();
-    metadata_store.update_file_metadata(path, updated_metadata);
+    // Os dados novos vão sempre para blocos novos; os antigos podem estar
+    // compartilhados com uma cópia e só são liberados depois da escrita. Se faltar
+    // espaço no meio, os blocos já alocados são devolvidos.
+    let mut updated_metadata = metadata.clone();
+    let blocks = write_new_blocks(data.as_bytes(), block_manager)?;
+
+    let released_blocks =
+        replace_contents(&mut updated_metadata, blocks, data.len() as u64, policy);
+    metadata_store.update_file_metadata(&resolved_path, updated_metadata);
 
-    println!("Dados escritos no arquivo '{}'", path);
+    for block_index in released_blocks {
+        block_manager.free_block(block_index)?;
+    }
+
+    info!("Dados escritos no arquivo '{}'", resolved_path);
     Ok(())
 }
 
@@ -170,19 +255,24 @@ pub fn write_to_file(
 pub fn remove_file(
     path: &str,
     metadata_store: &mut MetadataStore,
-    block_manager: &mut BlockManager,
-) -> io::Result<()> {
-    if let Some(metadata) = metadata_store.get_file_metadata(path) {
-        // Liberar blocos alocados
-        for &block_index in &metadata.block_indices {
+    block_manager: &BlockManager,
+) -> Result<()> {
+    let path = VfsPath::parse(path)?.to_string();
+    if let Some(metadata) = metadata_store.get_file_metadata(&path) {
+        if metadata.archive.is_some() {
+            return Err(read_only_error());
+        }
+
+        // Liberar blocos alocados, inclusive os das versões anteriores
+        for block_index in metadata.referenced_blocks() {
             block_manager.free_block(block_index)?;
         }
 
         // Remover metadados associados
-        metadata_store.remove_file_metadata(path);
-        println!("Arquivo virtual '{}' removido com sucesso.", path);
+        metadata_store.remove_file_metadata(&path);
+        info!("Arquivo virtual '{}' removido com sucesso.", path);
     } else {
-        println!("O arquivo virtual '{}' não existe.", path);
+        warn!("O arquivo virtual '{}' não existe.", path);
     }
 
     Ok(())
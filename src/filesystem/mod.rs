@@ -0,0 +1,537 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::{DiscoError, Result},
+    path::VfsPath,
+    vfs::{DirectoryEntry, EntryKind, Stat, Vfs},
+};
+
+/// Operações básicas de um sistema de arquivos virtual
+///
+/// Caminhos relativos partem do diretório atual da implementação (a raiz, se ela
+/// não tiver um). As implementações devem falhar com o mesmo tipo de erro
+/// (`DiscoError::kind`) nas mesmas situações, o que permite trocar uma pela outra
+/// e comparar os resultados.
+pub trait FileSystem {
+    /// Cria um arquivo vazio
+    fn create(&mut self, path: &str, permissions: &str) -> Result<()>;
+    fn read(&self, path: &str) -> Result<String>;
+    /// Substitui o conteúdo de um arquivo existente
+    fn write(&mut self, path: &str, data: &str) -> Result<()>;
+    /// Remove um arquivo ou, com `recursive`, um diretório e todo o seu conteúdo
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()>;
+    fn mkdir(&mut self, path: &str) -> Result<()>;
+    /// Lista um diretório: subdiretórios primeiro, cada grupo em ordem alfabética
+    fn readdir(&self, path: &str) -> Result<Vec<DirectoryEntry>>;
+    fn stat(&self, path: &str) -> Result<Stat>;
+    /// Renomeia ou move; um diretório existente no destino recebe a entrada
+    fn rename(&mut self, source: &str, destination: &str) -> Result<()>;
+}
+
+/// Sistema de arquivos guardado em blocos na imagem
+impl FileSystem for Vfs {
+    fn create(&mut self, path: &str, permissions: &str) -> Result<()> {
+        Ok(Vfs::create(self, path, permissions)?)
+    }
+
+    fn read(&self, path: &str) -> Result<String> {
+        Ok(Vfs::read(self, path)?)
+    }
+
+    fn write(&mut self, path: &str, data: &str) -> Result<()> {
+        Ok(Vfs::write(self, path, data)?)
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()> {
+        Vfs::rm(self, path, recursive)?;
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<()> {
+        Ok(Vfs::mkdir(self, path)?)
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<DirectoryEntry>> {
+        Ok(Vfs::ls(self, path)?)
+    }
+
+    fn stat(&self, path: &str) -> Result<Stat> {
+        Ok(Vfs::stat(self, path)?)
+    }
+
+    fn rename(&mut self, source: &str, destination: &str) -> Result<()> {
+        Ok(Vfs::rename(self, source, destination)?)
+    }
+}
+
+/// Ordena uma listagem como `FileSystem::readdir`: diretórios primeiro, depois por nome
+pub(crate) fn sort_entries(entries: &mut [DirectoryEntry]) {
+    entries.sort_by(|a, b| {
+        let rank = |kind: EntryKind| kind != EntryKind::Directory;
+        (rank(a.kind), &a.name).cmp(&(rank(b.kind), &b.name))
+    });
+}
+
+/// Entrada do sistema de arquivos em memória
+#[derive(Debug, Clone)]
+enum Node {
+    File {
+        data: String,
+        permissions: String,
+        created_at: String,
+        modified_at: String,
+    },
+    Directory {
+        created_at: String,
+        modified_at: String,
+    },
+}
+
+impl Node {
+    fn directory() -> Self {
+        let now = Utc::now().to_rfc3339();
+        Node::Directory {
+            created_at: now.clone(),
+            modified_at: now,
+        }
+    }
+
+    fn touch(&mut self) {
+        let now = Utc::now().to_rfc3339();
+        match self {
+            Node::File { modified_at, .. } | Node::Directory { modified_at, .. } => {
+                *modified_at = now
+            }
+        }
+    }
+}
+
+/// Sistema de arquivos inteiramente em memória, sem imagem nem estado em disco
+///
+/// Serve de referência para testes e para código que não precisa persistir nada.
+/// As entradas ficam em um mapa ordenado pelo caminho.
+#[derive(Debug, Clone)]
+pub struct MemoryFileSystem {
+    nodes: BTreeMap<VfsPath, Node>,
+}
+
+impl Default for MemoryFileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFileSystem {
+    /// Sistema de arquivos contendo apenas a raiz
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(VfsPath::root(), Node::directory());
+        MemoryFileSystem { nodes }
+    }
+
+    fn is_directory(&self, path: &VfsPath) -> bool {
+        matches!(self.nodes.get(path), Some(Node::Directory { .. }))
+    }
+
+    fn is_file(&self, path: &VfsPath) -> bool {
+        matches!(self.nodes.get(path), Some(Node::File { .. }))
+    }
+
+    /// Caminhos dos filhos diretos de um diretório
+    fn children<'a>(&'a self, path: &'a VfsPath) -> impl Iterator<Item = &'a VfsPath> {
+        self.nodes
+            .range(path.clone()..)
+            .map(|(child, _)| child)
+            .take_while(move |child| child.starts_with(path))
+            .filter(move |child| child.parent().as_ref() == Some(path))
+    }
+
+    /// Caminhos de uma entrada e de todos os seus descendentes
+    fn subtree(&self, path: &VfsPath) -> Vec<VfsPath> {
+        self.nodes
+            .range(path.clone()..)
+            .map(|(child, _)| child)
+            .take_while(|child| child.starts_with(path))
+            .cloned()
+            .collect()
+    }
+
+    /// Diretório pai de uma nova entrada, que precisa existir
+    fn parent_of(&self, path: &VfsPath) -> Result<VfsPath> {
+        let parent = path.parent().ok_or_else(|| {
+            DiscoError::InvalidArgument("Path does not name an entry".to_string())
+        })?;
+        if !self.is_directory(&parent) {
+            return Err(DiscoError::NotFound("Directory not found".to_string()));
+        }
+        Ok(parent)
+    }
+
+    fn touch(&mut self, path: &VfsPath) {
+        if let Some(node) = self.nodes.get_mut(path) {
+            node.touch();
+        }
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn create(&mut self, path: &str, permissions: &str) -> Result<()> {
+        let path = VfsPath::parse(path)?;
+        let parent = self.parent_of(&path)?;
+        if self.is_directory(&path) {
+            return Err(DiscoError::AlreadyExists(
+                "A directory with this name already exists".to_string(),
+            ));
+        }
+        if self.is_file(&path) {
+            return Err(DiscoError::AlreadyExists(
+                "File already exists in this directory".to_string(),
+            ));
+        }
+
+        let now = Utc::now().to_rfc3339();
+        self.nodes.insert(
+            path,
+            Node::File {
+                data: String::new(),
+                permissions: permissions.to_string(),
+                created_at: now.clone(),
+                modified_at: now,
+            },
+        );
+        self.touch(&parent);
+        Ok(())
+    }
+
+    fn read(&self, path: &str) -> Result<String> {
+        match self.nodes.get(&VfsPath::parse(path)?) {
+            Some(Node::File { data, .. }) => Ok(data.clone()),
+            _ => Err(DiscoError::NotFound("File not found".to_string())),
+        }
+    }
+
+    fn write(&mut self, path: &str, contents: &str) -> Result<()> {
+        match self.nodes.get_mut(&VfsPath::parse(path)?) {
+            Some(node @ Node::File { .. }) => {
+                if let Node::File { data, .. } = node {
+                    *data = contents.to_string();
+                }
+                node.touch();
+                Ok(())
+            }
+            _ => Err(DiscoError::NotFound("File not found".to_string())),
+        }
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()> {
+        let path = VfsPath::parse(path)?;
+        if self.is_directory(&path) && !recursive {
+            return Err(DiscoError::IsADirectory(
+                "Is a directory (use -r)".to_string(),
+            ));
+        }
+        let Some(parent) = path.parent() else {
+            return Err(DiscoError::InvalidArgument(
+                "Cannot remove the root directory".to_string(),
+            ));
+        };
+        if !self.nodes.contains_key(&path) {
+            return Err(DiscoError::NotFound("Source not found".to_string()));
+        }
+
+        for removed in self.subtree(&path) {
+            self.nodes.remove(&removed);
+        }
+        self.touch(&parent);
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<()> {
+        let path = VfsPath::parse(path)?;
+        let parent = self.parent_of(&path)?;
+        if self.is_file(&path) {
+            return Err(DiscoError::AlreadyExists(
+                "A file with this name already exists".to_string(),
+            ));
+        }
+        if self.is_directory(&path) {
+            return Err(DiscoError::AlreadyExists(
+                "Directory already exists".to_string(),
+            ));
+        }
+
+        self.nodes.insert(path, Node::directory());
+        self.touch(&parent);
+        Ok(())
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<DirectoryEntry>> {
+        let path = VfsPath::parse(path)?;
+        if !self.is_directory(&path) {
+            return Err(DiscoError::NotFound(format!(
+                "Directory '{}' not found",
+                path
+            )));
+        }
+
+        let mut entries: Vec<DirectoryEntry> = self
+            .children(&path)
+            .map(|child| DirectoryEntry {
+                name: child.file_name().unwrap_or_default().to_string(),
+                kind: if self.is_directory(child) {
+                    EntryKind::Directory
+                } else {
+                    EntryKind::File
+                },
+            })
+            .collect();
+        sort_entries(&mut entries);
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> Result<Stat> {
+        let path = VfsPath::parse(path)?;
+        match self.nodes.get(&path) {
+            Some(Node::Directory {
+                created_at,
+                modified_at,
+            }) => Ok(Stat {
+                kind: EntryKind::Directory,
+                size: self.children(&path).count() as u64,
+                permissions: "rwxr-xr-x".to_string(),
+                created_at: created_at.clone(),
+                modified_at: modified_at.clone(),
+                block_indices: vec![],
+                read_only: false,
+                path,
+            }),
+            Some(Node::File {
+                data,
+                permissions,
+                created_at,
+                modified_at,
+            }) => Ok(Stat {
+                kind: EntryKind::File,
+                size: data.len() as u64,
+                permissions: permissions.clone(),
+                created_at: created_at.clone(),
+                modified_at: modified_at.clone(),
+                block_indices: vec![],
+                read_only: false,
+                path,
+            }),
+            None => Err(DiscoError::NotFound("File not found".to_string())),
+        }
+    }
+
+    fn rename(&mut self, source: &str, destination: &str) -> Result<()> {
+        let invalid = |message: &str| DiscoError::InvalidArgument(message.to_string());
+        let source = VfsPath::parse(source)?;
+        let destination = VfsPath::parse(destination)?;
+
+        let Some(source_parent) = source.parent() else {
+            return Err(invalid("Cannot move the root directory"));
+        };
+        if !self.is_directory(&source_parent) || !self.nodes.contains_key(&source) {
+            return Err(DiscoError::NotFound("Source not found".to_string()));
+        }
+        let moving_directory = self.is_directory(&source);
+
+        // Mover para um diretório existente coloca a entrada dentro dele
+        let mut destination = destination;
+        if destination != source && self.is_directory(&destination) {
+            destination = destination.child_unchecked(source.file_name().unwrap_or_default());
+        }
+        if destination == source {
+            return Ok(());
+        }
+        if moving_directory && destination.starts_with(&source) {
+            return Err(invalid("Cannot move a directory into its own descendant"));
+        }
+        let destination_parent = destination
+            .parent()
+            .ok_or_else(|| invalid("Invalid destination"))?;
+        if !self.is_directory(&destination_parent) {
+            return Err(DiscoError::NotFound(
+                "Destination directory not found".to_string(),
+            ));
+        }
+
+        if self.is_directory(&destination) {
+            if !moving_directory {
+                return Err(DiscoError::IsADirectory(
+                    "Cannot overwrite a directory with a file".to_string(),
+                ));
+            }
+            if self.children(&destination).next().is_some() {
+                return Err(DiscoError::NotEmpty(
+                    "Destination directory is not empty".to_string(),
+                ));
+            }
+        } else if self.is_file(&destination) && moving_directory {
+            return Err(DiscoError::NotADirectory(
+                "Cannot overwrite a file with a directory".to_string(),
+            ));
+        }
+
+        self.nodes.remove(&destination);
+        for old_path in self.subtree(&source) {
+            let rest = old_path.strip_prefix(&source).unwrap_or_default();
+            let new_path = rest
+                .iter()
+                .fold(destination.clone(), |path, name| path.child_unchecked(name));
+            if let Some(node) = self.nodes.remove(&old_path) {
+                self.nodes.insert(new_path, node);
+            }
+        }
+        self.touch(&source_parent);
+        self.touch(&destination_parent);
+        Ok(())
+    }
+}
+
+/// Diretório do sistema de arquivos real, exposto somente para leitura
+///
+/// Útil como camada inferior de um overlay. Toda operação que alteraria o
+/// diretório falha com `DiscoError::ReadOnly`. Links simbólicos dentro dele são
+/// ignorados, para que nada fora de `root` fique visível.
+#[derive(Debug, Clone)]
+pub struct HostDirectory {
+    root: PathBuf,
+}
+
+impl HostDirectory {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        HostDirectory { root: root.into() }
+    }
+
+    /// Caminho real correspondente a um caminho virtual
+    fn host_path(&self, path: &VfsPath) -> PathBuf {
+        path.components()
+            .fold(self.root.clone(), |host, name| host.join(name))
+    }
+
+    /// Metadados da entrada, sem seguir links simbólicos
+    ///
+    /// Um link em qualquer ponto do caminho poderia levar para fora de `root`,
+    /// então a entrada é tratada como inexistente.
+    fn metadata(&self, path: &VfsPath) -> Option<fs::Metadata> {
+        let mut host = self.root.clone();
+        let mut metadata = fs::metadata(&host).ok()?;
+        for name in path.components() {
+            host.push(name);
+            metadata = fs::symlink_metadata(&host).ok()?;
+            if metadata.file_type().is_symlink() {
+                return None;
+            }
+        }
+        Some(metadata)
+    }
+}
+
+fn host_read_only_error() -> DiscoError {
+    DiscoError::ReadOnly("Host directory is read-only".to_string())
+}
+
+impl FileSystem for HostDirectory {
+    fn create(&mut self, _path: &str, _permissions: &str) -> Result<()> {
+        Err(host_read_only_error())
+    }
+
+    fn read(&self, path: &str) -> Result<String> {
+        let path = VfsPath::parse(path)?;
+        if !self
+            .metadata(&path)
+            .is_some_and(|metadata| metadata.is_file())
+        {
+            return Err(DiscoError::NotFound("File not found".to_string()));
+        }
+        let content = fs::read(self.host_path(&path))?;
+        String::from_utf8(content)
+            .map_err(|e| DiscoError::corrupt("File contains invalid UTF-8 data", e))
+    }
+
+    fn write(&mut self, _path: &str, _data: &str) -> Result<()> {
+        Err(host_read_only_error())
+    }
+
+    fn remove(&mut self, _path: &str, _recursive: bool) -> Result<()> {
+        Err(host_read_only_error())
+    }
+
+    fn mkdir(&mut self, _path: &str) -> Result<()> {
+        Err(host_read_only_error())
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<DirectoryEntry>> {
+        let path = VfsPath::parse(path)?;
+        if !self
+            .metadata(&path)
+            .is_some_and(|metadata| metadata.is_dir())
+        {
+            return Err(DiscoError::NotFound(format!(
+                "Directory '{}' not found",
+                path
+            )));
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.host_path(&path))? {
+            let entry = entry?;
+            // Nomes que não são UTF-8 não têm caminho virtual e ficam de fora
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            // Links simbólicos também, já que podem apontar para fora de `root`
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                continue;
+            }
+            let kind = if file_type.is_dir() {
+                EntryKind::Directory
+            } else {
+                EntryKind::File
+            };
+            entries.push(DirectoryEntry { name, kind });
+        }
+        sort_entries(&mut entries);
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> Result<Stat> {
+        let path = VfsPath::parse(path)?;
+        let metadata = self
+            .metadata(&path)
+            .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?;
+        let modified_at = DateTime::<Utc>::from(metadata.modified()?).to_rfc3339();
+        let created_at = metadata
+            .created()
+            .map(|time| DateTime::<Utc>::from(time).to_rfc3339())
+            .unwrap_or_else(|_| modified_at.clone());
+
+        let (kind, size, permissions) = if metadata.is_dir() {
+            let entries = self.readdir(&path.to_string())?.len() as u64;
+            (EntryKind::Directory, entries, "rwxr-xr-x")
+        } else if metadata.permissions().readonly() {
+            (EntryKind::File, metadata.len(), "r--r--r--")
+        } else {
+            (EntryKind::File, metadata.len(), "rw-r--r--")
+        };
+        Ok(Stat {
+            path,
+            kind,
+            size,
+            permissions: permissions.to_string(),
+            created_at,
+            modified_at,
+            block_indices: vec![],
+            read_only: true,
+        })
+    }
+
+    fn rename(&mut self, _source: &str, _destination: &str) -> Result<()> {
+        Err(host_read_only_error())
+    }
+}
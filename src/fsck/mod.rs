@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, fmt, io};
+
+use serde::Serialize;
+
+use crate::{
+    block::{BlockManager, MetadataStore, BLOCK_SIZE, MAGIC_NUMBER, TOTAL_BLOCKS},
+    directory::DirectoryTree,
+    path::VfsPath,
+    snapshot::Snapshot,
+};
+
+/// Inconsistência encontrada pela verificação
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// A imagem não começa com o magic number esperado
+    BadMagic { found: u32 },
+    /// O arquivo está no índice, mas nenhum diretório o referencia
+    UnlinkedFile { path: String },
+    /// O diretório referencia um arquivo sem metadados no índice
+    MissingMetadata { path: String },
+    /// A chave do índice difere do caminho gravado nos metadados
+    PathMismatch { key: String, path: String },
+    /// O arquivo aponta para um bloco fora do disco
+    InvalidBlock { path: String, block: usize },
+    /// Um arquivo da snapshot aponta para um bloco fora do disco
+    InvalidSnapshotBlock {
+        snapshot: String,
+        path: String,
+        block: usize,
+    },
+    /// Os blocos do arquivo não comportam o tamanho registrado
+    SizeMismatch {
+        path: String,
+        size: u64,
+        blocks: usize,
+    },
+    /// O mapa de blocos não bate com as referências dos arquivos e snapshots
+    RefCountMismatch {
+        block: usize,
+        expected: usize,
+        found: u8,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadMagic { found } => write!(
+                f,
+                "magic number inválido: {:#010x} (esperado {:#010x})",
+                found, MAGIC_NUMBER
+            ),
+            Problem::UnlinkedFile { path } => {
+                write!(f, "'{}' está no índice, mas não em um diretório", path)
+            }
+            Problem::MissingMetadata { path } => {
+                write!(f, "'{}' está em um diretório, mas não no índice", path)
+            }
+            Problem::PathMismatch { key, path } => {
+                write!(f, "'{}' tem metadados com o caminho '{}'", key, path)
+            }
+            Problem::InvalidBlock { path, block } => {
+                write!(f, "'{}' aponta para o bloco inexistente {}", path, block)
+            }
+            Problem::InvalidSnapshotBlock {
+                snapshot,
+                path,
+                block,
+            } => write!(
+                f,
+                "'{}' na snapshot '{}' aponta para o bloco inexistente {}",
+                path, snapshot, block
+            ),
+            Problem::SizeMismatch { path, size, blocks } => write!(
+                f,
+                "'{}' tem {} bytes em apenas {} bloco(s)",
+                path, size, blocks
+            ),
+            Problem::RefCountMismatch {
+                block,
+                expected,
+                found,
+            } => write!(
+                f,
+                "bloco {} tem {} referência(s) no mapa, mas {} nos arquivos e snapshots",
+                block, found, expected
+            ),
+        }
+    }
+}
+
+/// Resultado de `check`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FsckReport {
+    pub files: usize,
+    pub directories: usize,
+    pub blocks_in_use: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Verifica a consistência entre a árvore, o índice e o mapa de blocos
+///
+/// Os blocos das snapshots também contam nas referências esperadas. A
+/// verificação só lê: nada é corrigido, e os problemas ficam no relatório.
+pub fn check(
+    tree: &DirectoryTree,
+    metadata_store: &MetadataStore,
+    snapshots: &BTreeMap<String, Snapshot>,
+    block_manager: &BlockManager,
+) -> io::Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    let magic = block_manager.read_magic()?;
+    if magic != MAGIC_NUMBER {
+        report.problems.push(Problem::BadMagic { found: magic });
+    }
+
+    // Referências da árvore para os arquivos
+    let directories = tree.subtree(tree.root());
+    report.directories = directories.len();
+    for id in directories {
+        let directory = tree.directory(id)?;
+        let directory_path = tree.path_of(id);
+        for name in &directory.files {
+            let path = directory_path.child_unchecked(name).to_string();
+            if metadata_store.get_file_metadata(&path).is_none() {
+                report.problems.push(Problem::MissingMetadata { path });
+            }
+        }
+    }
+
+    // Arquivos do índice e os blocos que eles referenciam
+    let mut expected_refs: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut paths: Vec<&str> = metadata_store.paths().collect();
+    paths.sort();
+    report.files = paths.len();
+    for key in paths {
+        let Some(metadata) = metadata_store.get_file_metadata(key) else {
+            continue;
+        };
+        if metadata.path != key {
+            report.problems.push(Problem::PathMismatch {
+                key: key.to_string(),
+                path: metadata.path.clone(),
+            });
+        }
+        if !is_linked(tree, key) {
+            report.problems.push(Problem::UnlinkedFile {
+                path: key.to_string(),
+            });
+        }
+        if metadata.archive.is_some() {
+            continue; // O conteúdo está no tar de origem, e não em blocos
+        }
+
+        let capacity = (metadata.block_indices.len() * BLOCK_SIZE) as u64;
+        if metadata.size > capacity {
+            report.problems.push(Problem::SizeMismatch {
+                path: key.to_string(),
+                size: metadata.size,
+                blocks: metadata.block_indices.len(),
+            });
+        }
+        for block in metadata.referenced_blocks() {
+            if block >= TOTAL_BLOCKS {
+                report.problems.push(Problem::InvalidBlock {
+                    path: key.to_string(),
+                    block,
+                });
+            } else {
+                *expected_refs.entry(block).or_default() += 1;
+            }
+        }
+    }
+
+    // Blocos congelados nas snapshots
+    for (name, snapshot) in snapshots {
+        let mut paths: Vec<&str> = snapshot.metadata_store.paths().collect();
+        paths.sort();
+        for key in paths {
+            let Some(metadata) = snapshot.metadata_store.get_file_metadata(key) else {
+                continue;
+            };
+            if metadata.archive.is_some() {
+                continue;
+            }
+            for block in metadata.referenced_blocks() {
+                if block >= TOTAL_BLOCKS {
+                    report.problems.push(Problem::InvalidSnapshotBlock {
+                        snapshot: name.clone(),
+                        path: key.to_string(),
+                        block,
+                    });
+                } else {
+                    *expected_refs.entry(block).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    // Blocos ocupados sem dono e blocos com contagem errada
+    for (block, &found) in block_manager.block_refs().iter().enumerate() {
+        let expected = expected_refs.get(&block).copied().unwrap_or(0);
+        if found > 0 {
+            report.blocks_in_use += 1;
+        }
+        if expected != usize::from(found) {
+            report.problems.push(Problem::RefCountMismatch {
+                block,
+                expected,
+                found,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+fn is_linked(tree: &DirectoryTree, key: &str) -> bool {
+    let Ok(path) = VfsPath::parse(key) else {
+        return false;
+    };
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    tree.lookup(&parent)
+        .and_then(|id| tree.get(id))
+        .is_some_and(|directory| directory.files.contains(name))
+}
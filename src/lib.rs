@@ -1,15 +1,30 @@
 pub mod block;
+pub mod cache;
+pub mod cli;
 pub mod directory;
+pub mod error;
 pub mod file;
+pub mod filesystem;
+pub mod fsck;
+pub mod lock;
+pub mod migration;
+pub mod nonblocking;
+pub mod overlay;
+pub mod path;
+pub mod script;
+pub mod shared;
+pub mod shell;
+pub mod snapshot;
+pub mod tar;
+pub mod versioning;
+pub mod vfs;
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
     use block::{BlockManager, MetadataStore};
-    use chrono::Utc;
-    use directory::{create_directory, DirectoryMetadata};
+    use directory::{create_directory, DirectoryId, DirectoryTree};
     use file::{create_file_in_directory, write_to_file, FileMetadata};
+    use path::VfsPath;
 
     use super::*; // Importa todos os itens do módulo principal
 
@@ -23,6 +38,9 @@ mod tests {
             modified_at: "2024-11-29T12:00:00Z".to_string(),
             size: 1024,
             block_indices: vec![1, 2, 3],
+            archive: None,
+            versions: Vec::new(),
+            next_version: 0,
         };
         store.add_file("test_file", metadata.clone());
         let result = store.get_file_metadata("test_file");
@@ -41,6 +59,9 @@ mod tests {
             modified_at: "2024-11-29T12:00:00Z".to_string(),
             size: 1024,
             block_indices: vec![1, 2, 3],
+            archive: None,
+            versions: Vec::new(),
+            next_version: 0,
         };
         store.add_file("test_file", metadata);
         store.remove_file_metadata("test_file");
@@ -53,7 +74,7 @@ mod tests {
     fn test_block_manager_allocation() {
         let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
         let disk_path = temp_disk.path().to_str().unwrap();
-        let mut block_manager = BlockManager::initialize(disk_path).unwrap();
+        let block_manager = BlockManager::initialize(disk_path).unwrap();
 
         let block_index = block_manager.allocate_block().unwrap();
         assert_eq!(block_index, 0);
@@ -67,7 +88,7 @@ mod tests {
     fn test_block_manager_write_and_read() {
         let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
         let disk_path = temp_disk.path().to_str().unwrap();
-        let mut block_manager = BlockManager::initialize(disk_path).unwrap();
+        let block_manager = BlockManager::initialize(disk_path).unwrap();
 
         let block_index = block_manager.allocate_block().unwrap();
         let data = b"Hello, VFS!";
@@ -79,35 +100,24 @@ mod tests {
 
     #[test]
     fn test_create_and_list_directory() {
-        let mut root_directory = DirectoryMetadata {
-            parent: None,
-            name: "/".to_string(),
-            created_at: Utc::now().to_rfc3339(),
-            modified_at: Utc::now().to_rfc3339(),
-            files: HashMap::new(),
-            subdirectories: HashMap::new(),
-        };
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
 
-        create_directory("test_dir", &mut root_directory).unwrap();
-        assert!(root_directory.subdirectories.contains_key("test_dir"));
+        create_directory("test_dir", &mut tree, root).unwrap();
+        assert!(tree.get(root).unwrap().subdirectories.contains_key("test_dir"));
     }
 
     #[test]
     fn test_create_and_read_file() {
         let mut metadata_store = MetadataStore::new();
-        let mut root_directory = DirectoryMetadata {
-            parent: None,
-            name: "/".to_string(),
-            created_at: Utc::now().to_rfc3339(),
-            modified_at: Utc::now().to_rfc3339(),
-            files: HashMap::new(),
-            subdirectories: HashMap::new(),
-        };
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
 
         // Cria o arquivo no diretório
         create_file_in_directory(
             "test_file",
-            &mut root_directory,
+            &mut tree,
+            root,
             &mut metadata_store,
             "rw-r--r--",
         )
@@ -125,22 +135,17 @@ mod tests {
     fn test_write_to_file() {
         let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
         let disk_path = temp_disk.path().to_str().unwrap();
-        let mut block_manager = BlockManager::initialize(disk_path).unwrap();
+        let block_manager = BlockManager::initialize(disk_path).unwrap();
 
         let mut metadata_store = MetadataStore::new();
-        let mut root_directory = DirectoryMetadata {
-            parent: None,
-            name: "/".to_string(),
-            created_at: Utc::now().to_rfc3339(),
-            modified_at: Utc::now().to_rfc3339(),
-            files: HashMap::new(),
-            subdirectories: HashMap::new(),
-        };
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
 
         // Cria o arquivo
         create_file_in_directory(
             "test_file",
-            &mut root_directory,
+            &mut tree,
+            root,
             &mut metadata_store,
             "rw-r--r--",
         )
@@ -151,8 +156,9 @@ mod tests {
             "/test_file",
             "Hello, VFS!",
             &mut metadata_store,
-            &mut block_manager,
-            &root_directory,
+            &block_manager,
+            &tree,
+            root,
         )
         .unwrap();
 
@@ -163,4 +169,2100 @@ mod tests {
         // Atualizado para o tamanho correto
         assert_eq!(file_metadata.size, 11); // O texto "Hello, VFS!" tem 11 bytes
     }
+
+    /// Monta um arquivo tar ustar mínimo com as entradas informadas
+    fn build_tar(entries: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (path, data) in entries {
+            let mut header = [0u8; 512];
+            header[..path.len()].copy_from_slice(path.as_bytes());
+            header[100..107].copy_from_slice(b"0000644");
+            let size = data.map_or(0, |d| d.len());
+            header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+            header[156] = if data.is_some() { b'0' } else { b'5' };
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[148..156].copy_from_slice(b"        ");
+            let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+            header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+            archive.extend_from_slice(&header);
+            if let Some(data) = data {
+                archive.extend_from_slice(data);
+                archive.resize(archive.len().div_ceil(512) * 512, 0);
+            }
+        }
+        archive.extend_from_slice(&[0u8; 1024]);
+        archive
+    }
+
+    /// Atalho para localizar um diretório pelo caminho absoluto
+    fn lookup(tree: &DirectoryTree, path: &str) -> Option<DirectoryId> {
+        tree.lookup(&VfsPath::parse(path).unwrap())
+    }
+
+    #[test]
+    fn test_mount_tar_read_only() {
+        use assert_fs::prelude::*;
+
+        let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
+        let block_manager =
+            BlockManager::initialize(temp_disk.path().to_str().unwrap()).unwrap();
+        let temp_tar = assert_fs::NamedTempFile::new("fixture.tar").unwrap();
+        temp_tar
+            .write_binary(&build_tar(&[
+                ("docs/", None),
+                ("docs/readme.txt", Some(b"Hello, tar!")),
+                ("top.txt", Some(b"top")),
+            ]))
+            .unwrap();
+
+        let mut metadata_store = MetadataStore::new();
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
+        tar::mount_tar(
+            temp_tar.path().to_str().unwrap(),
+            "mnt",
+            &mut tree,
+            root,
+            &mut metadata_store,
+        )
+        .unwrap();
+
+        let mount_point = lookup(&tree, "/mnt").unwrap();
+        assert!(tree.get(mount_point).unwrap().files.contains("top.txt"));
+        let docs = lookup(&tree, "/mnt/docs").unwrap();
+        assert!(tree.get(docs).unwrap().files.contains("readme.txt"));
+
+        let content =
+            file::read_file("/mnt/docs/readme.txt", &metadata_store, &block_manager).unwrap();
+        assert_eq!(content, "Hello, tar!");
+
+        let error = write_to_file(
+            "/mnt/top.txt",
+            "novo",
+            &mut metadata_store,
+            &block_manager,
+            &tree,
+            root,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ReadOnlyFilesystem);
+
+        let error = create_directory("novo", &mut tree, mount_point).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ReadOnlyFilesystem);
+    }
+
+    #[test]
+    fn test_rename_path_moves_files_and_subtrees() {
+        let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
+        let block_manager =
+            BlockManager::initialize(temp_disk.path().to_str().unwrap()).unwrap();
+        let mut metadata_store = MetadataStore::new();
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
+
+        let a = create_directory("a", &mut tree, root).unwrap();
+        create_directory("b", &mut tree, root).unwrap();
+        let nested = create_directory("nested", &mut tree, a).unwrap();
+        create_file_in_directory("data.txt", &mut tree, nested, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+
+        // Não é permitido mover um diretório para dentro de si mesmo
+        let error = directory::rename_path(
+            &VfsPath::parse("/a").unwrap(),
+            &VfsPath::parse("/a/nested/inner").unwrap(),
+            &mut tree,
+            &mut metadata_store,
+            &block_manager,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        // Mover para um diretório existente coloca a subárvore dentro dele
+        directory::rename_path(
+            &VfsPath::parse("/a").unwrap(),
+            &VfsPath::parse("/b").unwrap(),
+            &mut tree,
+            &mut metadata_store,
+            &block_manager,
+        )
+        .unwrap();
+        assert!(lookup(&tree, "/a").is_none());
+        // O ID do diretório movido continua válido
+        assert_eq!(lookup(&tree, "/b/a/nested"), Some(nested));
+        assert!(metadata_store.get_file_metadata("/a/nested/data.txt").is_none());
+        let moved = metadata_store
+            .get_file_metadata("/b/a/nested/data.txt")
+            .expect("Caminho não foi atualizado no MetadataStore");
+        assert_eq!(moved.path, "/b/a/nested/data.txt");
+
+        // Renomear um arquivo sobre outro substitui o destino
+        create_file_in_directory("old.txt", &mut tree, root, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        write_to_file("/old.txt", "antigo", &mut metadata_store, &block_manager, &tree, root)
+            .unwrap();
+        directory::rename_path(
+            &VfsPath::parse("/b/a/nested/data.txt").unwrap(),
+            &VfsPath::parse("/old.txt").unwrap(),
+            &mut tree,
+            &mut metadata_store,
+            &block_manager,
+        )
+        .unwrap();
+        assert_eq!(metadata_store.get_file_metadata("/old.txt").unwrap().size, 0);
+        assert!(tree.get(nested).unwrap().files.is_empty());
+        // O bloco do arquivo substituído volta a ficar livre
+        assert_eq!(block_manager.allocate_block().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_copy_path_with_and_without_reflink() {
+        let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
+        let block_manager =
+            BlockManager::initialize(temp_disk.path().to_str().unwrap()).unwrap();
+        let mut metadata_store = MetadataStore::new();
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
+
+        let src = create_directory("src", &mut tree, root).unwrap();
+        create_file_in_directory("a.txt", &mut tree, src, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        write_to_file("/src/a.txt", "conteúdo", &mut metadata_store, &block_manager, &tree, root)
+            .unwrap();
+        let source_block = metadata_store.get_file_metadata("/src/a.txt").unwrap().block_indices[0];
+
+        // Diretórios exigem cópia recursiva
+        let error = directory::copy_path(
+            &VfsPath::parse("/src").unwrap(),
+            &VfsPath::parse("/dst").unwrap(),
+            &mut tree,
+            &mut metadata_store,
+            &block_manager,
+            false,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::IsADirectory);
+
+        // Cópia comum duplica os blocos
+        directory::copy_path(
+            &VfsPath::parse("/src").unwrap(),
+            &VfsPath::parse("/dst").unwrap(),
+            &mut tree,
+            &mut metadata_store,
+            &block_manager,
+            true,
+            false,
+        )
+        .unwrap();
+        let copy = metadata_store.get_file_metadata("/dst/a.txt").unwrap();
+        assert_ne!(copy.block_indices[0], source_block);
+        let dst = lookup(&tree, "/dst").unwrap();
+        assert!(tree.get(dst).unwrap().files.contains("a.txt"));
+        assert_eq!(
+            file::read_file("/dst/a.txt", &metadata_store, &block_manager).unwrap(),
+            "conteúdo"
+        );
+
+        // Com reflink a cópia compartilha os blocos até ser modificada
+        directory::copy_path(
+            &VfsPath::parse("/src/a.txt").unwrap(),
+            &VfsPath::parse("/b.txt").unwrap(),
+            &mut tree,
+            &mut metadata_store,
+            &block_manager,
+            false,
+            true,
+        )
+        .unwrap();
+        let shared = metadata_store.get_file_metadata("/b.txt").unwrap();
+        assert_eq!(shared.block_indices, vec![source_block]);
+        assert_eq!(block_manager.block_ref_count(source_block).unwrap(), 2);
+
+        write_to_file("/b.txt", "alterado", &mut metadata_store, &block_manager, &tree, root)
+            .unwrap();
+        assert_eq!(block_manager.block_ref_count(source_block).unwrap(), 1);
+        assert_eq!(
+            file::read_file("/src/a.txt", &metadata_store, &block_manager).unwrap(),
+            "conteúdo"
+        );
+        assert_eq!(
+            file::read_file("/b.txt", &metadata_store, &block_manager).unwrap(),
+            "alterado"
+        );
+    }
+
+    #[test]
+    fn test_remove_path_recursive_frees_blocks() {
+        let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
+        let block_manager =
+            BlockManager::initialize(temp_disk.path().to_str().unwrap()).unwrap();
+        let mut metadata_store = MetadataStore::new();
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
+
+        let subtree = create_directory("tree", &mut tree, root).unwrap();
+        create_file_in_directory("a.txt", &mut tree, subtree, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        let sub = create_directory("sub", &mut tree, subtree).unwrap();
+        create_file_in_directory("b.txt", &mut tree, sub, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        write_to_file("/tree/a.txt", "abc", &mut metadata_store, &block_manager, &tree, root)
+            .unwrap();
+        write_to_file("/tree/sub/b.txt", "defgh", &mut metadata_store, &block_manager, &tree, root)
+            .unwrap();
+
+        // Remoção simples continua recusando diretórios com conteúdo
+        assert!(directory::remove_directory("tree", &mut tree, root).is_err());
+
+        let report = directory::remove_path_recursive(
+            &VfsPath::parse("/tree").unwrap(),
+            &mut tree,
+            &mut metadata_store,
+            &block_manager,
+        )
+        .unwrap();
+
+        assert_eq!(
+            report,
+            directory::RemovalReport {
+                files: 2,
+                directories: 2,
+                bytes: 8,
+                blocks_freed: 2,
+            }
+        );
+        assert!(lookup(&tree, "/tree").is_none());
+        // Os nós da subárvore saem da arena
+        assert!(tree.get(subtree).is_none());
+        assert!(tree.get(sub).is_none());
+        assert!(metadata_store.get_file_metadata("/tree/a.txt").is_none());
+        assert!(metadata_store.get_file_metadata("/tree/sub/b.txt").is_none());
+        assert_eq!(block_manager.block_ref_count(0).unwrap(), 0);
+        assert_eq!(block_manager.block_ref_count(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_vfs_path_normalization() {
+        let base = VfsPath::parse("/home/user").unwrap();
+
+        assert_eq!(base.join("./docs//a.txt").unwrap().to_string(), "/home/user/docs/a.txt");
+        assert_eq!(base.join("../other/./x").unwrap().to_string(), "/home/other/x");
+        assert_eq!(base.join("//etc/../var").unwrap().to_string(), "/var");
+        assert_eq!(base.join("../../../..").unwrap().to_string(), "/");
+        assert!(VfsPath::parse("/").unwrap().is_root());
+
+        let path = VfsPath::parse("a/b/c").unwrap();
+        assert_eq!(path.components().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert_eq!(path.file_name(), Some("c"));
+        assert_eq!(path.parent().unwrap().to_string(), "/a/b");
+        assert!(path.starts_with(&VfsPath::parse("/a").unwrap()));
+        assert!(!path.starts_with(&VfsPath::parse("/a/b/cd").unwrap()));
+
+        assert!(path::validate_name("").is_err());
+        assert!(path::validate_name("..").is_err());
+        assert!(path::validate_name("a\0b").is_err());
+        assert!(base.child("a/b").is_err());
+        assert!(base.join(&"x".repeat(path::MAX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_nested_directories_use_full_paths() {
+        let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
+        let block_manager =
+            BlockManager::initialize(temp_disk.path().to_str().unwrap()).unwrap();
+        let mut metadata_store = MetadataStore::new();
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
+
+        let a = create_directory("a", &mut tree, root).unwrap();
+        let b = create_directory("b", &mut tree, a).unwrap();
+        assert_eq!(directory::directory_path(&tree, b).to_string(), "/a/b");
+
+        let mut current_directory = b;
+        create_file_in_directory("f.txt", &mut tree, current_directory, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        assert!(metadata_store.get_file_metadata("/a/b/f.txt").is_some());
+
+        write_to_file(
+            "./f.txt",
+            "ok",
+            &mut metadata_store,
+            &block_manager,
+            &tree,
+            current_directory,
+        )
+        .unwrap();
+        assert_eq!(
+            file::read_file("//a/./b/../b/f.txt", &metadata_store, &block_manager).unwrap(),
+            "ok"
+        );
+
+        assert!(create_directory("../escape", &mut tree, current_directory).is_err());
+        directory::change_directory(&tree, &mut current_directory, "../..").unwrap();
+        assert_eq!(current_directory, root);
+    }
+
+    #[test]
+    fn test_directory_tree_is_shared_by_id() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let mut metadata_store = MetadataStore::new();
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
+
+        // Alterações feitas a partir do diretório atual aparecem na própria árvore
+        let a = create_directory("a", &mut tree, root).unwrap();
+        let mut current_directory = a;
+        create_directory("b", &mut tree, current_directory).unwrap();
+        create_file_in_directory("f.txt", &mut tree, current_directory, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        directory::change_directory(&tree, &mut current_directory, "b").unwrap();
+        directory::change_directory(&tree, &mut current_directory, "..").unwrap();
+        assert_eq!(current_directory, a);
+        assert!(tree.get(a).unwrap().files.contains("f.txt"));
+        assert_eq!(tree.get(lookup(&tree, "/a/b").unwrap()).unwrap().parent, Some(a));
+
+        // A arena é salva e recarregada com os mesmos IDs
+        let path = temp_dir.path().join("filesystem.json");
+        let path = path.to_str().unwrap();
+        directory::save_hierarchy(&tree, &metadata_store, path).unwrap();
+        let (loaded, _) = directory::load_hierarchy(path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(lookup(&loaded, "/a"), Some(a));
+
+        // O formato aninhado antigo continua sendo aceito
+        let legacy = r#"[{"name":"/","created_at":"","modified_at":"","files":{},
+            "subdirectories":{"x":{"name":"x","created_at":"","modified_at":"","files":{},
+            "subdirectories":{},"parent":null}},"parent":null},{"files":{}}]"#;
+        std::fs::write(path, legacy).unwrap();
+        let (converted, _) = directory::load_hierarchy(path).unwrap();
+        assert!(lookup(&converted, "/x").is_some());
+    }
+
+    #[test]
+    fn test_migrate_hierarchy_merges_file_metadata() {
+        use serde_json::json;
+
+        let file = |path: &str, size: u64, modified_at: &str| FileMetadata {
+            path: path.to_string(),
+            permissions: "rw-r--r--".to_string(),
+            created_at: "2024-11-29T12:00:00Z".to_string(),
+            modified_at: modified_at.to_string(),
+            size,
+            block_indices: vec![],
+            archive: None,
+            versions: Vec::new(),
+            next_version: 0,
+        };
+        let directory = |name: &str, parent: Option<u64>, files, subdirectories| {
+            json!({
+                "name": name, "created_at": "", "modified_at": "",
+                "files": files, "subdirectories": subdirectories, "parent": parent,
+            })
+        };
+
+        // Arena antiga: cada diretório ainda guardava cópias dos metadados
+        let tree_copy = file("/docs/a.txt", 3, "2024-11-29T12:00:00Z");
+        let only_in_tree = file("/docs/b.txt", 1, "2024-11-29T12:00:00Z");
+        let newer_in_store = file("/docs/a.txt", 7, "2024-11-30T12:00:00Z");
+        let only_in_store = file("/lost/c.txt", 2, "2024-11-29T12:00:00Z");
+        let old_format = json!([
+            {
+                "root": 0,
+                "next_id": 2,
+                "directories": {
+                    "0": directory("/", None, json!({}), json!({ "docs": 1 })),
+                    "1": directory(
+                        "docs",
+                        Some(0),
+                        json!({ "a.txt": tree_copy, "b.txt": only_in_tree }),
+                        json!({}),
+                    ),
+                },
+            },
+            { "files": { "/docs/a.txt": newer_in_store, "/lost/c.txt": only_in_store } },
+        ]);
+
+        let (tree, metadata_store, report) =
+            migration::migrate_hierarchy(&old_format.to_string()).unwrap();
+
+        assert_eq!(report.files, 3);
+        assert_eq!(report.recovered, vec!["/docs/b.txt"]);
+        assert_eq!(report.adopted, vec!["/lost/c.txt"]);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].path, "/docs/a.txt");
+        assert_eq!(report.conflicts[0].kept, migration::MetadataSource::Store);
+        assert_eq!(report.conflicts[0].discarded.size, 3);
+
+        // O índice é a única fonte dos metadados; a árvore só guarda os nomes
+        assert_eq!(metadata_store.get_file_metadata("/docs/a.txt").unwrap().size, 7);
+        assert!(metadata_store.get_file_metadata("/docs/b.txt").is_some());
+        let docs = tree.get(lookup(&tree, "/docs").unwrap()).unwrap();
+        assert_eq!(docs.files.iter().collect::<Vec<_>>(), vec!["a.txt", "b.txt"]);
+        let lost = tree.get(lookup(&tree, "/lost").unwrap()).unwrap();
+        assert!(lost.files.contains("c.txt"));
+
+        // O formato atual é lido sem migração
+        let current = serde_json::to_string(&(&tree, &metadata_store)).unwrap();
+        let (_, _, report) = migration::load_hierarchy_data(&current).unwrap();
+        assert!(report.is_none());
+    }
+
+    #[test]
+    fn test_vfs_facade_keeps_state_consistent() {
+        use vfs::{DirectoryEntry, EntryKind, Vfs};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        let mut vfs = Vfs::open(image).unwrap();
+        vfs.mkdir("docs").unwrap();
+        vfs.cd("docs").unwrap();
+        vfs.create("a.txt", "rw-r--r--").unwrap();
+        vfs.write("a.txt", "Hello, VFS!").unwrap();
+        assert_eq!(vfs.read("/docs/a.txt").unwrap(), "Hello, VFS!");
+        assert_eq!(vfs.pwd().to_string(), "/docs");
+
+        let stat = vfs.stat("a.txt").unwrap();
+        assert_eq!(stat.kind, EntryKind::File);
+        assert_eq!(stat.size, 11);
+        assert_eq!(vfs.stat("/docs").unwrap().size, 1);
+
+        // Diretórios só são removidos com `recursive`
+        let error = vfs.rm("/docs", false).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::IsADirectory);
+        vfs.mkdir("/tmp").unwrap();
+        vfs.create("/tmp/b.txt", "rw-r--r--").unwrap();
+        vfs.sync().unwrap();
+        drop(vfs);
+
+        // O estado salvo por `sync` é recuperado ao reabrir a imagem
+        let mut vfs = Vfs::open(image).unwrap();
+        assert_eq!(vfs.pwd().to_string(), "/docs");
+        assert_eq!(
+            vfs.ls("/").unwrap(),
+            vec![
+                DirectoryEntry {
+                    name: "docs".to_string(),
+                    kind: EntryKind::Directory,
+                },
+                DirectoryEntry {
+                    name: "tmp".to_string(),
+                    kind: EntryKind::Directory,
+                },
+            ]
+        );
+        assert_eq!(vfs.read("a.txt").unwrap(), "Hello, VFS!");
+
+        // Remover o diretório atual leva de volta para a raiz
+        let report = vfs.rm("/docs", true).unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(vfs.pwd().to_string(), "/");
+        assert!(vfs.stat("/docs/a.txt").is_err());
+        assert!(vfs.metadata_store().get_file_metadata("/docs/a.txt").is_none());
+    }
+
+    /// Simula uma invocação da CLI: abre a imagem, executa e salva o estado
+    fn run_command(image: &str, command: impl FnOnce(&mut vfs::Vfs)) {
+        let mut vfs = vfs::Vfs::open(image).unwrap();
+        command(&mut vfs);
+        vfs.sync().unwrap();
+    }
+
+    /// Lê a hierarquia salva em `filesystem.json`, como a próxima invocação faria
+    fn saved_hierarchy(temp_dir: &assert_fs::TempDir) -> (DirectoryTree, MetadataStore) {
+        let path = temp_dir.path().join(vfs::HIERARCHY_FILE);
+        directory::load_hierarchy(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_create_after_cd_persists_in_root_tree() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        run_command(image, |vfs| vfs.mkdir("a").unwrap());
+        run_command(image, |vfs| vfs.cd("a").unwrap());
+        run_command(image, |vfs| vfs.create("f.txt", "rw-r--r--").unwrap());
+        run_command(image, |vfs| vfs.write("f.txt", "dados").unwrap());
+
+        let (tree, metadata_store) = saved_hierarchy(&temp_dir);
+        let a = lookup(&tree, "/a").unwrap();
+        assert!(tree.get(a).unwrap().files.contains("f.txt"));
+        assert!(tree.get(tree.root()).unwrap().files.is_empty());
+        assert_eq!(metadata_store.get_file_metadata("/a/f.txt").unwrap().size, 5);
+
+        // O diretório atual é salvo como caminho dentro da árvore
+        let saved_cwd =
+            std::fs::read_to_string(temp_dir.path().join(vfs::CURRENT_DIRECTORY_FILE)).unwrap();
+        assert_eq!(serde_json::from_str::<String>(&saved_cwd).unwrap(), "/a");
+    }
+
+    #[test]
+    fn test_mkdir_after_cd_persists_in_root_tree() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        run_command(image, |vfs| vfs.mkdir("a").unwrap());
+        run_command(image, |vfs| vfs.cd("a").unwrap());
+        run_command(image, |vfs| vfs.mkdir("b").unwrap());
+        run_command(image, |vfs| vfs.cd("b").unwrap());
+        run_command(image, |vfs| vfs.mkdir("c").unwrap());
+        run_command(image, |vfs| vfs.mkdir("../irmao").unwrap());
+
+        let (tree, _) = saved_hierarchy(&temp_dir);
+        assert!(lookup(&tree, "/a/b/c").is_some());
+        assert!(lookup(&tree, "/a/irmao").is_some());
+        assert!(lookup(&tree, "/b").is_none());
+        assert!(lookup(&tree, "/c").is_none());
+        run_command(image, |vfs| assert_eq!(vfs.pwd().to_string(), "/a/b"));
+    }
+
+    #[test]
+    fn test_rm_after_cd_updates_root_tree() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        run_command(image, |vfs| {
+            vfs.mkdir("a").unwrap();
+            vfs.mkdir("a/b").unwrap();
+            vfs.create("a/f.txt", "rw-r--r--").unwrap();
+            vfs.create("a/b/g.txt", "rw-r--r--").unwrap();
+            vfs.write("a/b/g.txt", "conteúdo").unwrap();
+        });
+        run_command(image, |vfs| vfs.cd("a/b").unwrap());
+        run_command(image, |vfs| {
+            vfs.rm("../f.txt", false).unwrap();
+        });
+        run_command(image, |vfs| {
+            vfs.rm("g.txt", false).unwrap();
+        });
+
+        let (tree, metadata_store) = saved_hierarchy(&temp_dir);
+        assert!(tree.get(lookup(&tree, "/a").unwrap()).unwrap().files.is_empty());
+        assert!(tree.get(lookup(&tree, "/a/b").unwrap()).unwrap().files.is_empty());
+        assert!(metadata_store.get_file_metadata("/a/f.txt").is_none());
+        assert!(metadata_store.get_file_metadata("/a/b/g.txt").is_none());
+
+        // Remover o próprio diretório atual leva a próxima invocação para a raiz
+        run_command(image, |vfs| {
+            vfs.rm("/a", true).unwrap();
+        });
+        let (tree, _) = saved_hierarchy(&temp_dir);
+        assert!(lookup(&tree, "/a").is_none());
+        run_command(image, |vfs| assert!(vfs.pwd().is_root()));
+    }
+
+    #[test]
+    fn test_split_command_line_handles_quotes() {
+        assert_eq!(
+            shell::split_command_line(r#"write "a b.txt" 'olá mundo'  "x\"y""#).unwrap(),
+            vec!["write", "a b.txt", "olá mundo", "x\"y"]
+        );
+        assert_eq!(
+            shell::split_command_line(r#"write f.txt """#).unwrap(),
+            vec!["write", "f.txt", ""]
+        );
+        assert!(shell::split_command_line("write \"aberto").is_err());
+    }
+
+    #[test]
+    fn test_shell_saves_state_on_sync_and_exit() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+        let hierarchy = temp_dir.path().join(vfs::HIERARCHY_FILE);
+        let history_path = temp_dir.path().join(shell::HISTORY_FILE);
+        let history_path = history_path.to_str().unwrap();
+
+        let mut vfs = vfs::Vfs::open(image).unwrap();
+        let mut history = shell::History::load(history_path);
+        let input = "mkdir docs\ncd docs\ncreate a.txt rw-r--r--\nwrite a.txt \"olá, shell\"\n\
+                     comando-invalido\nsync\nmkdir sub\n!!\nexit\nmkdir depois\n";
+        shell::run_shell(&mut vfs, std::io::Cursor::new(input), &mut history).unwrap();
+
+        // `!!` repete o último comando; erros não encerram o shell
+        assert_eq!(history.entries().len(), 9);
+        assert_eq!(history.entries()[7], "mkdir sub");
+        assert_eq!(history.expand("!3").unwrap(), "create a.txt rw-r--r--");
+        assert!(history.expand("!99").is_err());
+        assert_eq!(shell::History::load(history_path).entries(), history.entries());
+
+        // Comandos depois de `exit` não são executados
+        let (tree, metadata_store) =
+            directory::load_hierarchy(hierarchy.to_str().unwrap()).unwrap();
+        assert!(lookup(&tree, "/docs/sub").is_some());
+        assert!(lookup(&tree, "/docs/depois").is_none());
+        assert_eq!(metadata_store.get_file_metadata("/docs/a.txt").unwrap().size, 11);
+
+        drop(vfs);
+        let reopened = vfs::Vfs::open(image).unwrap();
+        assert_eq!(reopened.pwd().to_string(), "/docs");
+        assert_eq!(reopened.read("a.txt").unwrap(), "olá, shell");
+    }
+
+    #[test]
+    fn test_run_script_stops_on_first_failure() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let mut vfs = vfs::Vfs::open(image.to_str().unwrap()).unwrap();
+
+        let mut variables = script::Variables::new();
+        variables.insert("NAME".to_string(), "a.txt".to_string());
+        let input = "# preparar os dados\nDIR=/dados\nmkdir $DIR\n\
+                     create \"${DIR}/$NAME\" rw-r--r--\nwrite $DIR/$NAME 'custa $5'\n\
+                     set +e\nmkdir $DIR\nset -e\nrm $DIR/ausente.txt\nmkdir /nunca\n";
+        let failure = script::run_script(
+            &mut vfs,
+            std::io::Cursor::new(input),
+            &mut variables,
+            shell::OutputFormat::Text,
+        )
+            .unwrap_err();
+
+        // Com `set +e` a falha da linha 7 não interrompe; a da linha 9 sim
+        assert_eq!(failure.line, 9);
+        assert_eq!(failure.command, "rm $DIR/ausente.txt");
+        assert_eq!(variables["DIR"], "/dados");
+        assert_eq!(vfs.read("/dados/a.txt").unwrap(), "custa $5");
+        assert!(vfs.tree().lookup(&VfsPath::parse("/nunca").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        let mut variables = script::Variables::new();
+        variables.insert("DIR".to_string(), "/dados".to_string());
+        assert_eq!(
+            script::expand_variables(r"cp ${DIR}/a '$DIR' \$DIR $", &variables).unwrap(),
+            r"cp /dados/a '$DIR' $DIR $"
+        );
+        assert!(script::expand_variables("ls $AUSENTE", &variables).is_err());
+        assert!(script::expand_variables("ls ${DIR", &variables).is_err());
+        assert_eq!(script::parse_assignment("DIR=/x"), Some(("DIR", "/x")));
+        assert_eq!(script::parse_assignment("write a=b"), None);
+    }
+
+    #[test]
+    fn test_cli_parses_global_options_and_subcommands() {
+        use cli::{Cli, Command};
+        let parse = |args: &[&str]| Cli::parse(args.iter().map(|arg| arg.to_string()));
+
+        let parsed = parse(&[
+            "--image",
+            "d/img.bin",
+            "--state-dir=estado",
+            "write",
+            "a",
+            "-x",
+        ])
+        .unwrap();
+        assert_eq!(parsed.image, "d/img.bin");
+        assert_eq!(parsed.state_dir, Some(std::path::PathBuf::from("estado")));
+        assert_eq!(
+            parsed.command,
+            Command::Execute(vec!["write".into(), "a".into(), "-x".into()])
+        );
+
+        let parsed = parse(&["run", "-", "DIR=/x"]).unwrap();
+        assert_eq!(parsed.image, cli::DEFAULT_IMAGE);
+        let Command::Run { script, variables } = parsed.command else {
+            panic!("expected run");
+        };
+        assert_eq!(script.as_deref(), Some("-"));
+        assert_eq!(variables["DIR"], "/x");
+
+        assert_eq!(
+            parse(&["rmdir", "--help"]).unwrap().command,
+            Command::Help(Some("rmdir".into()))
+        );
+        assert_eq!(parse(&[]).unwrap().command, Command::Help(None));
+        assert!(parse(&["--image"]).is_err());
+        assert!(parse(&["--desconhecida", "ls"]).is_err());
+
+        // Todos os comandos aparecem na ajuda
+        let usage = cli::usage();
+        for command in ["mkdir", "ls [<path>]", "cd", "rmdir", "mount-tar", "shell", "run"] {
+            assert!(usage.contains(&format!("  {}", command)), "{}", command);
+        }
+        assert_eq!(
+            cli::exit_code(&std::io::Error::from(std::io::ErrorKind::NotFound)),
+            cli::EXIT_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_cli_state_dir_option() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let state_dir = temp_dir.path().join("estado");
+        std::fs::create_dir(&state_dir).unwrap();
+        let args = [
+            "--image",
+            image.to_str().unwrap(),
+            "--state-dir",
+            state_dir.to_str().unwrap(),
+            "mkdir",
+            "docs",
+        ];
+        let parsed = cli::Cli::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+
+        let mut vfs = parsed.open().unwrap();
+        let cli::Command::Execute(command) = &parsed.command else {
+            panic!("expected a filesystem command");
+        };
+        shell::execute(&mut vfs, command, parsed.format).unwrap();
+        vfs.sync().unwrap();
+
+        // O estado fica no diretório escolhido, e não ao lado da imagem
+        assert!(state_dir.join(vfs::HIERARCHY_FILE).exists());
+        assert!(!temp_dir.path().join(vfs::HIERARCHY_FILE).exists());
+        drop(vfs);
+        let reopened = parsed.open().unwrap();
+        assert!(reopened.tree().lookup(&VfsPath::parse("/docs").unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_df_fsck_and_entry_metadata() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        let mut vfs = vfs::Vfs::open(image).unwrap();
+        vfs.mkdir("docs").unwrap();
+        vfs.create("docs/a.txt", "rw-r--r--").unwrap();
+        vfs.write("docs/a.txt", "olá").unwrap();
+        vfs.copy("docs/a.txt", "b.txt", false, true).unwrap();
+
+        let usage = vfs.df();
+        assert_eq!((usage.used_blocks, usage.shared_blocks), (1, 1));
+        assert_eq!((usage.files, usage.directories, usage.bytes), (2, 2, 8));
+        assert!(vfs.fsck().unwrap().is_clean());
+
+        // Os metadados em JSON são os mesmos gravados na hierarquia
+        let metadata = serde_json::to_value(vfs.entry_metadata("/docs/a.txt").unwrap()).unwrap();
+        assert_eq!(metadata["path"], "/docs/a.txt");
+        assert_eq!(metadata["block_indices"], serde_json::json!([0]));
+        let directory = vfs.entry_metadata("docs").unwrap();
+        assert_eq!(directory.kind(), vfs::EntryKind::Directory);
+        assert_eq!(
+            serde_json::to_value(directory).unwrap()["files"],
+            serde_json::json!(["a.txt"])
+        );
+        vfs.sync().unwrap();
+        drop(vfs);
+
+        // Uma referência perdida no mapa de blocos aparece na verificação
+        let block_manager = BlockManager::initialize(image).unwrap();
+        block_manager.free_block(0).unwrap();
+        drop(block_manager);
+        let report = vfs::Vfs::open(image).unwrap().fsck().unwrap();
+        assert_eq!(
+            report.problems,
+            vec![fsck::Problem::RefCountMismatch {
+                block: 0,
+                expected: 2,
+                found: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_verbose_flags_set_log_level() {
+        use log::LevelFilter;
+        let verbosity = |args: &[&str]| {
+            cli::Cli::parse(args.iter().map(|arg| arg.to_string()))
+                .unwrap()
+                .verbosity
+        };
+
+        assert_eq!(verbosity(&["ls"]), 0);
+        assert_eq!(verbosity(&["-v", "ls"]), 1);
+        assert_eq!(verbosity(&["--verbose", "-vv", "ls"]), 3);
+        // Depois do comando, `-v` é argumento do comando
+        assert_eq!(verbosity(&["write", "a.txt", "-v"]), 0);
+
+        assert_eq!(cli::log_level(0), LevelFilter::Off);
+        assert_eq!(cli::log_level(1), LevelFilter::Info);
+        assert_eq!(cli::log_level(2), LevelFilter::Debug);
+        assert_eq!(cli::log_level(9), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_disco_error_variants_and_conversion() {
+        use error::DiscoError;
+        use std::error::Error;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let block_manager = BlockManager::initialize(image.to_str().unwrap()).unwrap();
+        let mut tree = DirectoryTree::new();
+        let mut metadata_store = MetadataStore::new();
+        let root = tree.root();
+
+        let docs = create_directory("docs", &mut tree, root).unwrap();
+        create_file_in_directory("a.txt", &mut tree, docs, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        assert!(matches!(
+            create_directory("docs", &mut tree, root),
+            Err(DiscoError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            directory::remove_directory("docs", &mut tree, root),
+            Err(DiscoError::NotEmpty(_))
+        ));
+        assert!(matches!(
+            create_directory("a/b", &mut tree, root),
+            Err(DiscoError::InvalidPath(_))
+        ));
+
+        // Dados que não são UTF-8 indicam corrupção e guardam a causa
+        let block = block_manager.allocate_block().unwrap();
+        block_manager.write_block(block, &[0xff, 0xfe]).unwrap();
+        let mut metadata = metadata_store.get_file_metadata("/docs/a.txt").unwrap().clone();
+        metadata.block_indices = vec![block];
+        metadata.size = 2;
+        metadata_store.update_file_metadata("/docs/a.txt", metadata);
+        let error =
+            file::read_file("/docs/a.txt", &metadata_store, &block_manager).unwrap_err();
+        assert!(matches!(error, DiscoError::Corrupt { .. }));
+        assert!(error.source().is_some());
+
+        // A conversão para `io::Error` preserva o caso original
+        let io_error: std::io::Error = error.into();
+        assert_eq!(io_error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            DiscoError::from_io(&io_error),
+            Some(DiscoError::Corrupt { .. })
+        ));
+        assert!(matches!(DiscoError::from(io_error), DiscoError::Corrupt { .. }));
+
+        while block_manager.allocate_block().is_ok() {}
+        let error = block_manager.allocate_block().unwrap_err();
+        assert!(matches!(error, DiscoError::NoSpace(_)));
+        assert_eq!(cli::exit_code(&error.into()), cli::EXIT_NO_SPACE);
+    }
+
+    /// Executa a mesma sequência de operações e descreve cada resultado
+    fn exercise_filesystem(fs: &mut dyn filesystem::FileSystem) -> Vec<String> {
+        fn outcome<T: std::fmt::Debug>(result: error::Result<T>) -> String {
+            match result {
+                Ok(value) => format!("ok {:?}", value),
+                Err(e) => format!("erro {:?}", e.kind()),
+            }
+        }
+
+        let mut log = vec![
+            outcome(fs.mkdir("/docs")),
+            outcome(fs.mkdir("/docs")),
+            outcome(fs.create("/docs/a.txt", "rw-r--r--")),
+            outcome(fs.create("/docs/a.txt", "rw-r--r--")),
+            outcome(fs.create("/docs", "rw-r--r--")),
+            outcome(fs.create("/missing/b.txt", "rw-r--r--")),
+            outcome(fs.write("/docs/a.txt", "conteúdo")),
+            outcome(fs.write("/docs/b.txt", "nada")),
+            outcome(fs.read("/docs/a.txt")),
+            outcome(fs.mkdir("/docs/sub")),
+            outcome(fs.create("/docs/sub/c.txt", "rw-------")),
+            outcome(fs.rename("/docs/a.txt", "/docs/sub")),
+            outcome(fs.rename("/docs/sub", "/docs/sub/inner")),
+            outcome(fs.rename("/docs/sub/a.txt", "/docs/sub/c.txt")),
+            outcome(fs.rename("/docs", "/archive")),
+            outcome(fs.rename("/", "/x")),
+            outcome(fs.mkdir("/other")),
+            outcome(fs.mkdir("/other/sub")),
+            outcome(fs.create("/other/sub/d.txt", "rw-r--r--")),
+            outcome(fs.rename("/archive/sub", "/other")),
+            outcome(fs.readdir("/archive/sub")),
+            outcome(fs.readdir("/archive/sub/c.txt")),
+            outcome(fs.read("/archive/sub/c.txt")),
+            outcome(fs.remove("/archive", false)),
+            outcome(fs.remove("/archive/nope", true)),
+            outcome(fs.remove("/", true)),
+        ];
+        for path in ["/", "/archive", "/archive/sub/c.txt", "/docs"] {
+            log.push(outcome(
+                fs.stat(path)
+                    .map(|stat| (stat.path, stat.kind, stat.size, stat.permissions)),
+            ));
+        }
+        log.push(outcome(fs.remove("/archive/sub", true)));
+        log.push(outcome(fs.readdir("/archive")));
+        log.push(outcome(fs.readdir("/")));
+        log
+    }
+
+    #[test]
+    fn test_filesystem_implementations_behave_alike() {
+        use filesystem::MemoryFileSystem;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let mut vfs = vfs::Vfs::open(image.to_str().unwrap()).unwrap();
+        let mut memory = MemoryFileSystem::new();
+
+        let mut layered = overlay::OverlayFileSystem::new(
+            MemoryFileSystem::new(),
+            vec![Box::new(MemoryFileSystem::new())],
+        );
+
+        let on_disk = exercise_filesystem(&mut vfs);
+        let in_memory = exercise_filesystem(&mut memory);
+        assert_eq!(on_disk, in_memory);
+        assert_eq!(exercise_filesystem(&mut layered), in_memory);
+        assert!(in_memory.iter().any(|line| line == "ok \"conteúdo\""));
+        assert!(in_memory.iter().any(|line| line == "erro DirectoryNotEmpty"));
+    }
+
+    #[test]
+    fn test_overlay_copy_up_and_whiteouts() {
+        use filesystem::{FileSystem, HostDirectory};
+        use overlay::OverlayFileSystem;
+        use std::fs;
+        use vfs::{DirectoryEntry, EntryKind, Vfs};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let host = temp_dir.path().join("host");
+        fs::create_dir_all(host.join("etc")).unwrap();
+        fs::create_dir_all(host.join("shared")).unwrap();
+        fs::write(host.join("etc/config.txt"), "host").unwrap();
+        fs::write(host.join("etc/hosts"), "127.0.0.1").unwrap();
+        fs::write(host.join("shared/a.txt"), "a").unwrap();
+
+        let image_in = |name: &str| {
+            let dir = temp_dir.path().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            dir.join("vfs_disk.bin").to_str().unwrap().to_string()
+        };
+        let base_image = image_in("base");
+        let upper_image = image_in("upper");
+        let mut base = Vfs::open(&base_image).unwrap();
+        base.mkdir("/etc").unwrap();
+        base.create("/etc/config.txt", "rw-r--r--").unwrap();
+        base.write("/etc/config.txt", "image").unwrap();
+        base.mkdir("/docs").unwrap();
+        base.create("/docs/readme.txt", "rw-------").unwrap();
+        base.write("/docs/readme.txt", "base").unwrap();
+        base.sync().unwrap();
+
+        let upper = Vfs::open(&upper_image).unwrap();
+        let mut overlay =
+            OverlayFileSystem::new(upper, vec![Box::new(base), Box::new(HostDirectory::new(&host))]);
+        let names = |overlay: &OverlayFileSystem<Vfs>, path: &str| -> Vec<String> {
+            overlay.readdir(path).unwrap().into_iter().map(|entry| entry.name).collect()
+        };
+
+        // A primeira camada inferior encobre a segunda; diretórios se combinam
+        assert_eq!(overlay.read("/etc/config.txt").unwrap(), "image");
+        assert_eq!(names(&overlay, "/etc"), ["config.txt", "hosts"]);
+        assert_eq!(names(&overlay, "/"), ["docs", "etc", "shared"]);
+
+        // Copy-up: a escrita vai para cima, mantendo as permissões
+        overlay.write("/docs/readme.txt", "editado").unwrap();
+        assert_eq!(overlay.read("/docs/readme.txt").unwrap(), "editado");
+        assert_eq!(overlay.stat("/docs/readme.txt").unwrap().permissions, "rw-------");
+        assert_eq!(overlay.upper_mut().read("/docs/readme.txt").unwrap(), "editado");
+
+        // Remoções viram whiteouts na camada superior
+        overlay.remove("/shared/a.txt", false).unwrap();
+        assert!(names(&overlay, "/shared").is_empty());
+        assert_eq!(
+            overlay.upper().ls("/shared").unwrap(),
+            [DirectoryEntry { name: ".wh.a.txt".to_string(), kind: EntryKind::File }]
+        );
+        overlay.remove("/etc", true).unwrap();
+        overlay.mkdir("/etc").unwrap();
+        assert!(names(&overlay, "/etc").is_empty());
+        assert!(matches!(
+            overlay.create("/.wh.docs", "rw-r--r--"),
+            Err(error::DiscoError::InvalidPath(_))
+        ));
+
+        // Renomear um diretório de baixo copia tudo para cima e esconde a origem
+        overlay.rename("/docs", "/archive").unwrap();
+        assert_eq!(names(&overlay, "/"), ["archive", "etc", "shared"]);
+        assert_eq!(overlay.read("/archive/readme.txt").unwrap(), "editado");
+        assert_eq!(
+            overlay.read("/docs/readme.txt").unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        overlay.into_upper().sync().unwrap();
+
+        // As camadas inferiores continuam intactas e a superior persiste
+        let base = Vfs::open(&base_image).unwrap();
+        assert_eq!(base.read("/docs/readme.txt").unwrap(), "base");
+        assert_eq!(fs::read_to_string(host.join("shared/a.txt")).unwrap(), "a");
+        let upper = Vfs::open(&upper_image).unwrap();
+        let overlay =
+            OverlayFileSystem::new(upper, vec![Box::new(base), Box::new(HostDirectory::new(&host))]);
+        assert_eq!(names(&overlay, "/"), ["archive", "etc", "shared"]);
+        assert!(names(&overlay, "/etc").is_empty());
+    }
+
+    #[test]
+    fn test_mount_image_inside_another() {
+        use std::io::ErrorKind;
+        use vfs::Vfs;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image_in = |name: &str| {
+            let dir = temp_dir.path().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir.join("vfs_disk.bin").to_str().unwrap().to_string()
+        };
+        let primary = image_in("primary");
+        let data = image_in("data");
+        // Montada, a imagem usa o próprio diretório de estado
+        let data_state = vfs::mounted_state_dir(std::path::Path::new(&data));
+        std::fs::create_dir(&data_state).unwrap();
+        let mut vfs = Vfs::open_with_state_dir(&data, &data_state).unwrap();
+        vfs.mkdir("/sub").unwrap();
+        vfs.create("/x.txt", "rw-r--r--").unwrap();
+        vfs.write("/x.txt", "montado").unwrap();
+        vfs.sync().unwrap();
+        drop(vfs);
+
+        run_command(&primary, |vfs| {
+            vfs.mkdir("/mnt").unwrap();
+            vfs.create("/local.txt", "rw-r--r--").unwrap();
+            vfs.mount(&data, "/mnt").unwrap();
+            assert_eq!(vfs.read("/mnt/x.txt").unwrap(), "montado");
+
+            // Caminhos relativos e `..` atravessam o ponto de montagem
+            vfs.cd("/mnt/sub").unwrap();
+            assert_eq!(vfs.pwd().to_string(), "/mnt/sub");
+            vfs.create("y.txt", "rw-r--r--").unwrap();
+            vfs.cd("../..").unwrap();
+            assert_eq!(vfs.pwd().to_string(), "/");
+            assert_eq!(vfs.stat("mnt/sub/y.txt").unwrap().path.to_string(), "/mnt/sub/y.txt");
+
+            let kind = |result: std::io::Result<()>| result.unwrap_err().kind();
+            assert_eq!(kind(vfs.rename("/mnt/x.txt", "/x.txt")), ErrorKind::CrossesDevices);
+            assert_eq!(kind(vfs.rename("/local.txt", "/mnt")), ErrorKind::CrossesDevices);
+            assert_eq!(kind(vfs.rename("/mnt", "/elsewhere")), ErrorKind::ResourceBusy);
+            assert_eq!(kind(vfs.rm("/mnt", true).map(|_| ())), ErrorKind::ResourceBusy);
+            assert_eq!(kind(vfs.mount(&data, "/other")), ErrorKind::ResourceBusy);
+            assert_eq!(kind(vfs.mount(&primary, "/mnt/sub")), ErrorKind::ResourceBusy);
+            vfs.rename("/mnt/x.txt", "/mnt/sub").unwrap();
+
+            let error = vfs.rename("/mnt/sub/x.txt", "/").unwrap_err();
+            assert_eq!(cli::exit_code(&error), cli::EXIT_BUSY);
+            vfs.cd("/mnt/sub").unwrap();
+        });
+
+        // A tabela de montagens e o diretório atual voltam com a hierarquia
+        run_command(&primary, |vfs| {
+            assert_eq!(vfs.pwd().to_string(), "/mnt/sub");
+            let mounts = vfs.mounts();
+            assert_eq!(mounts.len(), 1);
+            assert_eq!(mounts[0].path.to_string(), "/mnt");
+            assert_eq!(vfs.read("x.txt").unwrap(), "montado");
+
+            vfs.umount("/mnt").unwrap();
+            assert_eq!(vfs.pwd().to_string(), "/mnt");
+            assert!(vfs.ls("/mnt").unwrap().is_empty());
+            assert!(vfs.mounts().is_empty());
+        });
+
+        let data = Vfs::open_with_state_dir(&data, &data_state).unwrap();
+        assert_eq!(data.read("/sub/x.txt").unwrap(), "montado");
+        assert!(data.stat("/sub/y.txt").is_ok());
+    }
+
+    #[test]
+    fn test_read_only_mode_never_writes() {
+        use std::io::ErrorKind;
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        // Sem imagem, o modo somente leitura não cria nada
+        let error = vfs::Vfs::open_read_only(image).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert!(!std::path::Path::new(image).exists());
+
+        run_command(image, |vfs| {
+            vfs.mkdir("docs").unwrap();
+            vfs.create("docs/a.txt", "rw-r--r--").unwrap();
+            vfs.write("docs/a.txt", "conteúdo").unwrap();
+        });
+        let files = [
+            std::path::PathBuf::from(image),
+            temp_dir.path().join(vfs::HIERARCHY_FILE),
+            temp_dir.path().join(vfs::CURRENT_DIRECTORY_FILE),
+        ];
+        let contents = || -> Vec<Vec<u8>> {
+            files.iter().map(|file| std::fs::read(file).unwrap()).collect()
+        };
+        let before = contents();
+
+        let mut vfs = vfs::Vfs::open_read_only(image).unwrap();
+        assert!(vfs.is_read_only());
+        assert_eq!(vfs.read("/docs/a.txt").unwrap(), "conteúdo");
+        assert_eq!(vfs.ls("/docs").unwrap().len(), 1);
+        assert!(vfs.stat("/docs/a.txt").is_ok());
+        vfs.cd("docs").unwrap();
+
+        let kind = |result: std::io::Result<()>| result.unwrap_err().kind();
+        assert_eq!(kind(vfs.write("a.txt", "outro")), ErrorKind::ReadOnlyFilesystem);
+        assert_eq!(kind(vfs.create("b.txt", "rw-r--r--")), ErrorKind::ReadOnlyFilesystem);
+        assert_eq!(kind(vfs.mkdir("/novo")), ErrorKind::ReadOnlyFilesystem);
+        assert_eq!(kind(vfs.rename("a.txt", "c.txt")), ErrorKind::ReadOnlyFilesystem);
+        assert_eq!(kind(vfs.rm("a.txt", false).map(|_| ())), ErrorKind::ReadOnlyFilesystem);
+        let error = vfs.sync().unwrap_err();
+        assert_eq!(cli::exit_code(&error), cli::EXIT_READ_ONLY);
+        drop(vfs);
+
+        // Pela CLI, o comando falha com o código de somente leitura e nada é gravado
+        let args = ["--read-only", "--image", image, "rm", "-r", "/docs"];
+        let parsed = cli::Cli::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert!(parsed.read_only);
+        let mut vfs = parsed.open().unwrap();
+        let cli::Command::Execute(command) = &parsed.command else {
+            panic!("expected a filesystem command");
+        };
+        let error = shell::execute(&mut vfs, command, shell::OutputFormat::Text).unwrap_err();
+        assert_eq!(cli::exit_code(&error), cli::EXIT_READ_ONLY);
+
+        assert_eq!(contents(), before);
+    }
+
+    #[test]
+    fn test_image_lock_between_openers() {
+        use std::io::ErrorKind;
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+        let kind = |result: std::io::Result<vfs::Vfs>| result.err().unwrap().kind();
+
+        // Quem escreve exclui todos; leitores só excluem quem escreve
+        let writer = vfs::Vfs::open(image).unwrap();
+        let error = vfs::Vfs::open(image).err().unwrap();
+        assert_eq!(cli::exit_code(&error), cli::EXIT_BUSY);
+        assert!(error.to_string().contains(&std::process::id().to_string()));
+        assert_eq!(kind(vfs::Vfs::open_read_only(image)), ErrorKind::ResourceBusy);
+        drop(writer);
+
+        let reader = vfs::Vfs::open_read_only(image).unwrap();
+        let other_reader = vfs::Vfs::open_read_only(image).unwrap();
+        assert_eq!(kind(vfs::Vfs::open(image)), ErrorKind::ResourceBusy);
+        drop((reader, other_reader));
+
+        // A trava de um processo em execução não é removida
+        let writer = vfs::Vfs::open(image).unwrap();
+        let error = lock::break_stale_lock(std::path::Path::new(image)).unwrap_err();
+        assert!(matches!(error, error::DiscoError::Busy(_)));
+        drop(writer);
+        assert!(!lock::break_stale_lock(std::path::Path::new(image)).unwrap());
+
+        // Uma trava em uso nunca é removida, mesmo sem PID (leitores não o gravam)
+        let lock_path = lock::lock_path(std::path::Path::new(image));
+        let args = ["--break-lock", "--image", image, "ls"];
+        let parsed = cli::Cli::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert!(parsed.break_lock);
+        let reader = vfs::Vfs::open_read_only(image).unwrap();
+        let error = lock::break_stale_lock(std::path::Path::new(image)).unwrap_err();
+        assert!(matches!(error, error::DiscoError::Busy(_)));
+        assert_eq!(kind(parsed.open()), ErrorKind::ResourceBusy);
+        assert!(lock_path.exists());
+        assert_eq!(kind(vfs::Vfs::open(image)), ErrorKind::ResourceBusy);
+        drop(reader);
+
+        // Livre, a trava só perde o PID deixado por um processo que já terminou
+        std::fs::write(&lock_path, u32::MAX.to_string()).unwrap();
+        assert!(lock::break_stale_lock(std::path::Path::new(image)).unwrap());
+        assert_eq!(std::fs::read_to_string(&lock_path).unwrap(), "");
+        let vfs = parsed.open().unwrap();
+        assert!(vfs.ls("/").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_shared_vfs_concurrent_stress() {
+        use shared::SharedVfs;
+        use std::thread;
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedVfs>();
+        assert_send_sync::<BlockManager>();
+
+        const WRITERS: usize = 4;
+        const READERS: usize = 4;
+        const ROUNDS: usize = 40;
+        // Mais de um bloco, para que uma leitura no meio de uma escrita apareça
+        const SHARED_SIZE: usize = 2 * block::BLOCK_SIZE + 100;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+        run_command(image, |vfs| {
+            vfs.mkdir("/dados").unwrap();
+            vfs.create("/dados/comum.txt", "rw-r--r--").unwrap();
+            vfs.write("/dados/comum.txt", &"a".repeat(SHARED_SIZE)).unwrap();
+            for writer in 0..WRITERS {
+                vfs.create(&format!("/dados/{}.txt", writer), "rw-r--r--").unwrap();
+            }
+        });
+        let shared = SharedVfs::new(vfs::Vfs::open(image).unwrap()).unwrap();
+
+        let contents = |writer: usize, round: usize| format!("{}:{};", writer, round).repeat(1000);
+        let mut threads = Vec::new();
+        for writer in 0..WRITERS {
+            let shared = shared.clone();
+            threads.push(thread::spawn(move || {
+                let path = format!("/dados/{}.txt", writer);
+                let scratch = format!("/dados/rascunho{}", writer);
+                for round in 0..ROUNDS {
+                    shared.write(&path, &contents(writer, round)).unwrap();
+                    assert_eq!(shared.read(&path).unwrap(), contents(writer, round));
+
+                    // Todos disputam o arquivo comum, sempre com uma letra só
+                    let letter = char::from(b'a' + ((writer + round) % 26) as u8);
+                    let data = letter.to_string().repeat(SHARED_SIZE);
+                    shared.write("/dados/comum.txt", &data).unwrap();
+
+                    // E alteram a hierarquia ao mesmo tempo
+                    shared.mkdir(&scratch).unwrap();
+                    let (x, y) = (format!("{}/x.txt", scratch), format!("{}/y.txt", scratch));
+                    shared.create(&x, "rw-r--r--").unwrap();
+                    shared.write(&x, "x").unwrap();
+                    shared.rename(&x, &y).unwrap();
+                    shared.rm(&scratch, true).unwrap();
+                }
+            }));
+        }
+        for _ in 0..READERS {
+            let shared = shared.clone();
+            threads.push(thread::spawn(move || {
+                for _ in 0..ROUNDS * 2 {
+                    let data = shared.read("/dados/comum.txt").unwrap();
+                    assert_eq!(data.len(), SHARED_SIZE);
+                    let first = data.chars().next().unwrap();
+                    assert!(data.chars().all(|c| c == first), "leitura no meio de uma escrita");
+                    assert!(shared.ls("/dados").unwrap().len() > WRITERS);
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Nenhum bloco perdido nem referência sobrando
+        shared.sync().unwrap();
+        let vfs = shared.into_inner().unwrap();
+        assert!(vfs.fsck().unwrap().problems.is_empty());
+        let blocks = |size: usize| size.div_ceil(block::BLOCK_SIZE);
+        assert_eq!(
+            vfs.df().used_blocks,
+            blocks(SHARED_SIZE) + WRITERS * blocks(contents(0, ROUNDS - 1).len())
+        );
+        drop(vfs);
+
+        let vfs = vfs::Vfs::open(image).unwrap();
+        for writer in 0..WRITERS {
+            let path = format!("/dados/{}.txt", writer);
+            assert_eq!(vfs.read(&path).unwrap(), contents(writer, ROUNDS - 1));
+        }
+        assert_eq!(vfs.ls("/dados").unwrap().len(), WRITERS + 1);
+    }
+
+    /// Executor mínimo para os testes: a thread dorme até o `Waker` acordá-la
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct ThreadWaker(std::thread::Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self: std::sync::Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(std::sync::Arc::new(ThreadWaker(std::thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_block_and_file_api() {
+        use nonblocking::AsyncBlockManager;
+
+        fn assert_send<T: Send>(_: &T) {}
+
+        let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
+        let disk_path = temp_disk.path().to_str().unwrap();
+        let block_manager = AsyncBlockManager::new(BlockManager::initialize(disk_path).unwrap());
+
+        let index = block_on(block_manager.allocate_block()).unwrap();
+        block_on(block_manager.write_block(index, b"bloco".to_vec())).unwrap();
+        assert_eq!(&block_on(block_manager.read_block(index)).unwrap()[..5], b"bloco");
+        block_on(block_manager.free_block(index)).unwrap();
+        assert_eq!(block_manager.blocking().block_ref_count(index).unwrap(), 0);
+
+        let mut tree = DirectoryTree::new();
+        let mut metadata_store = MetadataStore::new();
+        let root = tree.root();
+        create_file_in_directory("a.txt", &mut tree, root, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+
+        // Mais de um bloco, e uma segunda escrita que libera os blocos da primeira
+        let data = "0123456789".repeat(1000);
+        let write = nonblocking::write_to_file(
+            "a.txt",
+            &data,
+            &mut metadata_store,
+            &block_manager,
+            &tree,
+            root,
+        );
+        assert_send(&write);
+        block_on(write).unwrap();
+        let first_blocks = metadata_store
+            .get_file_metadata("/a.txt")
+            .unwrap()
+            .block_indices
+            .clone();
+        assert_eq!(first_blocks.len(), 3);
+        block_on(nonblocking::write_to_file(
+            "/a.txt",
+            &data[..5000],
+            &mut metadata_store,
+            &block_manager,
+            &tree,
+            root,
+        ))
+        .unwrap();
+        for block in first_blocks {
+            assert_eq!(block_manager.blocking().block_ref_count(block).unwrap(), 0);
+        }
+
+        // As duas APIs leem o mesmo conteúdo, inclusive em paralelo
+        let read = nonblocking::read_file("/a.txt", &metadata_store, &block_manager);
+        assert_send(&read);
+        assert_eq!(block_on(read).unwrap(), data[..5000]);
+        assert_eq!(
+            file::read_file("/a.txt", &metadata_store, block_manager.blocking()).unwrap(),
+            data[..5000]
+        );
+        std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        block_on(nonblocking::read_file(
+                            "/a.txt",
+                            &metadata_store,
+                            &block_manager,
+                        ))
+                    })
+                })
+                .collect();
+            for reader in readers {
+                assert_eq!(reader.join().unwrap().unwrap().len(), 5000);
+            }
+        });
+        let missing = block_on(nonblocking::read_file("/b.txt", &metadata_store, &block_manager));
+        assert!(matches!(missing, Err(error::DiscoError::NotFound(_))));
+
+        // Com uma política de versões, a escrita assíncrona guarda o conteúdo anterior
+        let policy = versioning::RetentionPolicy {
+            max_versions: Some(1),
+            max_age: None,
+        };
+        let path = VfsPath::parse("/a.txt").unwrap();
+        let write_versioned = |data: &str, metadata_store: &mut MetadataStore| {
+            block_on(nonblocking::write_file_contents(
+                &path,
+                data,
+                metadata_store,
+                &block_manager,
+                Some(&policy),
+            ))
+            .unwrap()
+        };
+        let previous = metadata_store.get_file_metadata("/a.txt").unwrap().block_indices.clone();
+        write_versioned("novo", &mut metadata_store);
+        let metadata = metadata_store.get_file_metadata("/a.txt").unwrap();
+        assert_eq!(metadata.versions.len(), 1);
+        assert_eq!(metadata.versions[0].block_indices, previous);
+        for &block in &previous {
+            assert_eq!(block_manager.blocking().block_ref_count(block).unwrap(), 1);
+        }
+        // A versão que a política deixa de reter tem os blocos liberados
+        write_versioned("mais novo", &mut metadata_store);
+        let metadata = metadata_store.get_file_metadata("/a.txt").unwrap();
+        assert_eq!(metadata.versions.len(), 1);
+        assert_eq!(metadata.versions[0].number, 2);
+        for block in previous {
+            assert_eq!(block_manager.blocking().block_ref_count(block).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_block_cache_write_back_and_read_ahead() {
+        use block::{BLOCK_SIZE, TOTAL_BLOCKS};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+        let on_disk = |index: usize| {
+            let offset = 4 + TOTAL_BLOCKS + index * BLOCK_SIZE;
+            std::fs::read(image).unwrap()[offset..offset + 5].to_vec()
+        };
+
+        // As escritas ficam no cache até o `sync` ou até sobrarem na capacidade
+        let block_manager = BlockManager::initialize(image).unwrap();
+        block_manager.set_cache_capacity(4).unwrap();
+        for index in 0..6 {
+            assert_eq!(block_manager.allocate_block().unwrap(), index);
+            let data = format!("b{:04}", index);
+            block_manager.write_block(index, data.as_bytes()).unwrap();
+        }
+        let stats = block_manager.cache_stats();
+        assert_eq!((stats.cached, stats.dirty), (4, 4));
+        assert_eq!(on_disk(0), b"b0000");
+        assert_eq!(on_disk(5), [0; 5]);
+        assert_eq!(&block_manager.read_block(5).unwrap()[..5], b"b0005");
+        block_manager.sync().unwrap();
+        assert_eq!(block_manager.cache_stats().dirty, 0);
+        assert_eq!(on_disk(5), b"b0005");
+
+        // Um bloco liberado sai do cache sem ser gravado; o descarte grava o resto
+        block_manager.write_block(5, b"outro").unwrap();
+        block_manager.free_block(5).unwrap();
+        block_manager.write_block(4, b"final").unwrap();
+        drop(block_manager);
+        assert_eq!(on_disk(5), b"b0005");
+        assert_eq!(on_disk(4), b"final");
+
+        // Sem cache, cada escrita vai direto para a imagem
+        let block_manager = BlockManager::initialize(image).unwrap();
+        block_manager.set_cache_capacity(0).unwrap();
+        block_manager.write_block(3, b"agora").unwrap();
+        assert_eq!(on_disk(3), b"agora");
+        assert_eq!(block_manager.cache_stats().cached, 0);
+        drop(block_manager);
+        std::fs::remove_file(image).unwrap();
+
+        // Leitura sequencial: o primeiro bloco de cada janela é uma falta, o resto
+        // já foi lido adiante
+        run_command(image, |vfs| {
+            vfs.create("grande.txt", "rw-r--r--").unwrap();
+            vfs.write("grande.txt", &"x".repeat(10 * BLOCK_SIZE)).unwrap();
+        });
+        let vfs = vfs::Vfs::open(image).unwrap();
+        assert_eq!(vfs.read("grande.txt").unwrap().len(), 10 * BLOCK_SIZE);
+        let stats = vfs.cache_stats();
+        assert_eq!((stats.misses, stats.hits, stats.read_ahead), (2, 8, 8));
+        vfs.read("grande.txt").unwrap();
+        assert_eq!(vfs.cache_stats().hits, 18);
+    }
+
+    #[test]
+    fn test_snapshots_keep_blocks_until_deleted() {
+        use std::io::ErrorKind;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        run_command(image, |vfs| {
+            vfs.mkdir("/docs").unwrap();
+            vfs.create("/docs/a.txt", "rw-r--r--").unwrap();
+            vfs.write("/docs/a.txt", "versão 1").unwrap();
+            vfs.snapshot_create("antes").unwrap();
+            let kind = |result: std::io::Result<()>| result.unwrap_err().kind();
+            assert_eq!(kind(vfs.snapshot_create("antes")), ErrorKind::AlreadyExists);
+            assert_eq!(kind(vfs.snapshot_delete("outra")), ErrorKind::NotFound);
+
+            // A escrita vai para um bloco novo; o antigo continua com a snapshot
+            vfs.write("/docs/a.txt", "versão 2").unwrap();
+            vfs.create("/b.txt", "rw-r--r--").unwrap();
+            vfs.write("/b.txt", "novo").unwrap();
+            assert_eq!(vfs.df().used_blocks, 3);
+            let snapshots = vfs.snapshots();
+            assert_eq!(snapshots.len(), 1);
+            assert_eq!((snapshots[0].files, snapshots[0].exclusive_blocks), (1, 1));
+            assert!(vfs.fsck().unwrap().is_clean());
+            vfs.cd("/docs").unwrap();
+        });
+
+        // A snapshot é gravada no diretório de estado e volta com a imagem
+        run_command(image, |vfs| {
+            vfs.snapshot_rollback("antes").unwrap();
+            assert_eq!(vfs.pwd().to_string(), "/docs");
+            assert_eq!(vfs.read("a.txt").unwrap(), "versão 1");
+            assert!(vfs.stat("/b.txt").is_err());
+            assert_eq!(vfs.df().used_blocks, 1);
+            assert!(vfs.fsck().unwrap().is_clean());
+
+            // Depois do rollback a snapshot continua valendo
+            vfs.write("a.txt", "versão 3").unwrap();
+            vfs.snapshot_rollback("antes").unwrap();
+            assert_eq!(vfs.read("a.txt").unwrap(), "versão 1");
+
+            // Apagada a snapshot, só as referências do sistema de arquivos ficam
+            vfs.write("a.txt", "versão 4").unwrap();
+            vfs.snapshot_delete("antes").unwrap();
+            assert!(vfs.snapshots().is_empty());
+            assert_eq!(vfs.df().used_blocks, 1);
+            assert!(vfs.fsck().unwrap().is_clean());
+        });
+    }
+
+    #[test]
+    fn test_file_versions_with_retention() {
+        use versioning::{expire_versions, parse_max_age, RetentionPolicy};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        run_command(image, |vfs| {
+            vfs.create("a.txt", "rw-r--r--").unwrap();
+            vfs.write("a.txt", "sem versão").unwrap();
+            vfs.write("a.txt", "v1").unwrap();
+            assert!(vfs.versions("a.txt").unwrap().is_empty());
+
+            let policy = RetentionPolicy {
+                max_versions: Some(2),
+                max_age: Some(parse_max_age("30d").unwrap()),
+            };
+            vfs.set_versioning(Some(policy)).unwrap();
+            for data in ["v2", "v3", "v4"] {
+                vfs.write("a.txt", data).unwrap();
+            }
+        });
+
+        // A política e as versões voltam com o estado; só as duas últimas ficam
+        run_command(image, |vfs| {
+            let versions = vfs.versions("a.txt").unwrap();
+            let numbers: Vec<u32> = versions.iter().map(|v| v.number).collect();
+            assert_eq!(numbers, [2, 3]);
+            assert_eq!(vfs.read_version("a.txt", 2).unwrap(), "v2");
+            assert!(vfs.read_version("a.txt", 1).is_err());
+            assert_eq!(vfs.df().used_blocks, 3);
+
+            // Restaurar é uma escrita: o conteúdo atual vira a versão 4
+            vfs.restore_version("a.txt", 2).unwrap();
+            assert_eq!(vfs.read("a.txt").unwrap(), "v2");
+            assert_eq!(vfs.read_version("a.txt", 4).unwrap(), "v4");
+            let numbers: Vec<u32> = vfs.versions("a.txt").unwrap().iter().map(|v| v.number).collect();
+            assert_eq!(numbers, [3, 4]);
+            assert_eq!(vfs.df().used_blocks, 3);
+            assert!(vfs.fsck().unwrap().is_clean());
+
+            // Com o tempo, as versões passam da idade máxima
+            let mut metadata = vfs.metadata_store().get_file_metadata("/a.txt").unwrap().clone();
+            let later = chrono::Utc::now() + chrono::Duration::days(31);
+            let policy = vfs.versioning().unwrap();
+            assert_eq!(expire_versions(&mut metadata, &policy, later).len(), 2);
+            assert!(metadata.versions.is_empty());
+
+            vfs.rm("a.txt", false).unwrap();
+            assert_eq!(vfs.df().used_blocks, 0);
+            assert!(vfs.fsck().unwrap().is_clean());
+        });
+    }
+
+    #[test]
+    fn test_shell_syncs_when_history_or_input_fails() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        // Um diretório no lugar do arquivo de histórico faz toda gravação falhar
+        let history_path = temp_dir.path().join("history_dir");
+        std::fs::create_dir(&history_path).unwrap();
+        let mut history = shell::History::load(history_path.to_str().unwrap());
+        let mut vfs = vfs::Vfs::open(image).unwrap();
+        let input: &[u8] = b"mkdir docs\ncreate docs/a.txt rw-r--r--\n\xff\nmkdir depois\n";
+        let result = shell::run_shell(&mut vfs, input, &mut history);
+
+        // A linha inválida encerra o shell, mas o que foi feito antes é gravado
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(history.entries().len(), 2);
+        drop(vfs);
+        let reopened = vfs::Vfs::open(image).unwrap();
+        assert!(reopened.stat("/docs/a.txt").is_ok());
+        assert!(reopened.stat("/depois").is_err());
+    }
+
+    #[test]
+    fn test_version_numbers_survive_expiration() {
+        use versioning::{expire_versions, RetentionPolicy};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        run_command(image, |vfs| {
+            let policy = RetentionPolicy {
+                max_versions: None,
+                max_age: Some(60),
+            };
+            vfs.set_versioning(Some(policy)).unwrap();
+            vfs.create("a.txt", "rw-r--r--").unwrap();
+            for data in ["v1", "v2", "v3"] {
+                vfs.write("a.txt", data).unwrap();
+            }
+
+            // Todas as versões passam da idade máxima
+            let later = chrono::Utc::now() + chrono::Duration::minutes(2);
+            let mut metadata = vfs.metadata_store().get_file_metadata("/a.txt").unwrap().clone();
+            let expired = expire_versions(&mut metadata, &policy, later);
+            assert!(metadata.versions.is_empty());
+            vfs.metadata_store_mut().update_file_metadata("/a.txt", metadata);
+            for block in expired {
+                vfs.block_manager().free_block(block).unwrap();
+            }
+        });
+
+        // A numeração continua de onde parou, também depois de reabrir a imagem
+        run_command(image, |vfs| {
+            vfs.write("a.txt", "v4").unwrap();
+            let numbers: Vec<u32> = vfs.versions("a.txt").unwrap().iter().map(|v| v.number).collect();
+            assert_eq!(numbers, [4]);
+            assert_eq!(vfs.read_version("a.txt", 4).unwrap(), "v3");
+            assert!(vfs.read_version("a.txt", 1).is_err());
+            assert!(vfs.fsck().unwrap().is_clean());
+        });
+    }
+
+    #[test]
+    fn test_failed_write_releases_new_blocks() {
+        use block::{BLOCK_SIZE, TOTAL_BLOCKS};
+        use std::io::ErrorKind;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        run_command(image, |vfs| {
+            vfs.create("a.txt", "rw-r--r--").unwrap();
+            vfs.write("a.txt", "antes").unwrap();
+            let before = vfs.df();
+            assert_eq!(before.used_blocks, 1);
+
+            // Falta um bloco: os já alocados voltam a ficar livres
+            let data = "x".repeat(TOTAL_BLOCKS * BLOCK_SIZE);
+            let error = vfs.write("a.txt", &data).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::StorageFull);
+            assert_eq!(vfs.df(), before);
+            assert_eq!(vfs.read("a.txt").unwrap(), "antes");
+            assert!(vfs.fsck().unwrap().is_clean());
+        });
+    }
+
+    #[test]
+    fn test_remove_file_from_directory_frees_blocks() {
+        let temp_disk = assert_fs::NamedTempFile::new("test_disk.bin").unwrap();
+        let block_manager = BlockManager::initialize(temp_disk.path().to_str().unwrap()).unwrap();
+        let mut metadata_store = MetadataStore::new();
+        let mut tree = DirectoryTree::new();
+        let root = tree.root();
+
+        create_file_in_directory("a.txt", &mut tree, root, &mut metadata_store, "rw-r--r--")
+            .unwrap();
+        let data = "x".repeat(2 * block::BLOCK_SIZE);
+        write_to_file("/a.txt", &data, &mut metadata_store, &block_manager, &tree, root).unwrap();
+        assert_eq!(block_manager.block_refs().iter().filter(|&&refs| refs > 0).count(), 2);
+
+        file::remove_file_from_directory("a.txt", &mut tree, root, &mut metadata_store, &block_manager)
+            .unwrap();
+        assert!(metadata_store.is_empty());
+        assert!(block_manager.block_refs().iter().all(|&refs| refs == 0));
+    }
+
+    #[test]
+    fn test_migration_reclaims_blocks_of_discarded_copies() {
+        use block::TOTAL_BLOCKS;
+        use serde_json::json;
+        use vfs::{Vfs, HIERARCHY_FILE};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        // Imagem antiga: a cópia da árvore aponta para um bloco que o índice não usa
+        let block_manager = BlockManager::initialize(image).unwrap();
+        let discarded_block = block_manager.allocate_block().unwrap();
+        let kept_block = block_manager.allocate_block().unwrap();
+        drop(block_manager);
+
+        let file = |size: u64, modified_at: &str, block: usize| FileMetadata {
+            path: "/a.txt".to_string(),
+            permissions: "rw-r--r--".to_string(),
+            created_at: "2024-11-29T12:00:00Z".to_string(),
+            modified_at: modified_at.to_string(),
+            size,
+            block_indices: vec![block],
+            archive: None,
+            versions: Vec::new(),
+            next_version: 0,
+        };
+        let old_format = json!([
+            {
+                "root": 0,
+                "next_id": 1,
+                "directories": {
+                    "0": {
+                        "name": "/", "created_at": "", "modified_at": "", "parent": null,
+                        "files": { "a.txt": file(3, "2024-11-29T12:00:00Z", discarded_block) },
+                        "subdirectories": {},
+                    },
+                },
+            },
+            { "files": { "/a.txt": file(7, "2024-11-30T12:00:00Z", kept_block) } },
+        ]);
+        std::fs::write(temp_dir.path().join(HIERARCHY_FILE), old_format.to_string()).unwrap();
+
+        let (_, _, report) = migration::migrate_hierarchy(&old_format.to_string()).unwrap();
+        assert_eq!(report.orphaned_blocks(), vec![discarded_block]);
+
+        // Aberto para leitura, o mapa de blocos não é tocado
+        let vfs = Vfs::open_read_only(image).unwrap();
+        assert_eq!(vfs.df().used_blocks, 2);
+        drop(vfs);
+
+        let vfs = Vfs::open(image).unwrap();
+        let usage = vfs.df();
+        assert_eq!(usage.used_blocks, 1);
+        assert_eq!(usage.free_blocks, TOTAL_BLOCKS - 1);
+        assert_eq!(vfs.read("/a.txt").unwrap().len(), 7);
+        assert!(vfs.fsck().unwrap().is_clean());
+        vfs.sync().unwrap();
+        drop(vfs);
+
+        // Já no formato atual, a reabertura não libera mais nada
+        let vfs = Vfs::open(image).unwrap();
+        assert_eq!(vfs.df().used_blocks, 1);
+    }
+
+    #[test]
+    fn test_json_output_has_one_envelope_per_command() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let mut vfs = vfs::Vfs::open(image.to_str().unwrap()).unwrap();
+        let mut run = |command: &[&str]| {
+            let args: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+            let (json, result) = shell::execute_json(&mut vfs, &args);
+            // O objeto é o que a CLI imprime: uma linha de JSON válido
+            let json: serde_json::Value =
+                serde_json::from_str(&json.unwrap().to_string()).unwrap();
+            assert_eq!(json["command"], command[0]);
+            (json, result)
+        };
+
+        // Mutação: sucesso sem resultado
+        let (json, result) = run(&["mkdir", "docs"]);
+        assert!(result.is_ok());
+        assert_eq!(json, serde_json::json!({ "ok": true, "command": "mkdir", "result": null }));
+        run(&["create", "docs/a.txt", "rw-r--r--"]).1.unwrap();
+
+        // Consulta: o resultado vem dentro do envelope
+        let (json, result) = run(&["ls", "docs"]);
+        assert!(result.is_ok());
+        assert_eq!(json["ok"], true);
+        assert_eq!(json["result"]["path"], "/docs");
+        assert_eq!(json["result"]["entries"][0]["name"], "a.txt");
+        let (json, _) = run(&["cd", "docs"]);
+        assert_eq!(json["result"]["path"], "/docs");
+
+        // Erro: `ok` falso e o tipo do erro
+        let (json, result) = run(&["read", "/missing.txt"]);
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["error"]["kind"], "NotFound");
+        assert!(json.get("result").is_none());
+        let (json, _) = run(&["bogus"]);
+        assert_eq!(json["error"]["kind"], "InvalidInput");
+
+        // Problemas do fsck são um resultado, mas a execução falha para o código de saída
+        run(&["write", "a.txt", "olá"]).1.unwrap();
+        vfs.sync().unwrap();
+        drop(vfs);
+        let block_manager = BlockManager::initialize(image.to_str().unwrap()).unwrap();
+        block_manager.free_block(0).unwrap();
+        drop(block_manager);
+        let mut vfs = vfs::Vfs::open(image.to_str().unwrap()).unwrap();
+        let (json, result) = shell::execute_json(&mut vfs, &["fsck".to_string()]);
+        let json = json.unwrap();
+        assert_eq!(json["ok"], true);
+        assert_eq!(json["result"]["problems"].as_array().unwrap().len(), 1);
+        assert_eq!(cli::exit_code(&result.unwrap_err()), cli::EXIT_CORRUPT);
+    }
+
+    #[test]
+    fn test_tar_and_vfs_errors_keep_their_variant() {
+        use assert_fs::prelude::*;
+        use error::DiscoError;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let archive = temp_dir.child("arquivo.tar");
+        archive
+            .write_binary(&build_tar(&[("a.txt", Some(b"a"))]))
+            .unwrap();
+        let archive = archive.path().to_str().unwrap();
+
+        let mut tree = DirectoryTree::new();
+        let mut metadata_store = MetadataStore::new();
+        let root = tree.root();
+        tar::mount_tar(archive, "mnt", &mut tree, root, &mut metadata_store).unwrap();
+        assert!(matches!(
+            tar::mount_tar(archive, "mnt", &mut tree, root, &mut metadata_store),
+            Err(DiscoError::AlreadyExists(_))
+        ));
+
+        // Um cabeçalho adulterado é corrupção, não um erro genérico de E/S
+        let mut data = build_tar(&[("a.txt", Some(b"a"))]);
+        data[0] = b'b';
+        let damaged = temp_dir.child("danificado.tar");
+        damaged.write_binary(&data).unwrap();
+        assert!(matches!(
+            tar::read_tar_index(damaged.path().to_str().unwrap()),
+            Err(DiscoError::Corrupt { .. })
+        ));
+
+        // Os erros da fachada também guardam o caso original
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let mut vfs = vfs::Vfs::open(image.to_str().unwrap()).unwrap();
+        let error = vfs.cd("/missing").unwrap_err();
+        assert!(matches!(DiscoError::from_io(&error), Some(DiscoError::NotFound(_))));
+        let error = vfs.umount("/").unwrap_err();
+        assert!(matches!(
+            DiscoError::from_io(&error),
+            Some(DiscoError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_mount_two_images_from_one_directory() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let images = temp_dir.path().join("imagens");
+        std::fs::create_dir(&images).unwrap();
+        let image = |name: &str| images.join(name).to_str().unwrap().to_string();
+        let (first, second) = (image("a.bin"), image("b.bin"));
+        for image in [&first, &second] {
+            drop(BlockManager::initialize(image).unwrap());
+        }
+        let primary = temp_dir.path().join("vfs_disk.bin");
+        let primary = primary.to_str().unwrap();
+
+        run_command(primary, |vfs| {
+            vfs.mkdir("/a").unwrap();
+            vfs.mkdir("/b").unwrap();
+            vfs.mount(&first, "/a").unwrap();
+            vfs.mount(&second, "/b").unwrap();
+            vfs.create("/a/x.txt", "rw-r--r--").unwrap();
+            vfs.write("/a/x.txt", "primeira").unwrap();
+            vfs.mkdir("/b/sub").unwrap();
+        });
+
+        // Cada imagem guarda o estado no seu diretório, sem misturar as árvores
+        let first_state = vfs::mounted_state_dir(std::path::Path::new(&first));
+        let second_state = vfs::mounted_state_dir(std::path::Path::new(&second));
+        assert!(first_state.join(vfs::HIERARCHY_FILE).exists());
+        assert!(second_state.join(vfs::HIERARCHY_FILE).exists());
+        assert!(!images.join(vfs::HIERARCHY_FILE).exists());
+
+        run_command(primary, |vfs| {
+            assert_eq!(vfs.read("/a/x.txt").unwrap(), "primeira");
+            assert!(vfs.stat("/b/x.txt").is_err());
+            assert!(vfs.stat("/a/sub").is_err());
+            assert_eq!(vfs.ls("/b").unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_copy_refuses_subtree_with_mount_point() {
+        use std::io::ErrorKind;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let data = temp_dir.path().join("dados.bin");
+        let data = data.to_str().unwrap();
+        drop(BlockManager::initialize(data).unwrap());
+        let primary = temp_dir.path().join("vfs_disk.bin");
+
+        run_command(primary.to_str().unwrap(), |vfs| {
+            vfs.mkdir("/projeto").unwrap();
+            vfs.mkdir("/projeto/mnt").unwrap();
+            vfs.create("/projeto/a.txt", "rw-r--r--").unwrap();
+            vfs.mount(data, "/projeto/mnt").unwrap();
+            vfs.create("/projeto/mnt/b.txt", "rw-r--r--").unwrap();
+
+            let error = vfs.copy("/projeto", "/copia", true, false).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ResourceBusy);
+            assert!(vfs.stat("/copia").is_err());
+
+            // Fora da montagem, a cópia segue normal
+            vfs.copy("/projeto/a.txt", "/a.txt", false, false).unwrap();
+            vfs.umount("/projeto/mnt").unwrap();
+            vfs.copy("/projeto", "/copia", true, false).unwrap();
+            assert!(vfs.stat("/copia/mnt").is_ok());
+        });
+    }
+
+    #[test]
+    fn test_migration_normalizes_legacy_store_keys() {
+        use serde_json::json;
+        use vfs::{Vfs, HIERARCHY_FILE};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let image = image.to_str().unwrap();
+
+        let block_manager = BlockManager::initialize(image).unwrap();
+        let written = block_manager.allocate_block().unwrap();
+        block_manager.write_block(written, b"Hello, VFS!").unwrap();
+        let docs_block = block_manager.allocate_block().unwrap();
+        block_manager.write_block(docs_block, b"abc").unwrap();
+        let stale_block = block_manager.allocate_block().unwrap();
+        drop(block_manager);
+
+        let file = |path: &str, size: u64, modified_at: &str, blocks: Vec<usize>| FileMetadata {
+            path: path.to_string(),
+            permissions: "rw-r--r--".to_string(),
+            created_at: "2024-11-29T12:00:00Z".to_string(),
+            modified_at: modified_at.to_string(),
+            size,
+            block_indices: blocks,
+            archive: None,
+            versions: Vec::new(),
+            next_version: 0,
+        };
+        // Como o código antigo gravava: a escrita usava o argumento cru como chave
+        let created = file("/test_file", 0, "2024-11-29T12:00:00Z", vec![]);
+        let old_docs = file("/docs/a.txt", 1, "2024-11-29T12:00:00Z", vec![stale_block]);
+        let docs = file("docs/a.txt", 3, "2024-11-30T12:00:00Z", vec![docs_block]);
+        let legacy = json!([
+            {
+                "created_at": "", "modified_at": "",
+                "files": { "test_file": created },
+                "subdirectories": {
+                    "docs": { "created_at": "", "modified_at": "", "files": { "a.txt": docs } },
+                },
+            },
+            {
+                "files": {
+                    "/test_file": created,
+                    "test_file": file("test_file", 11, "2024-11-30T12:00:00Z", vec![written]),
+                    "/docs/a.txt": old_docs,
+                    "docs/a.txt": docs,
+                },
+            },
+        ]);
+
+        let (_, metadata_store, report) =
+            migration::migrate_hierarchy(&legacy.to_string()).unwrap();
+        let mut keys: Vec<&str> = metadata_store.paths().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["/docs/a.txt", "/test_file"]);
+        let test_file = metadata_store.get_file_metadata("/test_file").unwrap();
+        assert_eq!((test_file.path.as_str(), test_file.size), ("/test_file", 11));
+        // As duas colisões de chaves, mais a cópia vazia que a árvore guardava de `test_file`
+        assert_eq!(report.conflicts.len(), 3);
+        assert_eq!(report.orphaned_blocks(), vec![stale_block]);
+
+        // Aberta, a imagem lê o conteúdo gravado e devolve o bloco da cópia perdedora
+        std::fs::write(temp_dir.path().join(HIERARCHY_FILE), legacy.to_string()).unwrap();
+        let vfs = Vfs::open(image).unwrap();
+        assert_eq!(vfs.read("/test_file").unwrap(), "Hello, VFS!");
+        assert_eq!(vfs.read("/docs/a.txt").unwrap(), "abc");
+        assert_eq!(vfs.df().used_blocks, 2);
+        assert!(vfs.fsck().unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_tar_rejects_malformed_archives() {
+        use assert_fs::prelude::*;
+        use error::DiscoError;
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let write_archive = |name: &str, data: &[u8]| {
+            let archive = temp_dir.child(name);
+            archive.write_binary(data).unwrap();
+            archive.path().to_str().unwrap().to_string()
+        };
+
+        // Tamanho em base 256 perto de `u64::MAX`: a soma com a posição estouraria
+        let mut data = build_tar(&[("a.txt", Some(b"a"))]);
+        data[124] = 0x80;
+        data[125..136].fill(0xff);
+        data[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = data[..512].iter().map(|&b| u32::from(b)).sum();
+        data[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+        let huge = write_archive("enorme.tar", &data);
+        let error = tar::read_tar_index(&huge).unwrap_err();
+        assert!(matches!(error, DiscoError::Corrupt { .. }));
+        assert!(error.to_string().contains("exceeds archive length"));
+
+        // Um arquivo e um diretório com o mesmo caminho não são montados
+        let mut tree = DirectoryTree::new();
+        let mut metadata_store = MetadataStore::new();
+        let root = tree.root();
+        for entries in [
+            &[("a", Some(&b"a"[..])), ("a/", None)][..],
+            &[("a", Some(&b"a"[..])), ("a/b.txt", Some(&b"b"[..]))][..],
+        ] {
+            let archive = write_archive("ambiguo.tar", &build_tar(entries));
+            assert!(matches!(
+                tar::mount_tar(&archive, "mnt", &mut tree, root, &mut metadata_store),
+                Err(DiscoError::Corrupt { .. })
+            ));
+            assert!(lookup(&tree, "/mnt").is_none());
+        }
+        assert_eq!(metadata_store.len(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_host_directory_ignores_symlinks() {
+        use filesystem::{FileSystem, HostDirectory};
+        use overlay::OverlayFileSystem;
+        use std::{fs, io::ErrorKind, os::unix::fs::symlink};
+        use vfs::{DirectoryEntry, EntryKind, Vfs};
+
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let outside = temp_dir.path().join("outside");
+        let host = temp_dir.path().join("host");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&host).unwrap();
+        fs::write(outside.join("secret"), "segredo").unwrap();
+        fs::write(host.join("a.txt"), "a").unwrap();
+        symlink(outside.join("secret"), host.join("link_file")).unwrap();
+        symlink(&outside, host.join("link_dir")).unwrap();
+
+        let lower = HostDirectory::new(&host);
+        assert_eq!(
+            lower.readdir("/").unwrap(),
+            vec![DirectoryEntry {
+                name: "a.txt".to_string(),
+                kind: EntryKind::File,
+            }]
+        );
+        assert_eq!(lower.stat("/").unwrap().size, 1);
+        for path in ["/link_file", "/link_dir/secret"] {
+            assert_eq!(lower.read(path).unwrap_err().kind(), ErrorKind::NotFound);
+            assert_eq!(lower.stat(path).unwrap_err().kind(), ErrorKind::NotFound);
+        }
+        assert_eq!(
+            lower.readdir("/link_dir").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        // Nem a cópia para a camada superior alcança o arquivo de fora
+        let image = temp_dir.path().join("vfs_disk.bin");
+        let upper = Vfs::open(image.to_str().unwrap()).unwrap();
+        let mut overlay = OverlayFileSystem::new(upper, vec![Box::new(lower)]);
+        assert_eq!(
+            overlay.write("/link_file", "x").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(overlay.read("/a.txt").unwrap(), "a");
+    }
 }
@@ -0,0 +1,172 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use log::{debug, warn};
+
+use crate::error::{DiscoError, Result};
+
+/// Extensão do arquivo de trava, criado ao lado da imagem
+pub const LOCK_EXTENSION: &str = "lock";
+
+/// Tipo de trava sobre a imagem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Um único processo, que pode alterar a imagem
+    Exclusive,
+    /// Qualquer número de processos que só leem a imagem
+    Shared,
+}
+
+/// Trava consultiva sobre uma imagem, liberada ao ser descartada
+///
+/// A trava é do sistema operacional (`flock`) sobre `<imagem>.lock`, então é
+/// liberada também quando o processo termina sem descartá-la. Quem tem a trava
+/// exclusiva grava o seu PID no arquivo, para a mensagem de erro dos demais e
+/// para `break_stale_lock`.
+#[derive(Debug)]
+pub struct ImageLock {
+    file: Option<File>, // `None`: somente leitura, sem como criar o arquivo de trava
+    mode: LockMode,
+}
+
+/// Caminho do arquivo de trava de uma imagem
+pub fn lock_path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".");
+    path.push(LOCK_EXTENSION);
+    PathBuf::from(path)
+}
+
+impl ImageLock {
+    /// Trava a imagem, falhando com `DiscoError::Busy` se outro processo a usa
+    pub fn acquire(image: &Path, mode: LockMode) -> Result<Self> {
+        let path = lock_path(image);
+        let file = match open_lock_file(&path, mode) {
+            Ok(file) => file,
+            Err(e) if mode == LockMode::Shared && is_permission_error(&e) => {
+                // Numa mídia somente leitura ninguém altera a imagem
+                warn!(
+                    "Sem trava para '{}': {}; a imagem é lida sem trava",
+                    image.display(),
+                    e
+                );
+                return Ok(ImageLock { file: None, mode });
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let locked = match mode {
+            LockMode::Exclusive => file.try_lock(),
+            LockMode::Shared => file.try_lock_shared(),
+        };
+        match locked {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(in_use_error(image, &file)),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        if mode == LockMode::Exclusive {
+            let mut file = &file;
+            file.set_len(0)?;
+            write!(file, "{}", process::id())?;
+        }
+        debug!("Imagem '{}' travada ({:?})", image.display(), mode);
+        Ok(ImageLock {
+            file: Some(file),
+            mode,
+        })
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+impl Drop for ImageLock {
+    fn drop(&mut self) {
+        // O arquivo fica, vazio: apagá-lo deixaria outro processo travando um
+        // arquivo que não existe mais
+        if let (Some(file), LockMode::Exclusive) = (&self.file, self.mode) {
+            let _ = file.set_len(0);
+        }
+    }
+}
+
+/// Limpa o PID deixado na trava por um processo que não existe mais
+///
+/// Como a trava é liberada quando o processo termina, uma trava ocupada é
+/// sempre de um processo vivo: nesse caso a função falha com `DiscoError::Busy`
+/// e o arquivo não é tocado. Retorna `true` se havia um PID a limpar.
+pub fn break_stale_lock(image: &Path) -> Result<bool> {
+    let path = lock_path(image);
+    let file = match OpenOptions::new().read(true).write(true).open(&path) {
+        Ok(file) => file,
+        // Sem o arquivo, ou sem poder alterá-lo, não há o que limpar
+        Err(e) if e.kind() == io::ErrorKind::NotFound || is_permission_error(&e) => {
+            return Ok(false)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Err(in_use_error(image, &file)),
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    let Some(pid) = holder(&file) else {
+        return Ok(false);
+    };
+    file.set_len(0)?;
+    warn!(
+        "PID {} deixado na trava de '{}' removido",
+        pid,
+        image.display()
+    );
+    Ok(true)
+}
+
+fn open_lock_file(path: &Path, mode: LockMode) -> io::Result<File> {
+    let created = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path);
+    match created {
+        // A trava compartilhada não grava nada, então basta poder ler o arquivo
+        Err(e) if mode == LockMode::Shared && is_permission_error(&e) => {
+            File::open(path).map_err(|_| e)
+        }
+        result => result,
+    }
+}
+
+fn is_permission_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+    )
+}
+
+/// PID gravado por quem tem a trava exclusiva
+fn holder(mut file: &File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+fn in_use_error(image: &Path, file: &File) -> DiscoError {
+    let message = match holder(file) {
+        Some(pid) => format!(
+            "Image '{}' is in use by another process (pid {})",
+            image.display(),
+            pid
+        ),
+        None => format!("Image '{}' is in use by another process", image.display()),
+    };
+    DiscoError::Busy(message)
+}
@@ -1,163 +1,112 @@
-use chrono::Utc;
-use std::collections::HashMap;
 use std::env;
-use std::io;
-use std::path::Path;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process;
 
-use disco::block::{BlockManager, MetadataStore};
-use disco::directory::{create_directory, change_directory, list_directory, remove_directory, save_directory_metadata, load_hierarchy, save_hierarchy, load_current_directory, save_current_directory};
-use disco::file::{create_file_in_directory, read_file, remove_file_from_directory, write_to_file};
-use disco::directory::DirectoryMetadata;
+use disco::cli::{
+    exit_code, help_for, init_logging, usage, Cli, Command, EXIT_SUCCESS, EXIT_USAGE,
+};
+use disco::script::run_script;
+use disco::shell::{execute, report_error, run_shell, History, HISTORY_FILE};
 
-fn main() -> io::Result<()> {
-    let metadata_path = "metadata.json";
-    let disk_path = "vfs_disk.bin";
-
-    // Inicializar o gerenciador de blocos
-    let mut block_manager = BlockManager::initialize(disk_path)?;
-
-    // Carregar ou inicializar o MetadataStore
-    let _metadata_store = if Path::new(metadata_path).exists() {
-        MetadataStore::load_from_file(metadata_path)?
-    } else {
-        MetadataStore::new()
+fn main() {
+    // Obter argumentos de linha de comando
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Erro: {}", e);
+            eprintln!("Use `disco --help` para ver as opções.");
+            process::exit(EXIT_USAGE);
+        }
     };
+    init_logging(cli.verbosity);
+    process::exit(run(cli));
+}
 
-    let root_directory_path = "root_directory.json";
-
-    let (mut root_directory, mut metadata_store) = if Path::new("filesystem.json").exists() {
-        load_hierarchy("filesystem.json")?
-    } else {
-        (
-            DirectoryMetadata {
-                parent: None,
-                name: "/".to_string(),
-                created_at: Utc::now().to_rfc3339(),
-                modified_at: Utc::now().to_rfc3339(),
-                files: HashMap::new(),
-                subdirectories: HashMap::new(),
+/// Executa o comando e retorna o código de saída
+fn run(cli: Cli) -> i32 {
+    if let Command::Help(name) = &cli.command {
+        return match name {
+            None => {
+                println!("{}", usage());
+                EXIT_SUCCESS
+            }
+            Some(name) => match help_for(name) {
+                Some(help) => {
+                    println!("{}", help.detailed());
+                    EXIT_SUCCESS
+                }
+                None => {
+                    eprintln!("Comando desconhecido: '{}'", name);
+                    EXIT_USAGE
+                }
             },
-            MetadataStore::new(),
-        )
-    };
+        };
+    }
 
-    let mut current_directory = if Path::new("current_directory.json").exists() {
-        load_current_directory("current_directory.json")?
-    } else {
-        root_directory.clone()
+    // Exibe o erro de uma operação e retorna o código de saída correspondente;
+    // erros de `execute` e dos scripts não passam por aqui, já foram exibidos
+    let name = match &cli.command {
+        Command::Execute(args) => args[0].clone(),
+        Command::Run { .. } => "run".to_string(),
+        Command::Shell => "shell".to_string(),
+        Command::Help(_) => "help".to_string(),
+    };
+    let format = cli.format;
+    let failed = |context: &str, e: io::Error| {
+        report_error(format, &name, context, &e);
+        exit_code(&e)
     };
 
-    // Obter argumentos de linha de comando
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Uso:");
-        println!("  create <file_name> <permissions>");
-        println!("  write <file_name> <data>");
-        println!("  read <file_name>");
-        println!("  metadata <file_name>");
-        println!("  remove <file_name>");
-        return Ok(());
-    }
+    // Abrir a imagem junto com a hierarquia e o diretório atual salvos
+    let mut vfs = match cli.open() {
+        Ok(vfs) => vfs,
+        Err(e) => return failed("Erro ao abrir a imagem", e),
+    };
 
-    let command = &args[1];
-    match command.as_str() {
-        "create" => {
-            if args.len() < 4 {
-                println!("Uso: create <file_name> <permissions>");
+    let code = match cli.command {
+        Command::Shell => {
+            // No shell o estado é carregado uma vez e salvo no `sync` ou na saída
+            // Somente leitura, nem o histórico é gravado
+            let mut history = if vfs.is_read_only() {
+                History::new()
             } else {
-                let file_name = &args[2];
-                let permissions = &args[3];
-
-                create_file_in_directory(
-                    file_name,
-                    &mut current_directory, // Use o diretório atual
-                    &mut metadata_store,
-                    permissions,
-                )?;
-            }
+                History::load(&vfs.state_dir().join(HISTORY_FILE).to_string_lossy())
+            };
+            // O shell é interativo e sempre usa texto
+            return match run_shell(&mut vfs, io::stdin().lock(), &mut history) {
+                Ok(()) => EXIT_SUCCESS,
+                Err(e) => failed("Erro no shell", e),
+            };
         }
-
-        "read" => {
-            if args.len() < 3 {
-                println!("Uso: read <file_name>");
-            } else {
-                let file_name = &args[2];
-                match read_file(file_name, &metadata_store, &mut block_manager) {
-                    Ok(content) => println!("Conteúdo do arquivo '{}':\n{}", file_name, content),
-                    Err(e) => eprintln!("Erro ao ler o arquivo: {}", e),
+        Command::Run {
+            script,
+            mut variables,
+        } => {
+            // Sem script (ou com `-`), os comandos são lidos da entrada padrão
+            let result = match script.as_deref() {
+                None | Some("-") => {
+                    run_script(&mut vfs, io::stdin().lock(), &mut variables, format)
                 }
-            }
+                Some(path) => match File::open(path) {
+                    Ok(file) => run_script(&mut vfs, BufReader::new(file), &mut variables, format),
+                    Err(e) => return failed("Erro ao abrir o script", e),
+                },
+            };
+            result.map_or_else(|failure| exit_code(&failure.error), |_| EXIT_SUCCESS)
         }
-        "write" => {
-            if args.len() < 4 {
-                println!("Uso: write <file_name> <data>");
-            } else {
-                let file_name = &args[2];
-                let data = &args[3];
-                write_to_file(
-                    file_name,
-                    data,
-                    &mut metadata_store,
-                    &mut block_manager,
-                    &current_directory,
-                )?;
-            }
-        }
-        "remove" => {
-            if args.len() < 3 {
-                println!("Uso: remove <file_name>");
-            } else {
-                let file_name = &args[2];
-                remove_file_from_directory(file_name, &mut current_directory, &mut metadata_store)?;
-            }
-        }
-        "mkdir" => {
-            if args.len() < 3 {
-                println!("Uso: mkdir <directory_name>");
-            } else {
-                let dir_name = &args[2];
-                if let Err(e) = create_directory(dir_name, &mut current_directory) {
-                    eprintln!("Erro ao criar diretório: {}", e);
-                } else {
-                    save_hierarchy(&root_directory, &metadata_store, "filesystem.json")?;
-                    save_current_directory(&current_directory, "current_directory.json")?;
-                }
-            }
+        Command::Execute(args) => {
+            execute(&mut vfs, &args, format).map_or_else(|e| exit_code(&e), |_| EXIT_SUCCESS)
         }
+        Command::Help(_) => EXIT_SUCCESS, // Já tratado acima
+    };
 
-        "ls" => {
-            list_directory(&current_directory); // Liste o conteúdo do diretório atual
-        }
-        "rmdir" => {
-            if args.len() < 3 {
-                println!("Uso: rmdir <directory_name>");
-            } else {
-                let dir_name = &args[2];
-                remove_directory(dir_name, &mut root_directory)?;
-            }
-        }
-        "cd" => {
-            if args.len() < 3 {
-                println!("Uso: cd <directory_path>");
-            } else {
-                let dir_path = &args[2];
-                if let Err(e) = change_directory(&mut current_directory, &root_directory, dir_path)
-                {
-                    eprintln!("Erro ao mudar de diretório: {}", e);
-                }
-            }
-        }
-        _ => println!("Comando desconhecido. Use 'create', 'write', ou 'remove'."),
+    // O que foi executado, mesmo antes de uma falha, permanece salvo
+    if vfs.is_read_only() {
+        return code;
+    }
+    match vfs.sync() {
+        Ok(()) => code,
+        Err(e) => failed("Erro ao salvar o estado", e),
     }
-
-    // Salvar metadados no arquivo
-    metadata_store.save_to_file(metadata_path)?;
-
-    // Salvar diretório raiz antes de encerrar
-    save_directory_metadata(&root_directory, root_directory_path)?;
-
-    save_hierarchy(&root_directory, &metadata_store, "filesystem.json")?;
-    save_current_directory(&current_directory, "current_directory.json")?;
-
-    Ok(())
 }
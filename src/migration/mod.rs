@@ -0,0 +1,389 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+};
+
+use chrono::DateTime;
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::{
+    block::{BlockManager, MetadataStore},
+    directory::{DirectoryId, DirectoryMetadata, DirectoryTree},
+    file::FileMetadata,
+    path::VfsPath,
+};
+
+/// Formato aninhado original, com subdiretórios e arquivos copiados dentro de cada nó
+#[derive(Deserialize)]
+struct LegacyDirectoryMetadata {
+    created_at: String,
+    modified_at: String,
+    #[serde(default)]
+    files: HashMap<String, FileMetadata>,
+    #[serde(default)]
+    subdirectories: HashMap<String, LegacyDirectoryMetadata>,
+    #[serde(default)]
+    read_only: bool,
+}
+
+/// Arena em que cada diretório ainda guardava uma cópia dos metadados dos arquivos
+#[derive(Deserialize)]
+struct ArenaDirectoryMetadata {
+    name: String,
+    created_at: String,
+    modified_at: String,
+    files: HashMap<String, FileMetadata>,
+    subdirectories: HashMap<String, DirectoryId>,
+    parent: Option<DirectoryId>,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Deserialize)]
+struct ArenaDirectoryTree {
+    root: DirectoryId,
+    next_id: DirectoryId,
+    directories: BTreeMap<DirectoryId, ArenaDirectoryMetadata>,
+}
+
+/// Origem de uma versão dos metadados de um arquivo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataSource {
+    Tree,  // Cópia guardada no diretório
+    Store, // Entrada do MetadataStore
+}
+
+/// Arquivo cujas duas cópias divergiam na hierarquia antiga
+#[derive(Debug, Clone)]
+pub struct MetadataConflict {
+    pub path: String,
+    pub kept: MetadataSource,        // Versão mantida no índice
+    pub discarded: FileMetadata,     // Versão descartada, para conferência
+    pub orphaned_blocks: Vec<usize>, // Blocos da versão descartada que nenhum arquivo usa
+}
+
+/// Resultado da migração de uma hierarquia antiga
+#[derive(Debug, Default, Clone)]
+pub struct MigrationReport {
+    pub files: usize,           // Arquivos no índice após a migração
+    pub recovered: Vec<String>, // Presentes só na árvore, adicionados ao índice
+    pub adopted: Vec<String>,   // Presentes só no índice, religados à árvore
+    pub conflicts: Vec<MetadataConflict>,
+}
+
+impl MigrationReport {
+    /// Blocos das versões descartadas que nenhum arquivo do índice referencia
+    pub fn orphaned_blocks(&self) -> Vec<usize> {
+        let blocks: BTreeSet<usize> = self
+            .conflicts
+            .iter()
+            .flat_map(|conflict| conflict.orphaned_blocks.iter().copied())
+            .collect();
+        blocks.into_iter().collect()
+    }
+
+    /// Registra o resumo da migração e cada conflito no log
+    pub fn log(&self) {
+        info!(
+            "Hierarquia migrada: {} arquivo(s), {} recuperado(s) da árvore, {} religado(s) à árvore, {} conflito(s)",
+            self.files,
+            self.recovered.len(),
+            self.adopted.len(),
+            self.conflicts.len()
+        );
+        for conflict in &self.conflicts {
+            let kept = match conflict.kept {
+                MetadataSource::Tree => "árvore",
+                MetadataSource::Store => "índice",
+            };
+            warn!(
+                "Conflito em '{}': mantida a versão do(a) {} (descartada: {} bytes, blocos {:?}, modificada em {}; órfãos: {:?})",
+                conflict.path,
+                kept,
+                conflict.discarded.size,
+                conflict.discarded.block_indices,
+                conflict.discarded.modified_at,
+                conflict.orphaned_blocks
+            );
+        }
+    }
+}
+
+/// Interpreta o conteúdo de `filesystem.json` em qualquer formato conhecido
+///
+/// Retorna o relatório apenas quando o conteúdo estava em um formato antigo e
+/// precisou ser migrado.
+pub fn load_hierarchy_data(
+    data: &str,
+) -> io::Result<(DirectoryTree, MetadataStore, Option<MigrationReport>)> {
+    match serde_json::from_str::<(DirectoryTree, MetadataStore)>(data) {
+        Ok((tree, metadata_store)) => Ok((tree, metadata_store, None)),
+        Err(error) => {
+            let (tree, metadata_store, report) = migrate_hierarchy(data).map_err(|_| error)?;
+            Ok((tree, metadata_store, Some(report)))
+        }
+    }
+}
+
+/// Converte uma hierarquia antiga, unificando as duas cópias dos metadados
+pub fn migrate_hierarchy(
+    data: &str,
+) -> io::Result<(DirectoryTree, MetadataStore, MigrationReport)> {
+    let (mut tree, embedded, mut metadata_store) =
+        match serde_json::from_str::<(ArenaDirectoryTree, MetadataStore)>(data) {
+            Ok((arena, metadata_store)) => {
+                let (tree, embedded) = from_arena(arena);
+                (tree, embedded, metadata_store)
+            }
+            Err(_) => {
+                let (legacy, metadata_store): (LegacyDirectoryMetadata, MetadataStore) =
+                    serde_json::from_str(data)?;
+                let (tree, embedded) = from_legacy(legacy);
+                (tree, embedded, metadata_store)
+            }
+        };
+
+    let report = merge_file_metadata(&mut tree, embedded, &mut metadata_store);
+    Ok((tree, metadata_store, report))
+}
+
+/// Metadados embutidos na árvore antiga, com o caminho derivado da posição
+type EmbeddedFiles = Vec<(VfsPath, FileMetadata)>;
+
+fn from_arena(arena: ArenaDirectoryTree) -> (DirectoryTree, EmbeddedFiles) {
+    let mut embedded = Vec::new();
+    let mut directories = BTreeMap::new();
+    for (id, directory) in arena.directories {
+        let mut converted = DirectoryMetadata::new(&directory.name, directory.parent);
+        converted.created_at = directory.created_at;
+        converted.modified_at = directory.modified_at;
+        converted.subdirectories = directory.subdirectories;
+        converted.read_only = directory.read_only;
+        embedded.push((id, directory.files));
+        directories.insert(id, converted);
+    }
+
+    let tree = DirectoryTree::from_parts(arena.root, arena.next_id, directories);
+    let embedded = embedded
+        .into_iter()
+        .flat_map(|(id, files)| {
+            let directory_path = tree.path_of(id);
+            files
+                .into_iter()
+                .map(move |(name, metadata)| (directory_path.child_unchecked(&name), metadata))
+        })
+        .collect();
+    (tree, embedded)
+}
+
+fn from_legacy(root: LegacyDirectoryMetadata) -> (DirectoryTree, EmbeddedFiles) {
+    let mut tree = DirectoryTree::new();
+    let mut embedded = Vec::new();
+    let root_id = tree.root();
+    import_legacy(&mut tree, root_id, root, &mut embedded);
+    (tree, embedded)
+}
+
+fn import_legacy(
+    tree: &mut DirectoryTree,
+    id: DirectoryId,
+    legacy: LegacyDirectoryMetadata,
+    embedded: &mut EmbeddedFiles,
+) {
+    let directory_path = tree.path_of(id);
+    if let Some(directory) = tree.get_mut(id) {
+        directory.created_at = legacy.created_at;
+        directory.modified_at = legacy.modified_at;
+        directory.read_only = legacy.read_only;
+    }
+    for (name, metadata) in legacy.files {
+        embedded.push((directory_path.child_unchecked(&name), metadata));
+    }
+    for (name, subdirectory) in legacy.subdirectories {
+        let child = tree.add_directory(id, &name);
+        import_legacy(tree, child, subdirectory, embedded);
+    }
+}
+
+/// Compara as datas de modificação; datas ilegíveis contam como mais antigas
+fn is_newer(candidate: &FileMetadata, current: &FileMetadata) -> bool {
+    let parse = |value: &str| DateTime::parse_from_rfc3339(value).ok();
+    parse(&candidate.modified_at) > parse(&current.modified_at)
+}
+
+/// Guarda `candidate` sob `key`; se o índice já tem outra cópia, vale a
+/// modificada por último (a do índice vence empates) e a outra vai para o relatório
+///
+/// Retorna `false` se o índice ainda não tinha a chave.
+fn merge_copy(
+    metadata_store: &mut MetadataStore,
+    key: &str,
+    candidate: FileMetadata,
+    source: MetadataSource,
+    report: &mut MigrationReport,
+) -> bool {
+    let Some(current) = metadata_store.get_file_metadata(key).cloned() else {
+        metadata_store.add_file(key, candidate);
+        return false;
+    };
+    if current == candidate {
+        return true;
+    }
+    let (kept, discarded) = if is_newer(&candidate, &current) {
+        metadata_store.update_file_metadata(key, candidate);
+        (source, current)
+    } else {
+        (MetadataSource::Store, candidate)
+    };
+    report.conflicts.push(MetadataConflict {
+        path: key.to_string(),
+        kept,
+        discarded,
+        orphaned_blocks: Vec::new(),
+    });
+    true
+}
+
+/// Leva cada entrada do índice para o caminho absoluto normalizado
+///
+/// Versões antigas gravavam a chave com o argumento recebido (`test_file`,
+/// `docs/a.txt`), que nenhuma busca pelo caminho normalizado alcança. Chaves que
+/// passam a coincidir são fundidas como qualquer outro conflito.
+fn normalize_store_keys(metadata_store: &mut MetadataStore, report: &mut MigrationReport) {
+    let mut keys: Vec<String> = metadata_store.paths().map(str::to_string).collect();
+    // As chaves já normalizadas ficam no lugar; as demais são fundidas a elas
+    keys.sort_by_key(|key| {
+        (
+            VfsPath::parse(key).is_ok_and(|path| path.to_string() != *key),
+            key.clone(),
+        )
+    });
+    for key in keys {
+        let Ok(path) = VfsPath::parse(&key) else {
+            continue;
+        };
+        let Some(mut metadata) = metadata_store.get_file_metadata(&key).cloned() else {
+            continue;
+        };
+        let normalized = path.to_string();
+        metadata.path = normalized.clone();
+        if normalized == key {
+            metadata_store.update_file_metadata(&key, metadata);
+            continue;
+        }
+        metadata_store.remove_file_metadata(&key);
+        merge_copy(
+            metadata_store,
+            &normalized,
+            metadata,
+            MetadataSource::Store,
+            report,
+        );
+    }
+}
+
+/// Unifica as cópias da árvore com o MetadataStore, que passa a ser o único índice
+///
+/// As chaves do índice são normalizadas antes. Cópias iguais são fundidas e,
+/// quando divergem, vale a modificada por último (`merge_copy`). Arquivos só da
+/// árvore entram no índice e arquivos só do índice ganham referência na árvore,
+/// recriando diretórios que faltarem.
+fn merge_file_metadata(
+    tree: &mut DirectoryTree,
+    embedded: EmbeddedFiles,
+    metadata_store: &mut MetadataStore,
+) -> MigrationReport {
+    let mut report = MigrationReport::default();
+    normalize_store_keys(metadata_store, &mut report);
+
+    for (path, mut tree_copy) in embedded {
+        let key = path.to_string();
+        tree_copy.path = key.clone();
+        let known = merge_copy(
+            metadata_store,
+            &key,
+            tree_copy,
+            MetadataSource::Tree,
+            &mut report,
+        );
+        if !known {
+            report.recovered.push(key);
+        }
+        link_file(tree, &path);
+    }
+
+    // Entradas do índice que a árvore não conhecia
+    let store_paths: Vec<String> = metadata_store.paths().map(str::to_string).collect();
+    for key in store_paths {
+        let Ok(path) = VfsPath::parse(&key) else {
+            continue;
+        };
+        if link_file(tree, &path) {
+            report.adopted.push(key);
+        }
+    }
+
+    // Blocos das versões descartadas que ficaram sem dono no índice unificado
+    let referenced: BTreeSet<usize> = metadata_store
+        .paths()
+        .filter_map(|key| metadata_store.get_file_metadata(key))
+        .flat_map(|metadata| metadata.referenced_blocks())
+        .collect();
+    for conflict in &mut report.conflicts {
+        let orphaned: BTreeSet<usize> = conflict
+            .discarded
+            .referenced_blocks()
+            .filter(|block| !referenced.contains(block))
+            .collect();
+        conflict.orphaned_blocks = orphaned.into_iter().collect();
+    }
+
+    report.recovered.sort();
+    report.adopted.sort();
+    report.files = metadata_store.len();
+    report
+}
+
+/// Garante a referência ao arquivo na árvore; retorna `true` se ela foi criada
+fn link_file(tree: &mut DirectoryTree, path: &VfsPath) -> bool {
+    let (Some(parent_path), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    let mut directory = tree.root();
+    for component in parent_path.components() {
+        let existing = tree
+            .get(directory)
+            .and_then(|current| current.subdirectories.get(component).copied());
+        directory = match existing {
+            Some(id) => id,
+            None => tree.add_directory(directory, component),
+        };
+    }
+    tree.get_mut(directory).is_some_and(|directory| {
+        // Um diretório com o mesmo nome tem precedência sobre a referência
+        !directory.subdirectories.contains_key(name) && directory.files.insert(name.to_string())
+    })
+}
+
+/// Libera os blocos órfãos das versões descartadas numa migração
+///
+/// Só blocos ainda ocupados são liberados (a escrita antiga pode já tê-los
+/// devolvido), e todas as referências deles, já que nenhum arquivo os usa.
+/// Retorna quantos blocos voltaram a ficar livres.
+pub fn reclaim_orphaned_blocks(
+    report: &MigrationReport,
+    block_manager: &BlockManager,
+) -> io::Result<usize> {
+    let mut reclaimed = 0;
+    for block in report.orphaned_blocks() {
+        if block_manager.block_ref_count(block)? == 0 {
+            continue;
+        }
+        while block_manager.block_ref_count(block)? > 0 {
+            block_manager.free_block(block)?;
+        }
+        reclaimed += 1;
+    }
+    Ok(reclaimed)
+}
@@ -0,0 +1,242 @@
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex, OnceLock, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    block::{BlockManager, MetadataStore},
+    directory::{read_only_error, resolve_path, write_new_blocks, DirectoryId, DirectoryTree},
+    error::{DiscoError, Result},
+    file::read_contents,
+    path::VfsPath,
+    versioning::{replace_contents, RetentionPolicy},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fila das threads que executam a E/S bloqueante, iniciadas no primeiro uso
+///
+/// `None` se nenhuma thread pôde ser criada.
+fn pool() -> Option<&'static mpsc::Sender<Job>> {
+    static POOL: OnceLock<Option<mpsc::Sender<Job>>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = thread::available_parallelism().map_or(4, NonZeroUsize::get);
+        let mut started = 0;
+        for worker in 0..workers {
+            let receiver = Arc::clone(&receiver);
+            let spawned = thread::Builder::new()
+                .name(format!("disco-io-{}", worker))
+                .spawn(move || loop {
+                    let job = receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                });
+            match spawned {
+                Ok(_) => started += 1,
+                Err(e) => warn!("Não foi possível iniciar a thread de E/S {}: {}", worker, e),
+            }
+        }
+        debug!("{} threads de E/S iniciadas", started);
+        (started > 0).then_some(sender)
+    })
+    .as_ref()
+}
+
+struct TaskState<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Resultado de `spawn_blocking`, disponível quando a função termina
+///
+/// Não depende de um runtime específico: quem espera é acordado pelo `Waker`
+/// recebido no último `poll`.
+pub struct BlockingTask<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+/// Executa `function` numa das threads de E/S, sem bloquear quem a espera
+///
+/// Sem threads de E/S disponíveis, a função roda na hora, na thread de quem a
+/// chama, e a tarefa já nasce pronta. Um pânico na função é repassado a quem
+/// aguarda a tarefa.
+pub fn spawn_blocking<T, F>(function: F) -> BlockingTask<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let state = Arc::new(Mutex::new(TaskState {
+        result: None,
+        waker: None,
+    }));
+    let task_state = Arc::clone(&state);
+    let job: Job = Box::new(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(function));
+        let mut state = task_state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    let inline = match pool() {
+        Some(pool) => pool.send(job).err().map(|mpsc::SendError(job)| job),
+        None => Some(job),
+    };
+    if let Some(job) = inline {
+        job();
+    }
+    BlockingTask { state }
+}
+
+impl<T> Future for BlockingTask<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Versão assíncrona das operações do `BlockManager`
+///
+/// Cada operação roda numa thread de E/S; o gerenciador continua acessível pela
+/// API síncrona em `blocking`. Clonar é barato e os clones usam o mesmo gerenciador.
+#[derive(Clone)]
+pub struct AsyncBlockManager {
+    inner: Arc<BlockManager>,
+}
+
+impl AsyncBlockManager {
+    pub fn new(block_manager: BlockManager) -> Self {
+        AsyncBlockManager {
+            inner: Arc::new(block_manager),
+        }
+    }
+
+    /// O gerenciador, para as operações síncronas
+    pub fn blocking(&self) -> &BlockManager {
+        &self.inner
+    }
+
+    /// Executa `operation` sobre o gerenciador numa thread de E/S
+    async fn run<T, F>(&self, operation: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&BlockManager) -> T + Send + 'static,
+    {
+        let block_manager = Arc::clone(&self.inner);
+        spawn_blocking(move || operation(&block_manager)).await
+    }
+
+    pub async fn allocate_block(&self) -> Result<usize> {
+        self.run(|block_manager| block_manager.allocate_block())
+            .await
+    }
+
+    pub async fn free_block(&self, index: usize) -> Result<()> {
+        self.run(move |block_manager| block_manager.free_block(index))
+            .await
+    }
+
+    pub async fn write_block(&self, index: usize, data: Vec<u8>) -> Result<()> {
+        self.run(move |block_manager| block_manager.write_block(index, &data))
+            .await
+    }
+
+    pub async fn read_block(&self, index: usize) -> Result<Vec<u8>> {
+        self.run(move |block_manager| block_manager.read_block(index))
+            .await
+    }
+}
+
+/// Versão assíncrona de `file::read_file`: todos os blocos são lidos numa thread de E/S
+pub async fn read_file(
+    path: &str,
+    metadata_store: &MetadataStore,
+    block_manager: &AsyncBlockManager,
+) -> Result<String> {
+    let path = VfsPath::parse(path)?.to_string();
+    let metadata = metadata_store
+        .get_file_metadata(&path)
+        .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?
+        .clone();
+    block_manager
+        .run(move |block_manager| read_contents(&metadata, block_manager))
+        .await
+}
+
+/// Versão assíncrona de `file::write_to_file`
+pub async fn write_to_file(
+    path: &str,
+    data: &str,
+    metadata_store: &mut MetadataStore,
+    block_manager: &AsyncBlockManager,
+    tree: &DirectoryTree,
+    current_directory: DirectoryId,
+) -> Result<()> {
+    let resolved_path = resolve_path(tree, current_directory, path)?;
+    write_file_contents(&resolved_path, data, metadata_store, block_manager, None).await
+}
+
+/// Versão assíncrona de `file::write_file_contents`
+///
+/// Os dados vão para blocos novos numa thread de E/S; os metadados só mudam
+/// depois, por `replace_contents`, e os blocos que o arquivo deixou de usar são
+/// liberados por último, como na versão síncrona.
+pub async fn write_file_contents(
+    path: &VfsPath,
+    data: &str,
+    metadata_store: &mut MetadataStore,
+    block_manager: &AsyncBlockManager,
+    policy: Option<&RetentionPolicy>,
+) -> Result<()> {
+    let resolved_path = path.to_string();
+    let mut updated_metadata = metadata_store
+        .get_file_metadata(&resolved_path)
+        .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?
+        .clone();
+    if updated_metadata.archive.is_some() {
+        return Err(read_only_error());
+    }
+
+    let data = data.as_bytes().to_vec();
+    let size = data.len() as u64;
+    let blocks = block_manager
+        .run(move |block_manager| write_new_blocks(&data, block_manager))
+        .await?;
+
+    let released_blocks = replace_contents(&mut updated_metadata, blocks, size, policy);
+    metadata_store.update_file_metadata(&resolved_path, updated_metadata);
+
+    block_manager
+        .run(move |block_manager| {
+            released_blocks
+                .iter()
+                .try_for_each(|&block_index| block_manager.free_block(block_index))
+        })
+        .await?;
+
+    info!("Dados escritos no arquivo '{}'", resolved_path);
+    Ok(())
+}
@@ -0,0 +1,446 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+    error::{DiscoError, Result},
+    filesystem::{sort_entries, FileSystem},
+    path::VfsPath,
+    vfs::{DirectoryEntry, EntryKind, Stat},
+};
+
+/// Prefixo das entradas que escondem um nome das camadas inferiores
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+/// Entrada que torna um diretório opaco: nada das camadas inferiores aparece nele
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+/// Permissões dos arquivos de controle (whiteouts e marcadores)
+const MARKER_PERMISSIONS: &str = "---------";
+
+/// Sistema de arquivos em camadas: uma camada superior gravável sobre camadas
+/// inferiores somente leitura
+///
+/// As camadas inferiores nunca são alteradas. Escrever em um arquivo que só
+/// existe embaixo copia o arquivo para cima (copy-up); remover uma entrada de
+/// baixo grava um whiteout (`.wh.<nome>`) na camada superior. Um diretório
+/// recriado por cima de um removido recebe o marcador `.wh..wh..opq`. Como os
+/// whiteouts são entradas comuns, eles persistem junto com a camada superior.
+///
+/// As camadas inferiores vêm em ordem de prioridade: a primeira encobre as
+/// seguintes. Caminhos relativos partem da raiz.
+pub struct OverlayFileSystem<U: FileSystem> {
+    upper: U,
+    lowers: Vec<Box<dyn FileSystem>>,
+}
+
+/// Índice da camada superior; as inferiores vêm em seguida
+const UPPER: usize = 0;
+
+impl<U: FileSystem> OverlayFileSystem<U> {
+    pub fn new(upper: U, lowers: Vec<Box<dyn FileSystem>>) -> Self {
+        OverlayFileSystem { upper, lowers }
+    }
+
+    pub fn upper(&self) -> &U {
+        &self.upper
+    }
+
+    pub fn upper_mut(&mut self) -> &mut U {
+        &mut self.upper
+    }
+
+    /// Devolve a camada superior, por exemplo para sincronizar a imagem
+    pub fn into_upper(self) -> U {
+        self.upper
+    }
+
+    fn layer(&self, index: usize) -> &dyn FileSystem {
+        match index {
+            UPPER => &self.upper,
+            index => self.lowers[index - 1].as_ref(),
+        }
+    }
+
+    /// Tipo da entrada em uma única camada, ou `None` se ela não existir ali
+    fn probe(&self, index: usize, path: &VfsPath) -> Result<Option<EntryKind>> {
+        match self.layer(index).stat(&path.to_string()) {
+            Ok(stat) => Ok(Some(stat.kind)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn has_marker(&self, index: usize, directory: &VfsPath, marker: &str) -> Result<bool> {
+        Ok(self
+            .probe(index, &directory.child_unchecked(marker))?
+            .is_some())
+    }
+
+    /// Camadas que contribuem para um caminho, da mais alta para a mais baixa
+    ///
+    /// Um arquivo encobre tudo o que está abaixo dele; diretórios de camadas
+    /// diferentes se combinam até um whiteout, um marcador opaco ou um arquivo.
+    fn layers(&self, path: &VfsPath) -> Result<Vec<(usize, EntryKind)>> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            let mut layers = Vec::new();
+            for index in 0..=self.lowers.len() {
+                if self.probe(index, path)? == Some(EntryKind::Directory) {
+                    layers.push((index, EntryKind::Directory));
+                }
+            }
+            return Ok(layers);
+        };
+        if name.starts_with(WHITEOUT_PREFIX) {
+            return Ok(Vec::new()); // Entradas de controle não fazem parte da visão
+        }
+
+        let mut layers = Vec::new();
+        for (index, kind) in self.layers(&parent)? {
+            if kind != EntryKind::Directory {
+                break;
+            }
+            if let Some(kind) = self.probe(index, path)? {
+                if kind == EntryKind::File && !layers.is_empty() {
+                    break;
+                }
+                layers.push((index, kind));
+                if kind == EntryKind::File {
+                    break;
+                }
+            }
+            let whiteout = format!("{}{}", WHITEOUT_PREFIX, name);
+            if self.has_marker(index, &parent, &whiteout)?
+                || self.has_marker(index, &parent, OPAQUE_MARKER)?
+            {
+                break;
+            }
+        }
+        Ok(layers)
+    }
+
+    /// Tipo da entrada como aparece no overlay
+    fn kind_of(&self, path: &VfsPath) -> Result<Option<EntryKind>> {
+        Ok(self.layers(path)?.first().map(|&(_, kind)| kind))
+    }
+
+    /// Diretório pai de uma nova entrada, que precisa existir no overlay
+    fn parent_of(&self, path: &VfsPath) -> Result<VfsPath> {
+        let parent = path.parent().ok_or_else(|| {
+            DiscoError::InvalidArgument("Path does not name an entry".to_string())
+        })?;
+        if let Some(name) = path
+            .file_name()
+            .filter(|name| name.starts_with(WHITEOUT_PREFIX))
+        {
+            return Err(DiscoError::InvalidPath(format!(
+                "'{}' is reserved for the overlay",
+                name
+            )));
+        }
+        if self.kind_of(&parent)? != Some(EntryKind::Directory) {
+            return Err(DiscoError::NotFound("Directory not found".to_string()));
+        }
+        Ok(parent)
+    }
+
+    /// Cria na camada superior os diretórios acima de `path` que só existem embaixo
+    fn copy_up_parents(&mut self, path: &VfsPath) -> Result<()> {
+        let mut ancestors: Vec<VfsPath> =
+            std::iter::successors(path.parent(), VfsPath::parent).collect();
+        ancestors.pop(); // A raiz sempre existe
+        for ancestor in ancestors.into_iter().rev() {
+            if self.probe(UPPER, &ancestor)?.is_none() {
+                self.upper.mkdir(&ancestor.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copia uma entrada (e, se for diretório, todo o seu conteúdo) para cima
+    fn copy_up(&mut self, path: &VfsPath) -> Result<()> {
+        let layers = self.layers(path)?;
+        let Some(&(top, kind)) = layers.first() else {
+            return Err(DiscoError::NotFound("Source not found".to_string()));
+        };
+
+        if kind == EntryKind::File {
+            if top != UPPER {
+                let file = path.to_string();
+                let permissions = self.layer(top).stat(&file)?.permissions;
+                let data = self.layer(top).read(&file)?;
+                self.copy_up_parents(path)?;
+                self.upper.create(&file, &permissions)?;
+                self.upper.write(&file, &data)?;
+            }
+            return Ok(());
+        }
+
+        if top != UPPER {
+            self.copy_up_parents(path)?;
+            self.upper.mkdir(&path.to_string())?;
+        }
+        for entry in self.readdir(&path.to_string())? {
+            self.copy_up(&path.child_unchecked(&entry.name))?;
+        }
+        Ok(())
+    }
+
+    fn whiteout_path(path: &VfsPath) -> VfsPath {
+        let parent = path.parent().unwrap_or_default();
+        let name = path.file_name().unwrap_or_default();
+        parent.child_unchecked(&format!("{}{}", WHITEOUT_PREFIX, name))
+    }
+
+    /// Esconde `path` das camadas inferiores
+    fn whiteout(&mut self, path: &VfsPath) -> Result<()> {
+        self.copy_up_parents(path)?;
+        let whiteout = Self::whiteout_path(path);
+        self.upper.create(&whiteout.to_string(), MARKER_PERMISSIONS)
+    }
+
+    /// Remove o whiteout de `path`, indicando se havia um
+    fn clear_whiteout(&mut self, path: &VfsPath) -> Result<bool> {
+        let whiteout = Self::whiteout_path(path);
+        if self.probe(UPPER, &whiteout)?.is_none() {
+            return Ok(false);
+        }
+        self.upper.remove(&whiteout.to_string(), false)?;
+        Ok(true)
+    }
+
+    /// Torna opaco um diretório da camada superior
+    fn make_opaque(&mut self, directory: &VfsPath) -> Result<()> {
+        if self.has_marker(UPPER, directory, OPAQUE_MARKER)? {
+            return Ok(());
+        }
+        let marker = directory.child_unchecked(OPAQUE_MARKER);
+        self.upper.create(&marker.to_string(), MARKER_PERMISSIONS)
+    }
+}
+
+impl<U: FileSystem> FileSystem for OverlayFileSystem<U> {
+    fn create(&mut self, path: &str, permissions: &str) -> Result<()> {
+        let path = VfsPath::parse(path)?;
+        self.parent_of(&path)?;
+        match self.kind_of(&path)? {
+            Some(EntryKind::Directory) => {
+                return Err(DiscoError::AlreadyExists(
+                    "A directory with this name already exists".to_string(),
+                ))
+            }
+            Some(EntryKind::File) => {
+                return Err(DiscoError::AlreadyExists(
+                    "File already exists in this directory".to_string(),
+                ))
+            }
+            None => {}
+        }
+
+        self.copy_up_parents(&path)?;
+        self.clear_whiteout(&path)?;
+        self.upper.create(&path.to_string(), permissions)
+    }
+
+    fn read(&self, path: &str) -> Result<String> {
+        let path = VfsPath::parse(path)?;
+        match self.layers(&path)?.first() {
+            Some(&(index, EntryKind::File)) => self.layer(index).read(&path.to_string()),
+            _ => Err(DiscoError::NotFound("File not found".to_string())),
+        }
+    }
+
+    fn write(&mut self, path: &str, data: &str) -> Result<()> {
+        let path = VfsPath::parse(path)?;
+        let Some(&(top, EntryKind::File)) = self.layers(&path)?.first() else {
+            return Err(DiscoError::NotFound("File not found".to_string()));
+        };
+
+        let file = path.to_string();
+        if top != UPPER {
+            // Copy-up: o arquivo passa a existir em cima com as mesmas permissões;
+            // o conteúdo antigo não precisa ser copiado, pois será substituído
+            let permissions = self.layer(top).stat(&file)?.permissions;
+            self.copy_up_parents(&path)?;
+            self.upper.create(&file, &permissions)?;
+        }
+        self.upper.write(&file, data)
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()> {
+        let path = VfsPath::parse(path)?;
+        let kind = self.kind_of(&path)?;
+        if kind == Some(EntryKind::Directory) && !recursive {
+            return Err(DiscoError::IsADirectory(
+                "Is a directory (use -r)".to_string(),
+            ));
+        }
+        if path.is_root() {
+            return Err(DiscoError::InvalidArgument(
+                "Cannot remove the root directory".to_string(),
+            ));
+        }
+        if kind.is_none() {
+            return Err(DiscoError::NotFound("Source not found".to_string()));
+        }
+
+        if self.probe(UPPER, &path)?.is_some() {
+            self.upper.remove(&path.to_string(), true)?;
+        }
+        // O que estava embaixo reaparece se não for escondido
+        if self.kind_of(&path)?.is_some() {
+            self.whiteout(&path)?;
+        }
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<()> {
+        let path = VfsPath::parse(path)?;
+        self.parent_of(&path)?;
+        match self.kind_of(&path)? {
+            Some(EntryKind::Directory) => {
+                return Err(DiscoError::AlreadyExists(
+                    "Directory already exists".to_string(),
+                ))
+            }
+            Some(EntryKind::File) => {
+                return Err(DiscoError::AlreadyExists(
+                    "A file with this name already exists".to_string(),
+                ))
+            }
+            None => {}
+        }
+
+        self.copy_up_parents(&path)?;
+        let replaces_removed = self.clear_whiteout(&path)?;
+        self.upper.mkdir(&path.to_string())?;
+        if replaces_removed {
+            // O diretório removido embaixo não pode voltar a aparecer aqui dentro
+            self.make_opaque(&path)?;
+        }
+        Ok(())
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<DirectoryEntry>> {
+        let path = VfsPath::parse(path)?;
+        let layers = self.layers(&path)?;
+        if layers.first().map(|&(_, kind)| kind) != Some(EntryKind::Directory) {
+            return Err(DiscoError::NotFound(format!(
+                "Directory '{}' not found",
+                path
+            )));
+        }
+
+        let mut entries: BTreeMap<String, EntryKind> = BTreeMap::new();
+        let mut hidden: HashSet<String> = HashSet::new();
+        for (index, _) in layers {
+            let mut opaque = false;
+            let mut whiteouts = Vec::new();
+            for entry in self.layer(index).readdir(&path.to_string())? {
+                if entry.name == OPAQUE_MARKER {
+                    opaque = true;
+                } else if let Some(name) = entry.name.strip_prefix(WHITEOUT_PREFIX) {
+                    whiteouts.push(name.to_string());
+                } else if !hidden.contains(&entry.name) {
+                    entries.entry(entry.name).or_insert(entry.kind);
+                }
+            }
+            // Whiteouts e marcadores só valem para as camadas de baixo
+            hidden.extend(whiteouts);
+            if opaque {
+                break;
+            }
+        }
+
+        let mut entries: Vec<DirectoryEntry> = entries
+            .into_iter()
+            .map(|(name, kind)| DirectoryEntry { name, kind })
+            .collect();
+        sort_entries(&mut entries);
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> Result<Stat> {
+        let path = VfsPath::parse(path)?;
+        let Some(&(top, kind)) = self.layers(&path)?.first() else {
+            return Err(DiscoError::NotFound("File not found".to_string()));
+        };
+        let mut stat = self.layer(top).stat(&path.to_string())?;
+        if kind == EntryKind::Directory {
+            stat.size = self.readdir(&path.to_string())?.len() as u64;
+        }
+        Ok(stat)
+    }
+
+    fn rename(&mut self, source: &str, destination: &str) -> Result<()> {
+        let invalid = |message: &str| DiscoError::InvalidArgument(message.to_string());
+        let source = VfsPath::parse(source)?;
+        let destination = VfsPath::parse(destination)?;
+
+        if source.is_root() {
+            return Err(invalid("Cannot move the root directory"));
+        }
+        let Some(source_kind) = self.kind_of(&source)? else {
+            return Err(DiscoError::NotFound("Source not found".to_string()));
+        };
+        let moving_directory = source_kind == EntryKind::Directory;
+
+        // Mover para um diretório existente coloca a entrada dentro dele
+        let mut destination = destination;
+        if destination != source && self.kind_of(&destination)? == Some(EntryKind::Directory) {
+            destination = destination.child_unchecked(source.file_name().unwrap_or_default());
+        }
+        if destination == source {
+            return Ok(());
+        }
+        if moving_directory && destination.starts_with(&source) {
+            return Err(invalid("Cannot move a directory into its own descendant"));
+        }
+        if destination.is_root() {
+            return Err(invalid("Invalid destination"));
+        }
+        match self.parent_of(&destination) {
+            Err(DiscoError::NotFound(_)) => {
+                return Err(DiscoError::NotFound(
+                    "Destination directory not found".to_string(),
+                ))
+            }
+            result => result?,
+        };
+
+        let replaced = self.kind_of(&destination)?;
+        match replaced {
+            Some(EntryKind::Directory) if !moving_directory => {
+                return Err(DiscoError::IsADirectory(
+                    "Cannot overwrite a directory with a file".to_string(),
+                ))
+            }
+            Some(EntryKind::Directory) if !self.readdir(&destination.to_string())?.is_empty() => {
+                return Err(DiscoError::NotEmpty(
+                    "Destination directory is not empty".to_string(),
+                ))
+            }
+            Some(EntryKind::File) if moving_directory => {
+                return Err(DiscoError::NotADirectory(
+                    "Cannot overwrite a file with a directory".to_string(),
+                ))
+            }
+            _ => {}
+        }
+
+        // As camadas inferiores não mudam: a origem inteira sobe, é movida em
+        // cima e o que restar embaixo nos dois caminhos é escondido
+        if replaced.is_some() {
+            self.remove(&destination.to_string(), true)?;
+        }
+        self.copy_up(&source)?;
+        self.copy_up_parents(&destination)?;
+        let covers_lower = self.clear_whiteout(&destination)?;
+        self.upper
+            .rename(&source.to_string(), &destination.to_string())?;
+        if moving_directory && covers_lower {
+            self.make_opaque(&destination)?;
+        }
+        if self.kind_of(&source)?.is_some() {
+            self.whiteout(&source)?;
+        }
+        Ok(())
+    }
+}
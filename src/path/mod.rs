@@ -0,0 +1,144 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DiscoError, Result};
+
+pub const MAX_NAME_LENGTH: usize = 255; // Tamanho máximo de um nome de arquivo ou diretório
+
+/// Caminho absoluto e normalizado dentro do sistema de arquivos virtual
+///
+/// Um `VfsPath` nunca contém componentes vazios, `.` ou `..`; é sempre relativo
+/// à raiz e sua forma textual é `/` ou `/a/b`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct VfsPath {
+    components: Vec<String>,
+}
+
+/// Valida um nome de entrada (um único componente de caminho)
+pub fn validate_name(name: &str) -> Result<()> {
+    let invalid = |message: &str| Err(DiscoError::InvalidPath(format!("{}: '{}'", message, name)));
+
+    if name.is_empty() {
+        return invalid("Name cannot be empty");
+    }
+    if name == "." || name == ".." {
+        return invalid("Reserved name");
+    }
+    if name.contains('/') || name.contains('\0') {
+        return invalid("Name contains an illegal character");
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return invalid("Name is too long");
+    }
+    Ok(())
+}
+
+impl VfsPath {
+    /// Caminho da raiz (`/`)
+    pub fn root() -> Self {
+        VfsPath::default()
+    }
+
+    /// Interpreta um caminho absoluto (o `/` inicial é opcional)
+    pub fn parse(path: &str) -> Result<Self> {
+        VfsPath::root().join(path)
+    }
+
+    /// Resolve `path` a partir deste caminho; caminhos com `/` inicial partem da raiz
+    ///
+    /// Componentes vazios e `.` são descartados e `..` sobe um nível (na raiz
+    /// permanece na raiz, como no POSIX).
+    pub fn join(&self, path: &str) -> Result<Self> {
+        let mut components = if path.starts_with('/') {
+            Vec::new()
+        } else {
+            self.components.clone()
+        };
+
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                name => {
+                    validate_name(name)?;
+                    components.push(name.to_string());
+                }
+            }
+        }
+
+        Ok(VfsPath { components })
+    }
+
+    /// Caminho de uma entrada filha, validando o nome
+    pub fn child(&self, name: &str) -> Result<Self> {
+        validate_name(name)?;
+        let mut components = self.components.clone();
+        components.push(name.to_string());
+        Ok(VfsPath { components })
+    }
+
+    /// Como `child`, para nomes vindos da própria árvore (já validados)
+    pub(crate) fn child_unchecked(&self, name: &str) -> Self {
+        let mut components = self.components.clone();
+        components.push(name.to_string());
+        VfsPath { components }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Iterador sobre os componentes, da raiz para a folha
+    pub fn components(&self) -> impl DoubleEndedIterator<Item = &str> + ExactSizeIterator {
+        self.components.iter().map(String::as_str)
+    }
+
+    /// Último componente; `None` para a raiz
+    pub fn file_name(&self) -> Option<&str> {
+        self.components.last().map(String::as_str)
+    }
+
+    /// Diretório que contém este caminho; `None` para a raiz
+    pub fn parent(&self) -> Option<Self> {
+        let (_, parent) = self.components.split_last()?;
+        Some(VfsPath {
+            components: parent.to_vec(),
+        })
+    }
+
+    /// Verdadeiro se `prefix` for este caminho ou um de seus ancestrais
+    pub fn starts_with(&self, prefix: &VfsPath) -> bool {
+        self.components.starts_with(&prefix.components)
+    }
+
+    /// Componentes que sobram após remover `prefix`
+    pub fn strip_prefix(&self, prefix: &VfsPath) -> Option<Vec<&str>> {
+        self.components
+            .strip_prefix(prefix.components.as_slice())
+            .map(|rest| rest.iter().map(String::as_str).collect())
+    }
+}
+
+impl fmt::Display for VfsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}", self.components.join("/"))
+    }
+}
+
+impl From<VfsPath> for String {
+    fn from(path: VfsPath) -> Self {
+        path.to_string()
+    }
+}
+
+impl TryFrom<String> for VfsPath {
+    type Error = DiscoError;
+
+    fn try_from(path: String) -> Result<Self> {
+        VfsPath::parse(&path)
+    }
+}
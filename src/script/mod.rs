@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead},
+};
+
+use crate::{
+    shell::{execute, report_error, split_command_line, OutputFormat},
+    vfs::Vfs,
+};
+
+/// Falha de um script: linha (a partir de 1), comando e erro
+#[derive(Debug)]
+pub struct ScriptFailure {
+    pub line: usize,
+    pub command: String,
+    pub error: io::Error,
+}
+
+impl fmt::Display for ScriptFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.command, self.error)
+    }
+}
+
+/// Variáveis de um script (`NOME=valor`), usadas como `$NOME` ou `${NOME}`
+pub type Variables = HashMap<String, String>;
+
+/// Interpreta `NOME=valor` como atribuição de variável
+pub fn parse_assignment(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once('=')?;
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if valid_start && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some((name, value))
+    } else {
+        None
+    }
+}
+
+/// Substitui `$NOME` e `${NOME}` pelos valores das variáveis
+///
+/// Trechos entre aspas simples não são expandidos e `\$` produz um `$` literal.
+/// Variáveis não definidas são um erro, para que um caminho incompleto nunca
+/// chegue a um comando.
+pub fn expand_variables(line: &str, variables: &Variables) -> io::Result<String> {
+    let mut expanded = String::new();
+    let mut chars = line.chars().peekable();
+    let mut in_single_quotes = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_single_quotes = !in_single_quotes;
+                expanded.push(c);
+            }
+            '\\' if !in_single_quotes && chars.peek() == Some(&'$') => {
+                expanded.push('$');
+                chars.next();
+            }
+            '$' if !in_single_quotes => {
+                let braced = chars.next_if_eq(&'{').is_some();
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                if braced && chars.next() != Some('}') {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Unterminated variable reference",
+                    ));
+                }
+                if name.is_empty() {
+                    if braced {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Empty variable name",
+                        ));
+                    }
+                    expanded.push('$');
+                    continue;
+                }
+                let value = variables.get(&name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Undefined variable: {}", name),
+                    )
+                })?;
+                expanded.push_str(value);
+            }
+            _ => expanded.push(c),
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Executa um script de comandos sobre uma imagem já aberta
+///
+/// Cada linha é um comando da CLI; linhas vazias e iniciadas por `#` são
+/// ignoradas. Como no `set -e` do shell, a primeira falha interrompe o script;
+/// `set +e` passa a apenas registrar as falhas e `set -e` volta ao padrão. Com
+/// `set +e` o resultado traz a última falha, se houver. O estado não é gravado
+/// aqui: o chamador decide quando chamar `sync`.
+pub fn run_script(
+    vfs: &mut Vfs,
+    input: impl BufRead,
+    variables: &mut Variables,
+    format: OutputFormat,
+) -> Result<(), ScriptFailure> {
+    let mut stop_on_error = true;
+    let mut last_failure = None;
+
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let failure = |command: &str, error: io::Error| ScriptFailure {
+            line: line_number,
+            command: command.to_string(),
+            error,
+        };
+
+        let line = line.map_err(|e| failure("", e))?;
+        let command = line.trim();
+        if command.is_empty() || command.starts_with('#') {
+            continue;
+        }
+
+        let result = match command {
+            "set -e" => {
+                stop_on_error = true;
+                Ok(())
+            }
+            "set +e" => {
+                stop_on_error = false;
+                Ok(())
+            }
+            _ => run_line(vfs, command, variables, format),
+        };
+
+        if let Err(error) = result {
+            let failure = failure(command, error);
+            eprintln!("Falha na linha {}: {}", failure.line, failure.command);
+            if stop_on_error {
+                return Err(failure);
+            }
+            last_failure = Some(failure);
+        }
+    }
+
+    last_failure.map_or(Ok(()), Err)
+}
+
+/// Expande as variáveis e executa uma linha (atribuição ou comando)
+fn run_line(
+    vfs: &mut Vfs,
+    command: &str,
+    variables: &mut Variables,
+    format: OutputFormat,
+) -> io::Result<()> {
+    let split = |text: &str, variables: &Variables| {
+        expand_variables(text, variables)
+            .and_then(|expanded| split_command_line(&expanded))
+            .inspect_err(|e| {
+                let name = command.split_whitespace().next().unwrap_or_default();
+                report_error(format, name, "Erro", e)
+            })
+    };
+
+    if let Some((name, value)) = parse_assignment(command) {
+        let value = split(value, variables)?.join(" ");
+        variables.insert(name.to_string(), value);
+        return Ok(());
+    }
+
+    let args = split(command, variables)?;
+    execute(vfs, &args, format)
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use log::debug;
+
+use crate::{
+    cache::CacheStats,
+    directory::{read_only_error, release_blocks, write_new_blocks, RemovalReport},
+    error::{DiscoError, Result},
+    filesystem::FileSystem,
+    versioning::replace_contents,
+    vfs::{DirectoryEntry, Stat, Vfs},
+};
+
+/// Handle de um `Vfs` compartilhado entre threads
+///
+/// Clonar o handle é barato e todos os clones operam sobre a mesma imagem. A
+/// hierarquia fica atrás de uma trava de leitura e escrita, mantida em escrita só
+/// enquanto ela é alterada, e o mapa de blocos tem a sua trava dentro do
+/// `BlockManager`. Leituras não travam nada além da hierarquia para leitura, então
+/// rodam em paralelo entre si e com a gravação de blocos. Quem altera um arquivo
+/// trava também o arquivo, e duas escritas no mesmo arquivo acontecem uma depois
+/// da outra.
+///
+/// As travas de arquivo são sempre obtidas antes da trava da hierarquia, em
+/// ordem de caminho, então as operações não se bloqueiam mutuamente.
+#[derive(Clone)]
+pub struct SharedVfs {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    vfs: RwLock<Vfs>,
+    files: Mutex<HashMap<String, Arc<Mutex<()>>>>, // Trava de cada arquivo, pelo caminho
+}
+
+impl SharedVfs {
+    /// Passa a compartilhar o `Vfs`; imagens montadas nele não são suportadas
+    pub fn new(vfs: Vfs) -> io::Result<Self> {
+        if vfs.has_mounts() {
+            return Err(DiscoError::Busy(
+                "Cannot share a filesystem with mounted images".to_string(),
+            )
+            .into());
+        }
+        Ok(SharedVfs {
+            inner: Arc::new(Shared {
+                vfs: RwLock::new(vfs),
+                files: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Devolve o `Vfs`, se este for o último handle
+    pub fn into_inner(self) -> Option<Vfs> {
+        let shared = Arc::into_inner(self.inner)?;
+        Some(
+            shared
+                .vfs
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    fn vfs(&self) -> RwLockReadGuard<'_, Vfs> {
+        self.inner
+            .vfs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn vfs_mut(&self) -> RwLockWriteGuard<'_, Vfs> {
+        self.inner
+            .vfs
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Trava do arquivo em `path` (absoluto), criada no primeiro uso
+    fn file_lock(&self, path: &str) -> Arc<Mutex<()>> {
+        let mut files = self
+            .inner
+            .files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !files.contains_key(path) {
+            // Descarta as travas que ninguém está usando
+            files.retain(|_, lock| Arc::strong_count(lock) > 1);
+        }
+        files.entry(path.to_string()).or_default().clone()
+    }
+
+    /// Executa `operation` com a hierarquia e os arquivos em `paths` (e abaixo
+    /// deles) travados
+    fn with_files_locked<T>(
+        &self,
+        paths: &[&str],
+        operation: impl FnOnce(&mut Vfs) -> io::Result<T>,
+    ) -> io::Result<T> {
+        loop {
+            let files = files_under(&self.vfs(), paths)?;
+            let locks: Vec<_> = files.iter().map(|file| self.file_lock(file)).collect();
+            let _files: Vec<_> = locks
+                .iter()
+                .map(|lock| lock.lock().unwrap_or_else(PoisonError::into_inner))
+                .collect();
+            let mut vfs = self.vfs_mut();
+            // Um arquivo criado ou removido antes da trava da hierarquia ficaria de fora
+            if files_under(&vfs, paths)? == files {
+                return operation(&mut vfs);
+            }
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.vfs().is_read_only()
+    }
+
+    /// Cria um arquivo vazio
+    pub fn create(&self, path: &str, permissions: &str) -> io::Result<()> {
+        self.vfs_mut().create(path, permissions)
+    }
+
+    /// Lê um arquivo; os blocos antigos de uma escrita só são liberados depois da
+    /// troca, que espera as leituras em andamento
+    pub fn read(&self, path: &str) -> io::Result<String> {
+        self.vfs().read(path)
+    }
+
+    /// Substitui o conteúdo de um arquivo existente
+    ///
+    /// Os blocos novos são gravados com a hierarquia travada só para leitura; ela
+    /// é travada para escrita apenas para trocar a lista de blocos do arquivo.
+    pub fn write(&self, path: &str, data: &str) -> io::Result<()> {
+        let path = self.vfs().resolve(path)?.to_string();
+        let lock = self.file_lock(&path);
+        let _file = lock.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let blocks = {
+            let vfs = self.vfs();
+            vfs.check_writable()?;
+            let metadata = vfs
+                .metadata_store()
+                .get_file_metadata(&path)
+                .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?;
+            if metadata.archive.is_some() {
+                return Err(read_only_error().into());
+            }
+            write_new_blocks(data.as_bytes(), vfs.block_manager())?
+        };
+
+        let released_blocks = {
+            let mut vfs = self.vfs_mut();
+            let Some(metadata) = vfs.metadata_store().get_file_metadata(&path) else {
+                // O arquivo saiu do lugar enquanto os blocos eram gravados
+                release_blocks(&blocks, vfs.block_manager());
+                return Err(DiscoError::NotFound("File not found".to_string()).into());
+            };
+            let mut updated_metadata = metadata.clone();
+            let policy = vfs.versioning();
+            let released_blocks = replace_contents(
+                &mut updated_metadata,
+                blocks,
+                data.len() as u64,
+                policy.as_ref(),
+            );
+            vfs.metadata_store_mut()
+                .update_file_metadata(&path, updated_metadata);
+            released_blocks
+        };
+
+        // Depois da troca, nenhuma leitura usa mais os blocos antigos
+        let vfs = self.vfs();
+        for block_index in released_blocks {
+            vfs.block_manager().free_block(block_index)?;
+        }
+        debug!("Dados escritos no arquivo '{}'", path);
+        Ok(())
+    }
+
+    pub fn mkdir(&self, path: &str) -> io::Result<()> {
+        self.vfs_mut().mkdir(path)
+    }
+
+    pub fn ls(&self, path: &str) -> io::Result<Vec<DirectoryEntry>> {
+        self.vfs().ls(path)
+    }
+
+    pub fn stat(&self, path: &str) -> io::Result<Stat> {
+        self.vfs().stat(path)
+    }
+
+    /// Remove um arquivo ou, com `recursive`, um diretório e todo o seu conteúdo
+    pub fn rm(&self, path: &str, recursive: bool) -> io::Result<RemovalReport> {
+        self.with_files_locked(&[path], |vfs| vfs.rm(path, recursive))
+    }
+
+    /// Renomeia ou move um arquivo ou diretório
+    pub fn rename(&self, source: &str, destination: &str) -> io::Result<()> {
+        self.with_files_locked(&[source, destination], |vfs| {
+            vfs.rename(source, destination)
+        })
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.vfs().cache_stats()
+    }
+
+    /// Grava o estado, como `Vfs::sync`
+    pub fn sync(&self) -> io::Result<()> {
+        self.vfs().sync()
+    }
+}
+
+impl FileSystem for SharedVfs {
+    fn create(&mut self, path: &str, permissions: &str) -> Result<()> {
+        Ok(SharedVfs::create(self, path, permissions)?)
+    }
+
+    fn read(&self, path: &str) -> Result<String> {
+        Ok(SharedVfs::read(self, path)?)
+    }
+
+    fn write(&mut self, path: &str, data: &str) -> Result<()> {
+        Ok(SharedVfs::write(self, path, data)?)
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> Result<()> {
+        SharedVfs::rm(self, path, recursive)?;
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<()> {
+        Ok(SharedVfs::mkdir(self, path)?)
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<DirectoryEntry>> {
+        Ok(SharedVfs::ls(self, path)?)
+    }
+
+    fn stat(&self, path: &str) -> Result<Stat> {
+        Ok(SharedVfs::stat(self, path)?)
+    }
+
+    fn rename(&mut self, source: &str, destination: &str) -> Result<()> {
+        Ok(SharedVfs::rename(self, source, destination)?)
+    }
+}
+
+/// Arquivos em `paths` e abaixo deles, em ordem de caminho
+fn files_under(vfs: &Vfs, paths: &[&str]) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = vfs.resolve(path)?.to_string();
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        files.extend(
+            vfs.metadata_store()
+                .paths()
+                .filter(|file| *file == path || file.starts_with(&prefix))
+                .map(str::to_string),
+        );
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
@@ -0,0 +1,931 @@
+use std::{
+    cell::Cell,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    versioning::{format_max_age, parse_max_age, RetentionPolicy},
+    vfs::{EntryKind, Vfs},
+};
+
+pub const HISTORY_FILE: &str = "shell_history"; // Histórico do shell, no diretório de estado
+
+/// Descrição de um comando aceito por `execute`, usada nos textos de ajuda
+#[derive(Debug, Clone, Copy)]
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub usage: &'static str,
+    pub summary: &'static str,
+}
+
+impl CommandHelp {
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    /// Ajuda detalhada do comando: uso, apelidos e descrição
+    pub fn detailed(&self) -> String {
+        let mut text = format!("Uso: {}\n\n{}", self.usage, self.summary);
+        if !self.aliases.is_empty() {
+            text.push_str(&format!(
+                "\n\nTambém disponível como: {}",
+                self.aliases.join(", ")
+            ));
+        }
+        text
+    }
+}
+
+/// Comandos aceitos por `execute`
+pub const COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        name: "create",
+        aliases: &[],
+        usage: "create <file_name> <permissions>",
+        summary: "Cria um arquivo vazio com as permissões indicadas (ex.: rw-r--r--)",
+    },
+    CommandHelp {
+        name: "write",
+        aliases: &[],
+        usage: "write <file_name> <data>",
+        summary: "Substitui o conteúdo de um arquivo existente",
+    },
+    CommandHelp {
+        name: "read",
+        aliases: &[],
+        usage: "read [--version <n>] <file_name>",
+        summary: "Exibe o conteúdo de um arquivo ou, com --version, o de uma versão anterior",
+    },
+    CommandHelp {
+        name: "versions",
+        aliases: &[],
+        usage: "versions <file_name>",
+        summary: "Lista as versões anteriores de um arquivo",
+    },
+    CommandHelp {
+        name: "restore",
+        aliases: &[],
+        usage: "restore <file_name> <n>",
+        summary: "Volta um arquivo ao conteúdo da versão indicada",
+    },
+    CommandHelp {
+        name: "metadata",
+        aliases: &["stat"],
+        usage: "metadata <path>",
+        summary: "Exibe os metadados de um arquivo ou diretório",
+    },
+    CommandHelp {
+        name: "remove",
+        aliases: &[],
+        usage: "remove <file_name>",
+        summary: "Remove um arquivo",
+    },
+    CommandHelp {
+        name: "mkdir",
+        aliases: &[],
+        usage: "mkdir <directory_name>",
+        summary: "Cria um diretório",
+    },
+    CommandHelp {
+        name: "ls",
+        aliases: &[],
+        usage: "ls [<path>]",
+        summary: "Lista um diretório (por padrão, o diretório atual)",
+    },
+    CommandHelp {
+        name: "cd",
+        aliases: &[],
+        usage: "cd <directory_path>",
+        summary: "Muda o diretório atual, que é lembrado entre execuções",
+    },
+    CommandHelp {
+        name: "rm",
+        aliases: &[],
+        usage: "rm [-r] <path>",
+        summary: "Remove um arquivo ou, com -r, um diretório e todo o seu conteúdo",
+    },
+    CommandHelp {
+        name: "rmdir",
+        aliases: &[],
+        usage: "rmdir [-r] <directory_path>",
+        summary: "Remove um diretório vazio ou, com -r, também o seu conteúdo",
+    },
+    CommandHelp {
+        name: "mv",
+        aliases: &[],
+        usage: "mv <source> <destination>",
+        summary: "Renomeia ou move um arquivo ou diretório",
+    },
+    CommandHelp {
+        name: "cp",
+        aliases: &[],
+        usage: "cp [-r] [--reflink] <source> <destination>",
+        summary: "Copia um arquivo ou, com -r, um diretório; com --reflink os blocos são compartilhados",
+    },
+    CommandHelp {
+        name: "mount-tar",
+        aliases: &[],
+        usage: "mount-tar <archive.tar> <directory_name>",
+        summary: "Monta um arquivo tar, somente leitura, como subdiretório do diretório atual",
+    },
+    CommandHelp {
+        name: "mount",
+        aliases: &[],
+        usage: "mount [<image> <directory>]",
+        summary: "Monta outra imagem em um diretório vazio; sem argumentos, lista as montagens",
+    },
+    CommandHelp {
+        name: "umount",
+        aliases: &[],
+        usage: "umount <directory>",
+        summary: "Desmonta a imagem montada no diretório, gravando o estado dela",
+    },
+    CommandHelp {
+        name: "df",
+        aliases: &[],
+        usage: "df",
+        summary: "Exibe a ocupação dos blocos e os totais de arquivos e diretórios",
+    },
+    CommandHelp {
+        name: "fsck",
+        aliases: &[],
+        usage: "fsck",
+        summary: "Verifica a consistência da árvore, do índice e do mapa de blocos, sem corrigir nada",
+    },
+    CommandHelp {
+        name: "snapshot",
+        aliases: &[],
+        usage: "snapshot <create|delete|rollback> <name> | snapshot list",
+        summary: "Cria, lista, apaga ou restaura snapshots de todo o sistema de arquivos",
+    },
+    CommandHelp {
+        name: "versioning",
+        aliases: &[],
+        usage: "versioning [on [--max-versions <n>] [--max-age <age>] | off]",
+        summary: "Liga ou desliga as versões dos arquivos, com a idade em s, m, h ou d (ex.: 30d); sem argumentos, exibe a política",
+    },
+    CommandHelp {
+        name: "sync",
+        aliases: &[],
+        usage: "sync",
+        summary: "Grava a hierarquia, o índice e o diretório atual no diretório de estado",
+    },
+];
+
+/// Procura um comando pelo nome ou apelido
+pub fn command_help(name: &str) -> Option<&'static CommandHelp> {
+    COMMANDS.iter().find(|command| command.matches(name))
+}
+
+/// Lista de comandos, um por linha, com o uso e a descrição resumida
+pub fn commands_usage<'a>(commands: impl IntoIterator<Item = &'a CommandHelp>) -> String {
+    let commands: Vec<&CommandHelp> = commands.into_iter().collect();
+    let width = commands.iter().map(|c| c.usage.len()).max().unwrap_or(0);
+    commands
+        .iter()
+        .map(|c| format!("  {:width$}  {}", c.usage, c.summary, width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Formato da saída dos comandos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text, // Mensagens em português, para pessoas
+    Json, // Um objeto JSON por comando, para outros programas
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> io::Result<Self> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown output format: {} (expected text or json)", value),
+            )),
+        }
+    }
+}
+
+/// Objeto JSON de um comando bem-sucedido: `{"ok": true, "command": ..., "result": ...}`
+///
+/// Comandos que só alteram o sistema de arquivos têm `result` nulo.
+pub fn result_envelope(command: &str, result: Value) -> Value {
+    json!({ "ok": true, "command": command, "result": result })
+}
+
+/// Objeto JSON de um comando que falhou:
+/// `{"ok": false, "command": ..., "error": {"kind": ..., "message": ...}}`
+pub fn error_envelope(command: &str, error: &io::Error) -> Value {
+    json!({
+        "ok": false,
+        "command": command,
+        "error": {
+            "kind": format!("{:?}", error.kind()),
+            "message": error.to_string(),
+        },
+    })
+}
+
+/// Exibe um erro no formato escolhido
+///
+/// Em JSON o erro vai para a saída padrão, como os demais resultados, no objeto
+/// de `error_envelope`.
+pub fn report_error(format: OutputFormat, command: &str, context: &str, error: &io::Error) {
+    match format {
+        OutputFormat::Text => eprintln!("{}: {}", context, error),
+        OutputFormat::Json => println!("{}", error_envelope(command, error)),
+    }
+}
+
+/// Saída de um comando em execução
+///
+/// Em JSON, o objeto do comando fica guardado até ele terminar, para que cada
+/// comando emita exatamente um.
+struct Output<'a> {
+    format: OutputFormat,
+    command: &'a str,
+    json: Cell<Option<Value>>,
+}
+
+impl Output<'_> {
+    /// Erro de uso
+    fn usage(&self) -> io::Result<()> {
+        let usage = command_help(self.command).map_or("", |help| help.usage);
+        let error = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Usage: {}", usage),
+        );
+        match self.format {
+            OutputFormat::Text => println!("Uso: {}", usage),
+            OutputFormat::Json => self.json.set(Some(error_envelope(self.command, &error))),
+        }
+        Err(error)
+    }
+
+    /// Exibe o erro com o contexto da operação e o devolve ao chamador
+    fn fail(&self, context: &str, error: io::Error) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => report_error(self.format, self.command, context, &error),
+            OutputFormat::Json => self.json.set(Some(error_envelope(self.command, &error))),
+        }
+        Err(error)
+    }
+
+    /// Resultado de um comando que só altera o sistema de arquivos
+    fn done(&self, context: &str, result: io::Result<()>) -> io::Result<()> {
+        self.show(context, result, |_| Value::Null, |_| {})
+    }
+
+    /// Resultado de um comando de consulta, em texto ou em JSON
+    fn show<T>(
+        &self,
+        context: &str,
+        result: io::Result<T>,
+        to_json: impl FnOnce(&T) -> Value,
+        to_text: impl FnOnce(&T),
+    ) -> io::Result<()> {
+        match result {
+            Ok(value) => {
+                match self.format {
+                    OutputFormat::Text => to_text(&value),
+                    OutputFormat::Json => self
+                        .json
+                        .set(Some(result_envelope(self.command, to_json(&value)))),
+                }
+                Ok(())
+            }
+            Err(e) => self.fail(context, e),
+        }
+    }
+}
+
+/// Executa um comando (nome seguido dos argumentos) sobre o sistema de arquivos
+///
+/// Mensagens de uso e de erro são exibidas aqui, no formato pedido; o resultado
+/// só indica ao chamador se o comando falhou.
+pub fn execute(vfs: &mut Vfs, args: &[String], format: OutputFormat) -> io::Result<()> {
+    let (json, result) = execute_command(vfs, args, format);
+    if let Some(json) = json {
+        println!("{}", json);
+    }
+    result
+}
+
+/// Executa um comando e devolve o objeto JSON que ele emitiria, sem exibi-lo
+///
+/// Um `fsck` que encontra problemas é bem-sucedido no objeto (`"ok": true`, com
+/// os problemas no resultado), mas o resultado da execução é um erro, para que
+/// a CLI saia com código diferente de zero.
+pub fn execute_json(vfs: &mut Vfs, args: &[String]) -> (Option<Value>, io::Result<()>) {
+    execute_command(vfs, args, OutputFormat::Json)
+}
+
+fn execute_command(
+    vfs: &mut Vfs,
+    args: &[String],
+    format: OutputFormat,
+) -> (Option<Value>, io::Result<()>) {
+    let Some(command) = args.first() else {
+        return (None, Ok(()));
+    };
+    let output = Output {
+        format,
+        command,
+        json: Cell::new(None),
+    };
+    let result = run_command(vfs, &output, &args[1..]);
+    (output.json.into_inner(), result)
+}
+
+fn run_command(vfs: &mut Vfs, output: &Output, args: &[String]) -> io::Result<()> {
+    let command = output.command;
+    match command {
+        "create" => {
+            if args.len() < 2 {
+                return output.usage();
+            }
+            output.done("Erro ao criar arquivo", vfs.create(&args[0], &args[1]))
+        }
+        "read" => {
+            let Some((version, paths)) = split_version_option(args) else {
+                return output.usage();
+            };
+            let Some(&file) = paths.first() else {
+                return output.usage();
+            };
+            let content = match version {
+                Some(number) => vfs.read_version(file, number),
+                None => vfs.read(file),
+            };
+            let content = content.and_then(|content| {
+                let path = vfs.resolve(file)?;
+                let metadata = vfs.entry_metadata(file)?;
+                Ok((path, content, json!(metadata)))
+            });
+            output.show(
+                "Erro ao ler o arquivo",
+                content,
+                |(path, content, metadata)| {
+                    let mut result =
+                        json!({ "path": path, "content": content, "metadata": metadata });
+                    if let Some(number) = version {
+                        result["version"] = json!(number);
+                    }
+                    result
+                },
+                |(_, content, _)| match version {
+                    Some(number) => println!(
+                        "Conteúdo do arquivo '{}' na versão {}:\n{}",
+                        file, number, content
+                    ),
+                    None => println!("Conteúdo do arquivo '{}':\n{}", file, content),
+                },
+            )
+        }
+        "versions" => {
+            if args.is_empty() {
+                return output.usage();
+            }
+            let versions = vfs
+                .versions(&args[0])
+                .and_then(|versions| Ok((vfs.resolve(&args[0])?, versions)));
+            output.show(
+                "Erro ao listar as versões",
+                versions,
+                |(path, versions)| json!({ "path": path, "versions": versions }),
+                |(path, versions)| {
+                    println!("Versões do arquivo '{}':", path);
+                    for version in versions {
+                        println!(
+                            "  {}  {}  {} bytes",
+                            version.number, version.modified_at, version.size
+                        );
+                    }
+                    if versions.is_empty() {
+                        println!("  nenhuma");
+                    }
+                },
+            )
+        }
+        "restore" => {
+            let number = args.get(1).and_then(|number| number.parse().ok());
+            let (Some(file), Some(number)) = (args.first(), number) else {
+                return output.usage();
+            };
+            output.done(
+                "Erro ao restaurar a versão",
+                vfs.restore_version(file, number),
+            )
+        }
+        "write" => {
+            if args.len() < 2 {
+                return output.usage();
+            }
+            output.done("Erro ao escrever no arquivo", vfs.write(&args[0], &args[1]))
+        }
+        "metadata" | "stat" => {
+            if args.is_empty() {
+                return output.usage();
+            }
+            let stat = vfs.stat(&args[0]).and_then(|stat| {
+                let metadata = vfs.entry_metadata(&args[0])?;
+                Ok((stat, json!(metadata)))
+            });
+            output.show(
+                "Erro ao obter metadados",
+                stat,
+                |(stat, metadata)| {
+                    json!({ "path": stat.path, "kind": stat.kind, "metadata": metadata })
+                },
+                |(stat, _)| {
+                    let kind = match stat.kind {
+                        EntryKind::File => "arquivo",
+                        EntryKind::Directory => "diretório",
+                    };
+                    println!("Caminho: {}", stat.path);
+                    println!("Tipo: {}", kind);
+                    println!("Tamanho: {}", stat.size);
+                    println!("Permissões: {}", stat.permissions);
+                    println!("Criado em: {}", stat.created_at);
+                    println!("Modificado em: {}", stat.modified_at);
+                    println!("Blocos: {:?}", stat.block_indices);
+                },
+            )
+        }
+        "remove" => {
+            if args.is_empty() {
+                return output.usage();
+            }
+            output.done("Erro ao remover", vfs.rm(&args[0], false).map(|_| ()))
+        }
+        "mkdir" => {
+            if args.is_empty() {
+                return output.usage();
+            }
+            output.done("Erro ao criar diretório", vfs.mkdir(&args[0]))
+        }
+        "ls" => {
+            let path = args.first().map_or(".", String::as_str);
+            let listing = vfs.resolve(path).and_then(|resolved| {
+                let entries = vfs
+                    .ls(path)?
+                    .into_iter()
+                    .map(|entry| {
+                        let child = resolved.child(&entry.name)?.to_string();
+                        let metadata = json!(vfs.entry_metadata(&child)?);
+                        Ok((entry, metadata))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                Ok((resolved, entries))
+            });
+            output.show(
+                "Erro ao listar diretório",
+                listing,
+                |(resolved, entries)| {
+                    let entries: Vec<Value> = entries
+                        .iter()
+                        .map(|(entry, metadata)| {
+                            json!({ "name": entry.name, "kind": entry.kind, "metadata": metadata })
+                        })
+                        .collect();
+                    json!({ "path": resolved, "entries": entries })
+                },
+                |(resolved, entries)| {
+                    println!("Conteúdo do diretório '{}':", resolved);
+                    for (entry, _) in entries {
+                        match entry.kind {
+                            EntryKind::File => println!("Arquivo: {}", entry.name),
+                            EntryKind::Directory => println!("Subdiretório: {}", entry.name),
+                        }
+                    }
+                },
+            )
+        }
+        "rmdir" | "rm" => {
+            let recursive = args.iter().any(|a| a == "-r");
+            let targets: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+            if targets.is_empty() {
+                return output.usage();
+            }
+            let result = if command == "rmdir" && !recursive {
+                vfs.rmdir(targets[0])
+            } else {
+                vfs.rm(targets[0], recursive).map(|_| ())
+            };
+            output.done("Erro ao remover", result)
+        }
+        "cd" => {
+            if args.is_empty() {
+                return output.usage();
+            }
+            let result = vfs.cd(&args[0]).map(|_| vfs.pwd());
+            output.show(
+                "Erro ao mudar de diretório",
+                result,
+                |pwd| json!({ "path": pwd }),
+                |pwd| println!("Diretório atual: {}", pwd),
+            )
+        }
+        "mv" => {
+            if args.len() < 2 {
+                return output.usage();
+            }
+            output.done("Erro ao mover", vfs.rename(&args[0], &args[1]))
+        }
+        "cp" => {
+            let recursive = args.iter().any(|a| a == "-r");
+            let reflink = args.iter().any(|a| a == "--reflink");
+            let paths: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+            if paths.len() < 2 {
+                return output.usage();
+            }
+            output.done(
+                "Erro ao copiar",
+                vfs.copy(paths[0], paths[1], recursive, reflink),
+            )
+        }
+        "df" => output.show(
+            "Erro ao calcular a ocupação do disco",
+            Ok(vfs.df()),
+            |usage| json!(usage),
+            |usage| {
+                println!("Blocos de {} bytes", usage.block_size);
+                println!(
+                    "Usados: {} de {} ({} livres, {} compartilhados)",
+                    usage.used_blocks, usage.total_blocks, usage.free_blocks, usage.shared_blocks
+                );
+                println!(
+                    "Arquivos: {} ({} bytes)  Diretórios: {}",
+                    usage.files, usage.bytes, usage.directories
+                );
+            },
+        ),
+        "fsck" => {
+            let report = vfs.fsck();
+            let problems = report.as_ref().map_or(0, |report| report.problems.len());
+            output.show(
+                "Erro ao verificar o sistema de arquivos",
+                report,
+                |report| json!(report),
+                |report| {
+                    println!(
+                        "Verificados: {} arquivo(s), {} diretório(s), {} bloco(s) em uso",
+                        report.files, report.directories, report.blocks_in_use
+                    );
+                    for problem in &report.problems {
+                        println!("  problema: {}", problem);
+                    }
+                    if report.is_clean() {
+                        println!("Nenhum problema encontrado");
+                    }
+                },
+            )?;
+            // O relatório já foi exibido; o erro só sinaliza a inconsistência
+            if problems > 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Filesystem has {} problem(s)", problems),
+                ));
+            }
+            Ok(())
+        }
+        "snapshot" => match (args.first().map(String::as_str), args.get(1)) {
+            (Some("list"), _) => output.show(
+                "Erro ao listar snapshots",
+                Ok(vfs.snapshots()),
+                |snapshots| json!({ "snapshots": snapshots }),
+                |snapshots| {
+                    for snapshot in snapshots {
+                        println!(
+                            "{}  {}  {} arquivo(s), {} bloco(s), {} só dela",
+                            snapshot.name,
+                            snapshot.created_at,
+                            snapshot.files,
+                            snapshot.blocks,
+                            snapshot.exclusive_blocks
+                        );
+                    }
+                },
+            ),
+            (Some("create"), Some(name)) => {
+                output.done("Erro ao criar snapshot", vfs.snapshot_create(name))
+            }
+            (Some("delete"), Some(name)) => {
+                output.done("Erro ao apagar snapshot", vfs.snapshot_delete(name))
+            }
+            (Some("rollback"), Some(name)) => {
+                output.done("Erro ao restaurar snapshot", vfs.snapshot_rollback(name))
+            }
+            _ => output.usage(),
+        },
+        "versioning" => match args.first().map(String::as_str) {
+            None => output.show(
+                "Erro ao consultar o versionamento",
+                Ok(vfs.versioning()),
+                |policy| json!({ "enabled": policy.is_some(), "policy": policy }),
+                |policy| match policy {
+                    None => println!("Versionamento desligado"),
+                    Some(policy) => {
+                        let versions = policy
+                            .max_versions
+                            .map_or("todas as versões".to_string(), |max| {
+                                format!("até {} versão(ões)", max)
+                            });
+                        let age = policy
+                            .max_age
+                            .map_or("sem limite de idade".to_string(), |age| {
+                                format!("por até {}", format_max_age(age))
+                            });
+                        println!("Versionamento ligado: {}, {}", versions, age);
+                    }
+                },
+            ),
+            Some("off") if args.len() == 1 => {
+                output.done("Erro ao desligar o versionamento", vfs.set_versioning(None))
+            }
+            Some("on") => {
+                let mut policy = RetentionPolicy::default();
+                let mut options = args[1..].iter();
+                while let Some(option) = options.next() {
+                    match (option.as_str(), options.next()) {
+                        ("--max-versions", Some(value)) => match value.parse() {
+                            Ok(max) => policy.max_versions = Some(max),
+                            Err(_) => return output.usage(),
+                        },
+                        ("--max-age", Some(value)) => match parse_max_age(value) {
+                            Ok(age) => policy.max_age = Some(age),
+                            Err(e) => {
+                                return output.fail("Erro ao ligar o versionamento", e.into())
+                            }
+                        },
+                        _ => return output.usage(),
+                    }
+                }
+                output.done(
+                    "Erro ao ligar o versionamento",
+                    vfs.set_versioning(Some(policy)),
+                )
+            }
+            _ => output.usage(),
+        },
+        "sync" => output.done("Erro ao salvar o estado", vfs.sync()),
+        "mount-tar" => {
+            if args.len() < 2 {
+                return output.usage();
+            }
+            output.done(
+                "Erro ao montar arquivo tar",
+                vfs.mount_tar(&args[0], &args[1]),
+            )
+        }
+        "mount" if args.is_empty() => output.show(
+            "Erro ao listar montagens",
+            Ok(vfs.mounts()),
+            |mounts| json!({ "mounts": mounts }),
+            |mounts| {
+                for mount in mounts {
+                    println!("{} em {}", mount.image.display(), mount.path);
+                }
+            },
+        ),
+        "mount" => {
+            if args.len() < 2 {
+                return output.usage();
+            }
+            output.done("Erro ao montar imagem", vfs.mount(&args[0], &args[1]))
+        }
+        "umount" => {
+            if args.is_empty() {
+                return output.usage();
+            }
+            output.done("Erro ao desmontar imagem", vfs.umount(&args[0]))
+        }
+        _ => {
+            let error = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown command: {}", command),
+            );
+            match output.format {
+                OutputFormat::Text => {
+                    println!("Comando desconhecido: '{}'", command);
+                    Err(error)
+                }
+                OutputFormat::Json => output.fail("", error),
+            }
+        }
+    }
+}
+
+/// Separa a opção `--version <n>` dos demais argumentos; `None` se ela estiver incompleta
+fn split_version_option(args: &[String]) -> Option<(Option<u32>, Vec<&String>)> {
+    let mut version = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--version" {
+            version = Some(args.next()?.parse().ok()?);
+        } else {
+            rest.push(arg);
+        }
+    }
+    Some((version, rest))
+}
+
+/// Divide uma linha em argumentos, respeitando aspas simples e duplas
+pub fn split_command_line(line: &str) -> io::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                // Dentro de aspas duplas, `\` escapa o próximo caractere
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_argument = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_argument {
+                    args.push(std::mem::take(&mut current));
+                    in_argument = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_argument = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Unterminated quote",
+        ));
+    }
+    if in_argument {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Histórico de comandos do shell, persistido no diretório de estado
+pub struct History {
+    entries: Vec<String>,
+    path: Option<String>,
+}
+
+impl History {
+    /// Histórico vazio, apenas em memória
+    pub fn new() -> Self {
+        History {
+            entries: Vec::new(),
+            path: None,
+        }
+    }
+
+    /// Carrega o histórico salvo (se houver) e passa a gravar as novas entradas
+    pub fn load(path: &str) -> Self {
+        let entries = fs::read_to_string(path)
+            .map(|data| data.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        History {
+            entries,
+            path: Some(path.to_string()),
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, line: &str) -> io::Result<()> {
+        self.entries.push(line.to_string());
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Expande `!!` (último comando) e `!n` (n-ésima entrada, a partir de 1)
+    pub fn expand(&self, line: &str) -> io::Result<String> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "Event not found in history");
+        let Some(reference) = line.strip_prefix('!') else {
+            return Ok(line.to_string());
+        };
+        let entry = if reference == "!" {
+            self.entries.last()
+        } else {
+            let index: usize = reference.parse().map_err(|_| not_found())?;
+            index
+                .checked_sub(1)
+                .and_then(|index| self.entries.get(index))
+        };
+        entry.cloned().ok_or_else(not_found)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Executa o shell interativo até `exit`, `quit` ou o fim da entrada
+///
+/// O estado só é gravado no disco pelo comando `sync` e ao sair, e não a cada
+/// comando como na CLI. Somente leitura, nada é gravado ao sair.
+pub fn run_shell(vfs: &mut Vfs, input: impl BufRead, history: &mut History) -> io::Result<()> {
+    let result = read_commands(vfs, input, history);
+
+    // O estado é salvo em qualquer saída, inclusive quando a entrada falha
+    if vfs.is_read_only() {
+        return result;
+    }
+    let synced = vfs.sync();
+    result.and(synced)
+}
+
+/// Laço do shell: lê e executa os comandos até `exit` ou o fim da entrada
+fn read_commands(vfs: &mut Vfs, input: impl BufRead, history: &mut History) -> io::Result<()> {
+    let mut lines = input.lines();
+    loop {
+        print!("disco:{}> ", vfs.pwd());
+        io::stdout().flush()?;
+
+        let Some(line) = lines.next() else {
+            println!();
+            break;
+        };
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let line = match history.expand(line) {
+            Ok(expanded) => {
+                if expanded != line {
+                    println!("{}", expanded);
+                }
+                expanded
+            }
+            Err(e) => {
+                eprintln!("Erro: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = history.push(&line) {
+            // Sem o arquivo de histórico o shell continua, com o histórico em memória
+            eprintln!("Erro ao gravar o histórico: {}", e);
+        }
+
+        let args = match split_command_line(&line) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("Erro: {}", e);
+                continue;
+            }
+        };
+        match args.first().map(String::as_str) {
+            Some("exit" | "quit") => break,
+            Some("history") => {
+                for (index, entry) in history.entries().iter().enumerate() {
+                    println!("{:5}  {}", index + 1, entry);
+                }
+            }
+            Some("help") => match args.get(1) {
+                Some(name) => match command_help(name) {
+                    Some(help) => println!("{}", help.detailed()),
+                    None => eprintln!("Comando desconhecido: '{}'", name),
+                },
+                None => {
+                    println!("Comandos:\n{}", commands_usage(COMMANDS));
+                    println!("  history\n  !! | !<n>\n  help [<command>]\n  exit | quit");
+                }
+            },
+            _ => {
+                // O erro já foi exibido por `execute`; o shell segue em frente
+                let _ = execute(vfs, &args, OutputFormat::Text);
+            }
+        }
+    }
+    Ok(())
+}
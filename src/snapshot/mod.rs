@@ -0,0 +1,114 @@
+use std::{collections::BTreeMap, fs};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::{BlockManager, MetadataStore},
+    directory::{share_blocks, DirectoryTree},
+    error::Result,
+};
+
+pub const SNAPSHOTS_FILE: &str = "snapshots.json"; // Snapshots, no diretório de estado
+
+/// Estado congelado do sistema de arquivos: a árvore e o índice no momento da criação
+///
+/// Cada bloco dos arquivos da snapshot recebe uma referência a mais no mapa de
+/// blocos. Como as escritas sempre vão para blocos novos, o conteúdo congelado
+/// nunca é sobrescrito, e um bloco só volta a ficar livre quando nem o sistema de
+/// arquivos nem nenhuma snapshot o usam.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub created_at: String,
+    pub tree: DirectoryTree,
+    pub metadata_store: MetadataStore,
+}
+
+/// Resumo de uma snapshot, retornado por `Vfs::snapshots`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created_at: String,
+    pub files: usize,
+    pub blocks: usize,
+    pub exclusive_blocks: usize, // Usados só por ela, liberados ao apagá-la
+}
+
+impl Snapshot {
+    /// Congela uma cópia da árvore e do índice
+    pub fn new(tree: &DirectoryTree, metadata_store: &MetadataStore) -> Self {
+        Snapshot {
+            created_at: Utc::now().to_rfc3339(),
+            tree: tree.clone(),
+            metadata_store: metadata_store.clone(),
+        }
+    }
+
+    pub fn info(&self, name: &str, block_manager: &BlockManager) -> SnapshotInfo {
+        let mut references: BTreeMap<usize, usize> = BTreeMap::new();
+        for block in file_blocks(&self.metadata_store) {
+            *references.entry(block).or_default() += 1;
+        }
+        let block_refs = block_manager.block_refs();
+        let exclusive_blocks = references
+            .iter()
+            .filter(|&(&block, &count)| {
+                block_refs
+                    .get(block)
+                    .is_some_and(|&refs| usize::from(refs) == count)
+            })
+            .count();
+        SnapshotInfo {
+            name: name.to_string(),
+            created_at: self.created_at.clone(),
+            files: self.metadata_store.len(),
+            blocks: references.len(),
+            exclusive_blocks,
+        }
+    }
+}
+
+/// Blocos referenciados pelos arquivos do índice e pelas versões anteriores
+/// deles, uma vez por referência
+///
+/// Arquivos de um tar montado não têm blocos e ficam de fora.
+pub fn file_blocks(metadata_store: &MetadataStore) -> Vec<usize> {
+    metadata_store
+        .paths()
+        .filter_map(|path| metadata_store.get_file_metadata(path))
+        .filter(|metadata| metadata.archive.is_none())
+        .flat_map(|metadata| metadata.referenced_blocks())
+        .collect()
+}
+
+/// Acrescenta uma referência a cada bloco dos arquivos do índice
+///
+/// Se algum bloco não puder ser compartilhado, as referências já acrescentadas
+/// são desfeitas e o mapa de blocos fica como estava.
+pub fn reference_blocks(
+    metadata_store: &MetadataStore,
+    block_manager: &BlockManager,
+) -> Result<()> {
+    share_blocks(&file_blocks(metadata_store), block_manager)
+}
+
+/// Libera uma referência de cada bloco dos arquivos do índice
+pub fn release_references(
+    metadata_store: &MetadataStore,
+    block_manager: &BlockManager,
+) -> Result<()> {
+    file_blocks(metadata_store)
+        .into_iter()
+        .try_for_each(|block| block_manager.free_block(block))
+}
+
+pub fn load_snapshots(path: &str) -> Result<BTreeMap<String, Snapshot>> {
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+pub fn save_snapshots(snapshots: &BTreeMap<String, Snapshot>, path: &str) -> Result<()> {
+    let data = serde_json::to_string_pretty(snapshots)?;
+    fs::write(path, data)?;
+    Ok(())
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::MetadataStore,
+    directory::{read_only_error, update_directory_modified_time, DirectoryId, DirectoryTree},
+    error::{DiscoError, Result},
+    file::FileMetadata,
+    path::VfsPath,
+};
+
+pub const TAR_BLOCK_SIZE: u64 = 512; // Tamanho de cada registro do formato tar
+
+/// Localização do conteúdo de um arquivo dentro de um tar montado
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchiveExtent {
+    pub archive: String, // Caminho do arquivo tar no sistema hospedeiro
+    pub offset: u64,     // Posição do primeiro byte de dados dentro do tar
+}
+
+/// Entrada encontrada ao percorrer um arquivo tar
+#[derive(Debug, Clone)]
+pub struct TarEntry {
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// Lê um campo numérico do cabeçalho (octal ou base-256 do GNU)
+fn parse_numeric(field: &[u8]) -> Result<u64> {
+    if field.first().is_some_and(|&b| b & 0x80 != 0) {
+        let mut value = u64::from(field[0] & 0x7f);
+        for &byte in &field[1..] {
+            value = (value << 8) | u64::from(byte);
+        }
+        return Ok(value);
+    }
+
+    let text = String::from_utf8_lossy(field);
+    let digits = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8)
+        .map_err(|e| DiscoError::corrupt("Invalid numeric field in tar header", e))
+}
+
+/// Erro para uma entrada cujo tamanho não cabe no arquivo tar
+fn exceeds_archive() -> DiscoError {
+    DiscoError::Corrupt {
+        message: "Tar entry exceeds archive length".to_string(),
+        source: None,
+    }
+}
+
+/// Lê um campo de texto do cabeçalho, terminado em NUL
+fn parse_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Extrai o atributo `path` de um cabeçalho estendido PAX
+fn parse_pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines().find_map(|line| {
+        let (_, record) = line.split_once(' ')?;
+        record.strip_prefix("path=").map(str::to_string)
+    })
+}
+
+/// Percorre o arquivo tar e retorna o índice de entradas sem copiar os dados
+pub fn read_tar_index(archive_path: &str) -> Result<Vec<TarEntry>> {
+    let mut file = File::open(archive_path)?;
+    let archive_len = file.metadata()?.len();
+    let mut entries = Vec::new();
+    let mut header = [0u8; TAR_BLOCK_SIZE as usize];
+    let mut position = 0u64;
+    let mut long_name: Option<String> = None;
+
+    while position + TAR_BLOCK_SIZE <= archive_len {
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+
+        // Um bloco zerado marca o fim do arquivo
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let stored_checksum = parse_numeric(&header[148..156])?;
+        let checksum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { 32 } else { u64::from(b) })
+            .sum();
+        if checksum != stored_checksum {
+            return Err(DiscoError::Corrupt {
+                message: "Invalid tar header checksum".to_string(),
+                source: None,
+            });
+        }
+
+        let size = parse_numeric(&header[124..136])?;
+        let data_offset = position + TAR_BLOCK_SIZE;
+        // Um tamanho em base 256 pode ser qualquer `u64`: as contas não podem estourar
+        if data_offset
+            .checked_add(size)
+            .is_none_or(|end| end > archive_len)
+        {
+            return Err(exceeds_archive());
+        }
+        let type_flag = header[156];
+
+        match type_flag {
+            // Nome longo do GNU ou cabeçalho PAX: valem para a próxima entrada
+            b'L' | b'x' => {
+                let mut data = vec![0u8; size as usize];
+                file.read_exact(&mut data)?;
+                long_name = if type_flag == b'L' {
+                    Some(parse_string(&data))
+                } else {
+                    parse_pax_path(&data)
+                };
+            }
+            b'0' | b'\0' | b'5' => {
+                let path = long_name.take().unwrap_or_else(|| {
+                    let name = parse_string(&header[0..100]);
+                    let prefix = if &header[257..262] == b"ustar" {
+                        parse_string(&header[345..500])
+                    } else {
+                        String::new()
+                    };
+                    if prefix.is_empty() {
+                        name
+                    } else {
+                        format!("{}/{}", prefix, name)
+                    }
+                });
+                let is_dir = type_flag == b'5' || path.ends_with('/');
+                entries.push(TarEntry {
+                    path: path.trim_end_matches('/').to_string(),
+                    offset: data_offset,
+                    size,
+                    is_dir,
+                });
+            }
+            // Links, dispositivos e afins não são suportados e são ignorados
+            _ => {
+                long_name = None;
+            }
+        }
+
+        position = size
+            .div_ceil(TAR_BLOCK_SIZE)
+            .checked_mul(TAR_BLOCK_SIZE)
+            .and_then(|padded| data_offset.checked_add(padded))
+            .ok_or_else(exceeds_archive)?;
+    }
+
+    Ok(entries)
+}
+
+/// Lê o conteúdo de um arquivo diretamente do tar de origem
+pub fn read_archive_extent(extent: &ArchiveExtent, size: u64) -> Result<Vec<u8>> {
+    let mut file = File::open(&extent.archive)?;
+    file.seek(SeekFrom::Start(extent.offset))?;
+    let mut buffer = vec![0u8; size as usize];
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Garante a existência do caminho de diretórios somente leitura dentro da montagem
+fn ensure_mounted_directory(
+    tree: &mut DirectoryTree,
+    mount_point: DirectoryId,
+    components: &[&str],
+) -> DirectoryId {
+    let mut current = mount_point;
+    for component in components {
+        let existing = tree
+            .get(current)
+            .and_then(|directory| directory.subdirectories.get(*component).copied());
+        current = match existing {
+            Some(id) => id,
+            None => {
+                let id = tree.add_directory(current, component);
+                if let Some(directory) = tree.get_mut(id) {
+                    directory.read_only = true;
+                }
+                id
+            }
+        };
+    }
+    current
+}
+
+/// Monta um arquivo tar, somente leitura, como subdiretório `mount_name`
+pub fn mount_tar(
+    archive_path: &str,
+    mount_name: &str,
+    tree: &mut DirectoryTree,
+    parent: DirectoryId,
+    metadata_store: &mut MetadataStore,
+) -> Result<()> {
+    let mount_path = tree.path_of(parent).child(mount_name)?;
+    let parent_directory = tree.directory(parent)?;
+    if parent_directory.read_only {
+        return Err(read_only_error());
+    }
+    if parent_directory.subdirectories.contains_key(mount_name)
+        || parent_directory.files.contains(mount_name)
+    {
+        return Err(DiscoError::AlreadyExists(
+            "Mount point already exists".to_string(),
+        ));
+    }
+
+    let archive = std::fs::canonicalize(archive_path)?
+        .to_string_lossy()
+        .into_owned();
+    let mut entries = Vec::new();
+    for entry in read_tar_index(&archive)? {
+        // Caminhos que tentam sair da montagem são recusados
+        if entry.path.split('/').any(|c| c == "..") {
+            return Err(DiscoError::Corrupt {
+                message: format!("Unsafe path in archive: {}", entry.path),
+                source: None,
+            });
+        }
+        entries.push((VfsPath::parse(&entry.path)?, entry));
+    }
+
+    // Um mesmo caminho não pode ser arquivo e diretório (explícito ou pai de outra entrada)
+    let mut files = HashSet::new();
+    let mut directories = HashSet::new();
+    for (path, entry) in &entries {
+        let mut ancestor = path.parent();
+        while let Some(directory) = ancestor {
+            ancestor = directory.parent();
+            directories.insert(directory);
+        }
+        if entry.is_dir {
+            directories.insert(path.clone());
+        } else {
+            files.insert(path.clone());
+        }
+    }
+    if let Some(path) = files.intersection(&directories).next() {
+        return Err(DiscoError::Corrupt {
+            message: format!("Archive has both a file and a directory at '{}'", path),
+            source: None,
+        });
+    }
+    let created_at = chrono::Utc::now().to_rfc3339();
+
+    // O índice é válido: só agora a árvore é alterada
+    let mount_point = tree.add_directory(parent, mount_name);
+    tree.directory_mut(mount_point)?.read_only = true;
+
+    for (entry_path, entry) in entries {
+        let components: Vec<&str> = entry_path.components().collect();
+        let Some((file_name, parents)) = components.split_last() else {
+            continue;
+        };
+
+        if entry.is_dir {
+            ensure_mounted_directory(tree, mount_point, &components);
+            continue;
+        }
+
+        let directory = ensure_mounted_directory(tree, mount_point, parents);
+        let metadata = FileMetadata {
+            path: components
+                .iter()
+                .fold(mount_path.clone(), |path, component| path.child_unchecked(component))
+                .to_string(),
+            permissions: "r--r--r--".to_string(),
+            created_at: created_at.clone(),
+            modified_at: created_at.clone(),
+            size: entry.size,
+            block_indices: vec![],
+            archive: Some(ArchiveExtent {
+                archive: archive.clone(),
+                offset: entry.offset,
+            }),
+            versions: Vec::new(),
+            next_version: 0,
+        };
+        metadata_store.add_file(&metadata.path, metadata.clone());
+        tree.directory_mut(directory)?
+            .files
+            .insert(file_name.to_string());
+    }
+
+    update_directory_modified_time(tree.directory_mut(parent)?);
+
+    info!(
+        "Arquivo tar '{}' montado em '{}' (somente leitura)",
+        archive_path, mount_path
+    );
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::fs;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::update_file_metadata,
+    error::{DiscoError, Result},
+    file::FileMetadata,
+};
+
+pub const VERSIONING_FILE: &str = "versioning.json"; // Política de versões, no diretório de estado
+
+/// Conteúdo anterior de um arquivo, guardado por uma escrita com o versionamento ligado
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileVersion {
+    pub number: u32,
+    pub size: u64,
+    pub modified_at: String, // Quando este conteúdo foi escrito
+    pub replaced_at: String, // Quando deixou de ser o atual; a idade conta daqui
+    pub block_indices: Vec<usize>,
+}
+
+/// Quantas versões de cada arquivo são mantidas, e por quanto tempo
+///
+/// Sem limites, todas as versões ficam até o arquivo ser removido.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RetentionPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_versions: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>, // Em segundos
+}
+
+impl RetentionPolicy {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_versions == Some(0) {
+            return Err(DiscoError::InvalidArgument(
+                "Maximum number of versions must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Interpreta uma idade como `30d`, `12h`, `15m` ou `90s` (sem unidade, segundos)
+pub fn parse_max_age(value: &str) -> Result<u64> {
+    let invalid = || DiscoError::InvalidArgument(format!("Invalid age: '{}'", value));
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => (&value[..index], unit),
+        _ => (value, 's'),
+    };
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    number.checked_mul(seconds).ok_or_else(invalid)
+}
+
+/// Idade no formato aceito por `parse_max_age`, na maior unidade exata
+pub fn format_max_age(seconds: u64) -> String {
+    [('d', 24 * 60 * 60), ('h', 60 * 60), ('m', 60)]
+        .into_iter()
+        .find(|&(_, unit)| seconds > 0 && seconds.is_multiple_of(unit))
+        .map_or_else(
+            || format!("{}s", seconds),
+            |(suffix, unit)| format!("{}{}", seconds / unit, suffix),
+        )
+}
+
+/// Troca os blocos do arquivo pelos do novo conteúdo
+///
+/// Com uma política, o conteúdo anterior vira a versão seguinte e as versões que
+/// ela não retém mais saem da lista. Devolve os blocos que o arquivo deixou de
+/// referenciar, para o chamador liberar depois de gravar os metadados.
+pub fn replace_contents(
+    metadata: &mut FileMetadata,
+    blocks: Vec<usize>,
+    size: u64,
+    policy: Option<&RetentionPolicy>,
+) -> Vec<usize> {
+    let previous_blocks = std::mem::replace(&mut metadata.block_indices, blocks);
+    let previous_size = metadata.size;
+    let previous_modified_at = metadata.modified_at.clone();
+    update_file_metadata(metadata, size);
+
+    let Some(policy) = policy else {
+        return previous_blocks;
+    };
+    // Registros antigos não têm `next_version`: a numeração segue da última versão
+    let number = metadata.next_version.max(
+        metadata
+            .versions
+            .last()
+            .map_or(1, |version| version.number + 1),
+    );
+    metadata.next_version = number + 1;
+    metadata.versions.push(FileVersion {
+        number,
+        size: previous_size,
+        modified_at: previous_modified_at,
+        replaced_at: metadata.modified_at.clone(),
+        block_indices: previous_blocks,
+    });
+    expire_versions(metadata, policy, Utc::now())
+}
+
+/// Tira da lista as versões que a política não retém em `now`, devolvendo os
+/// blocos delas
+pub fn expire_versions(
+    metadata: &mut FileMetadata,
+    policy: &RetentionPolicy,
+    now: DateTime<Utc>,
+) -> Vec<usize> {
+    let excess = policy
+        .max_versions
+        .map_or(0, |max| metadata.versions.len().saturating_sub(max));
+    let oldest = policy
+        .max_age
+        .and_then(|age| Duration::try_seconds(i64::try_from(age).ok()?))
+        .and_then(|age| now.checked_sub_signed(age));
+    let is_expired = |position: usize, version: &FileVersion| {
+        position < excess
+            || oldest.is_some_and(|oldest| {
+                DateTime::parse_from_rfc3339(&version.replaced_at)
+                    .is_ok_and(|replaced_at| replaced_at < oldest)
+            })
+    };
+
+    let mut freed = Vec::new();
+    let mut position = 0;
+    metadata.versions.retain(|version| {
+        let expired = is_expired(position, version);
+        position += 1;
+        if expired {
+            freed.extend(&version.block_indices);
+        }
+        !expired
+    });
+    freed
+}
+
+pub fn load_policy(path: &str) -> Result<Option<RetentionPolicy>> {
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+pub fn save_policy(policy: Option<&RetentionPolicy>, path: &str) -> Result<()> {
+    let data = serde_json::to_string_pretty(&policy)?;
+    fs::write(path, data)?;
+    Ok(())
+}
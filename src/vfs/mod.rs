@@ -0,0 +1,1165 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    block::{BlockManager, MetadataStore, BLOCK_SIZE, MAGIC_NUMBER, TOTAL_BLOCKS},
+    cache::CacheStats,
+    directory::{
+        copy_path, create_directory, load_current_directory, load_hierarchy_with_report,
+        read_only_error, remove_directory, remove_path_recursive, rename_path,
+        save_current_directory, save_directory_metadata, save_hierarchy, share_blocks, DirectoryId,
+        DirectoryMetadata, DirectoryTree, MountSource, RemovalReport,
+    },
+    error::DiscoError,
+    file::{create_file_in_directory, read_contents, read_file, write_file_contents, FileMetadata},
+    fsck::{check, FsckReport},
+    migration::reclaim_orphaned_blocks,
+    path::{validate_name, VfsPath},
+    snapshot::{
+        load_snapshots, reference_blocks, release_references, save_snapshots, Snapshot,
+        SnapshotInfo, SNAPSHOTS_FILE,
+    },
+    tar::mount_tar,
+    versioning::{
+        expire_versions, load_policy, replace_contents, save_policy, FileVersion, RetentionPolicy,
+        VERSIONING_FILE,
+    },
+};
+
+pub const HIERARCHY_FILE: &str = "filesystem.json"; // Árvore de diretórios e índice de arquivos
+pub const CURRENT_DIRECTORY_FILE: &str = "current_directory.json";
+pub const METADATA_FILE: &str = "metadata.json"; // Cópia do índice, para inspeção
+pub const ROOT_DIRECTORY_FILE: &str = "root_directory.json"; // Cópia da árvore, para inspeção
+
+/// Tipo de uma entrada do sistema de arquivos virtual
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// Entrada retornada por `Vfs::ls`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub kind: EntryKind,
+}
+
+/// Informações de uma entrada retornadas por `Vfs::stat`
+#[derive(Debug, Clone)]
+pub struct Stat {
+    pub path: VfsPath,
+    pub kind: EntryKind,
+    pub size: u64, // Bytes do arquivo; em diretórios, o número de entradas
+    pub permissions: String,
+    pub created_at: String,
+    pub modified_at: String,
+    pub block_indices: Vec<usize>,
+    pub read_only: bool,
+}
+
+/// Metadados de uma entrada, como são gravados na hierarquia
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(untagged)]
+pub enum EntryMetadata<'a> {
+    File(&'a FileMetadata),
+    Directory(&'a DirectoryMetadata),
+}
+
+impl EntryMetadata<'_> {
+    pub fn kind(&self) -> EntryKind {
+        match self {
+            EntryMetadata::File(_) => EntryKind::File,
+            EntryMetadata::Directory(_) => EntryKind::Directory,
+        }
+    }
+}
+
+/// Ocupação do disco retornada por `Vfs::df`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiskUsage {
+    pub block_size: usize,
+    pub total_blocks: usize,
+    pub used_blocks: usize,
+    pub free_blocks: usize,
+    pub shared_blocks: usize, // Blocos referenciados por mais de um arquivo ou snapshot
+    pub files: usize,
+    pub directories: usize,
+    pub bytes: u64, // Soma dos tamanhos dos arquivos
+}
+
+/// Modo de abertura da imagem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenMode {
+    #[default]
+    ReadWrite,
+    /// A imagem é aberta sem permissão de escrita, as operações que alteram o
+    /// sistema de arquivos falham e nenhum arquivo de estado é gravado
+    ReadOnly,
+}
+
+/// Imagem montada, retornada por `Vfs::mounts`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MountInfo {
+    pub path: VfsPath,
+    pub image: PathBuf,
+}
+
+/// Sistema de arquivos virtual completo: disco, índice, árvore e diretório atual
+///
+/// Todas as operações passam por aqui, o que mantém os componentes consistentes
+/// entre si. As alterações ficam em memória até `sync`, exceto os dados dos
+/// arquivos, que o `BlockManager` grava diretamente na imagem.
+///
+/// Outras imagens podem ser montadas em diretórios vazios. Cada uma é um `Vfs`
+/// próprio, e os caminhos que passam por um ponto de montagem são repassados a
+/// ela; quando o diretório atual está dentro de uma montagem, `current_directory`
+/// aponta para o ponto de montagem e a imagem montada guarda o restante.
+pub struct Vfs {
+    image: PathBuf,
+    mode: OpenMode,
+    state_dir: PathBuf,
+    block_manager: BlockManager,
+    metadata_store: MetadataStore,
+    tree: DirectoryTree,
+    current_directory: DirectoryId,
+    mounts: BTreeMap<DirectoryId, Vfs>, // Imagens montadas, pelo ponto de montagem
+    snapshots: BTreeMap<String, Snapshot>,
+    versioning: Option<RetentionPolicy>, // Sem política, as escritas não guardam versões
+}
+
+fn busy_error(message: String) -> io::Error {
+    DiscoError::Busy(message).into()
+}
+
+fn directory_not_found(path: &VfsPath) -> io::Error {
+    DiscoError::NotFound(format!("Directory '{}' not found", path)).into()
+}
+
+fn snapshot_not_found(name: &str) -> io::Error {
+    DiscoError::NotFound(format!("Snapshot '{}' not found", name)).into()
+}
+
+fn cross_device_error(operation: &str) -> io::Error {
+    DiscoError::CrossDevice(format!("Cannot {} across mounted filesystems", operation)).into()
+}
+
+/// Diretório de estado padrão: o diretório da própria imagem
+fn default_state_dir(image: &str) -> PathBuf {
+    Path::new(image)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_path_buf()
+}
+
+/// Diretório de estado de uma imagem montada: `<imagem>.state`, ao lado dela
+///
+/// Cada imagem tem o seu, então imagens do mesmo diretório podem ser montadas
+/// juntas. Para abrir a imagem fora da montagem com o mesmo estado, basta
+/// passá-lo a `Vfs::open_with_state_dir`.
+pub fn mounted_state_dir(image: &Path) -> PathBuf {
+    let mut name = image.file_name().unwrap_or_default().to_os_string();
+    name.push(".state");
+    image.with_file_name(name)
+}
+
+/// Erro para um ponto de montagem cuja imagem não pôde ser aberta
+fn unavailable_error(tree: &DirectoryTree, mount: DirectoryId) -> io::Error {
+    DiscoError::NotFound(format!(
+        "Image mounted at '{}' is not available",
+        tree.path_of(mount)
+    ))
+    .into()
+}
+
+impl Vfs {
+    /// Abre (ou cria) a imagem, com o estado guardado no mesmo diretório dela
+    pub fn open(image: &str) -> io::Result<Self> {
+        Vfs::open_with_mode(image, default_state_dir(image), OpenMode::ReadWrite)
+    }
+
+    /// Abre uma imagem existente somente para leitura
+    pub fn open_read_only(image: &str) -> io::Result<Self> {
+        Vfs::open_with_mode(image, default_state_dir(image), OpenMode::ReadOnly)
+    }
+
+    /// Abre a imagem guardando a hierarquia e o diretório atual em `state_dir`
+    pub fn open_with_state_dir(image: &str, state_dir: impl Into<PathBuf>) -> io::Result<Self> {
+        Vfs::open_with_mode(image, state_dir, OpenMode::ReadWrite)
+    }
+
+    /// Abre a imagem no modo indicado, com o estado em `state_dir`
+    pub fn open_with_mode(
+        image: &str,
+        state_dir: impl Into<PathBuf>,
+        mode: OpenMode,
+    ) -> io::Result<Self> {
+        Vfs::open_nested(image, state_dir.into(), mode, &mut Vec::new())
+    }
+
+    /// Abre uma imagem e as que estiverem montadas nela
+    ///
+    /// `open_images` acumula as imagens já abertas, o que impede que a mesma
+    /// imagem seja aberta duas vezes (inclusive em um ciclo de montagens).
+    fn open_nested(
+        image: &str,
+        state_dir: PathBuf,
+        mode: OpenMode,
+        open_images: &mut Vec<PathBuf>,
+    ) -> io::Result<Self> {
+        // Uma imagem já aberta neste processo é recusada antes de esbarrar na trava
+        if let Ok(image) = fs::canonicalize(image) {
+            if open_images.contains(&image) {
+                return Err(busy_error(format!(
+                    "Image '{}' is already in use",
+                    image.display()
+                )));
+            }
+        }
+        let block_manager = match mode {
+            OpenMode::ReadWrite => BlockManager::initialize(image)?,
+            OpenMode::ReadOnly => BlockManager::open_read_only(image)?,
+        };
+        let image = fs::canonicalize(image)?;
+        open_images.push(image.clone());
+
+        let hierarchy_path = state_dir.join(HIERARCHY_FILE);
+        let (tree, metadata_store, migration) = if hierarchy_path.exists() {
+            load_hierarchy_with_report(&hierarchy_path.to_string_lossy())?
+        } else {
+            (DirectoryTree::new(), MetadataStore::new(), None)
+        };
+        // Os blocos das cópias descartadas na migração não têm mais dono
+        if let (Some(report), OpenMode::ReadWrite) = (&migration, mode) {
+            let reclaimed = reclaim_orphaned_blocks(report, &block_manager)?;
+            if reclaimed > 0 {
+                info!("{} bloco(s) órfão(s) da migração liberado(s)", reclaimed);
+            }
+        }
+
+        // O diretório atual é um caminho na árvore; se não existir mais, vale a raiz
+        let current_directory = load_current_directory(
+            &tree,
+            &state_dir.join(CURRENT_DIRECTORY_FILE).to_string_lossy(),
+        )
+        .unwrap_or(tree.root());
+
+        let snapshots_path = state_dir.join(SNAPSHOTS_FILE);
+        let snapshots = if snapshots_path.exists() {
+            load_snapshots(&snapshots_path.to_string_lossy())?
+        } else {
+            BTreeMap::new()
+        };
+        let versioning_path = state_dir.join(VERSIONING_FILE);
+        let versioning = if versioning_path.exists() {
+            load_policy(&versioning_path.to_string_lossy())?
+        } else {
+            None
+        };
+
+        let mut vfs = Vfs {
+            image,
+            mode,
+            state_dir,
+            block_manager,
+            metadata_store,
+            tree,
+            current_directory,
+            mounts: BTreeMap::new(),
+            snapshots,
+            versioning,
+        };
+        vfs.open_mounts(open_images);
+        Ok(vfs)
+    }
+
+    /// Abre as imagens montadas registradas na hierarquia
+    ///
+    /// Uma imagem que não abre continua registrada, mas inacessível, até o `umount`.
+    fn open_mounts(&mut self, open_images: &mut Vec<PathBuf>) {
+        let sources: Vec<(DirectoryId, MountSource)> = self
+            .tree
+            .mount_points()
+            .map(|(id, source)| (id, source.clone()))
+            .collect();
+        for (id, source) in sources {
+            let image = source.image.to_string_lossy();
+            match Vfs::open_nested(&image, source.state_dir, self.mode, open_images) {
+                Ok(mounted) => {
+                    self.mounts.insert(id, mounted);
+                }
+                Err(e) => warn!(
+                    "Imagem '{}' montada em '{}' indisponível: {}",
+                    image,
+                    self.tree.path_of(id),
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Grava os blocos alterados na imagem e depois a hierarquia, o índice e o
+    /// diretório atual no diretório de estado, e também os das imagens montadas
+    ///
+    /// Somente leitura, falha sem gravar nada: o diretório de estado não é tocado.
+    pub fn sync(&self) -> io::Result<()> {
+        self.check_writable()?;
+        for mounted in self.mounts.values() {
+            mounted.sync()?;
+        }
+
+        // Os metadados nunca apontam para blocos que ainda não estão na imagem
+        self.block_manager.sync()?;
+        let state_file = |name: &str| self.state_dir.join(name).to_string_lossy().into_owned();
+
+        self.metadata_store
+            .save_to_file(&state_file(METADATA_FILE))?;
+        save_directory_metadata(&self.tree, &state_file(ROOT_DIRECTORY_FILE))?;
+        save_hierarchy(
+            &self.tree,
+            &self.metadata_store,
+            &state_file(HIERARCHY_FILE),
+        )?;
+        save_current_directory(
+            &self.tree,
+            self.current_directory,
+            &state_file(CURRENT_DIRECTORY_FILE),
+        )?;
+        // Sem snapshots, o arquivo só é gravado se já existir, para ficar vazio
+        let snapshots_file = state_file(SNAPSHOTS_FILE);
+        if !self.snapshots.is_empty() || Path::new(&snapshots_file).exists() {
+            save_snapshots(&self.snapshots, &snapshots_file)?;
+        }
+        let versioning_file = state_file(VERSIONING_FILE);
+        if self.versioning.is_some() || Path::new(&versioning_file).exists() {
+            save_policy(self.versioning.as_ref(), &versioning_file)?;
+        }
+        Ok(())
+    }
+
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.mode == OpenMode::ReadOnly
+    }
+
+    /// Falha se a imagem foi aberta somente para leitura
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(DiscoError::ReadOnly("Filesystem is opened read-only".to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// Falha se há imagens montadas, que ficam de fora das snapshots
+    fn check_no_mounts(&self) -> io::Result<()> {
+        if self.tree.mount_points().next().is_some() {
+            return Err(busy_error(
+                "Cannot snapshot a filesystem with mounted images".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Diretório onde a hierarquia e o diretório atual são gravados
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
+    pub fn tree(&self) -> &DirectoryTree {
+        &self.tree
+    }
+
+    pub fn metadata_store(&self) -> &MetadataStore {
+        &self.metadata_store
+    }
+
+    pub(crate) fn metadata_store_mut(&mut self) -> &mut MetadataStore {
+        &mut self.metadata_store
+    }
+
+    pub(crate) fn block_manager(&self) -> &BlockManager {
+        &self.block_manager
+    }
+
+    /// Acertos, faltas e ocupação do cache de blocos desta imagem
+    pub fn cache_stats(&self) -> CacheStats {
+        self.block_manager.cache_stats()
+    }
+
+    /// Muda o tamanho do cache de blocos desta imagem e das montadas
+    pub fn set_cache_capacity(&self, blocks: usize) -> io::Result<()> {
+        for mounted in self.mounts.values() {
+            mounted.set_cache_capacity(blocks)?;
+        }
+        Ok(self.block_manager.set_cache_capacity(blocks)?)
+    }
+
+    /// Se há imagens montadas nesta
+    pub fn has_mounts(&self) -> bool {
+        !self.mounts.is_empty()
+    }
+
+    /// Caminho absoluto do diretório atual
+    pub fn pwd(&self) -> VfsPath {
+        let path = self.tree.path_of(self.current_directory);
+        match self.mounts.get(&self.current_directory) {
+            Some(mounted) => mounted
+                .pwd()
+                .components()
+                .fold(path, |path, name| path.child_unchecked(name)),
+            None => path,
+        }
+    }
+
+    /// Resolve um caminho absoluto ou relativo ao diretório atual
+    pub fn resolve(&self, path: &str) -> io::Result<VfsPath> {
+        Ok(self.pwd().join(path)?)
+    }
+
+    fn mounted(&self, mount: DirectoryId) -> io::Result<&Vfs> {
+        let tree = &self.tree;
+        self.mounts
+            .get(&mount)
+            .ok_or_else(|| unavailable_error(tree, mount))
+    }
+
+    fn mounted_mut(&mut self, mount: DirectoryId) -> io::Result<&mut Vfs> {
+        let tree = &self.tree;
+        self.mounts
+            .get_mut(&mount)
+            .ok_or_else(|| unavailable_error(tree, mount))
+    }
+
+    /// Se há algum ponto de montagem no diretório `id` ou abaixo dele
+    fn contains_mount(&self, id: DirectoryId) -> bool {
+        self.tree
+            .subtree(id)
+            .into_iter()
+            .any(|id| self.tree.get(id).is_some_and(|d| d.mount.is_some()))
+    }
+
+    /// Diretório pai e nome da entrada indicada por `path`
+    fn parent_and_name(&self, path: &str) -> io::Result<(DirectoryId, String)> {
+        let path = self.resolve(path)?;
+        let (Some(parent_path), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(DiscoError::InvalidPath("Path does not name an entry".to_string()).into());
+        };
+        let parent = self
+            .tree
+            .lookup(&parent_path)
+            .ok_or_else(|| directory_not_found(&parent_path))?;
+        Ok((parent, name.to_string()))
+    }
+
+    /// Cria um arquivo vazio
+    pub fn create(&mut self, path: &str, permissions: &str) -> io::Result<()> {
+        self.check_writable()?;
+        if let Some((mount, rest)) = self.tree.mount_point(&self.resolve(path)?) {
+            if rest.is_root() {
+                return Err(DiscoError::AlreadyExists(
+                    "A directory with this name already exists".to_string(),
+                )
+                .into());
+            }
+            return self
+                .mounted_mut(mount)?
+                .create(&rest.to_string(), permissions);
+        }
+        let (parent, name) = self.parent_and_name(path)?;
+        if self
+            .tree
+            .directory(parent)?
+            .subdirectories
+            .contains_key(&name)
+        {
+            return Err(DiscoError::AlreadyExists(
+                "A directory with this name already exists".to_string(),
+            )
+            .into());
+        }
+        create_file_in_directory(
+            &name,
+            &mut self.tree,
+            parent,
+            &mut self.metadata_store,
+            permissions,
+        )?;
+        Ok(())
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<String> {
+        let path = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&path) {
+            return self.mounted(mount)?.read(&rest.to_string());
+        }
+        let content = read_file(
+            &path.to_string(),
+            &self.metadata_store,
+            &self.block_manager,
+        )?;
+        Ok(content)
+    }
+
+    /// Substitui o conteúdo de um arquivo existente
+    pub fn write(&mut self, path: &str, data: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            return self.mounted_mut(mount)?.write(&rest.to_string(), data);
+        }
+        write_file_contents(
+            &target,
+            data,
+            &mut self.metadata_store,
+            &self.block_manager,
+            self.versioning.as_ref(),
+        )?;
+        Ok(())
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> io::Result<()> {
+        self.check_writable()?;
+        if let Some((mount, rest)) = self.tree.mount_point(&self.resolve(path)?) {
+            if rest.is_root() {
+                return Err(DiscoError::AlreadyExists(
+                    "Directory already exists".to_string(),
+                )
+                .into());
+            }
+            return self.mounted_mut(mount)?.mkdir(&rest.to_string());
+        }
+        let (parent, name) = self.parent_and_name(path)?;
+        if self.tree.directory(parent)?.files.contains(&name) {
+            return Err(DiscoError::AlreadyExists(
+                "A file with this name already exists".to_string(),
+            )
+            .into());
+        }
+        create_directory(&name, &mut self.tree, parent)?;
+        Ok(())
+    }
+
+    /// Lista um diretório: subdiretórios primeiro, cada grupo em ordem alfabética
+    pub fn ls(&self, path: &str) -> io::Result<Vec<DirectoryEntry>> {
+        let path = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&path) {
+            return self.mounted(mount)?.ls(&rest.to_string());
+        }
+        let id = self.tree.lookup(&path).ok_or_else(|| directory_not_found(&path))?;
+        let directory = self.tree.directory(id)?;
+
+        let mut subdirectories: Vec<&String> = directory.subdirectories.keys().collect();
+        subdirectories.sort();
+        let entries = subdirectories
+            .into_iter()
+            .map(|name| DirectoryEntry {
+                name: name.clone(),
+                kind: EntryKind::Directory,
+            })
+            .chain(directory.files.iter().map(|name| DirectoryEntry {
+                name: name.clone(),
+                kind: EntryKind::File,
+            }))
+            .collect();
+        Ok(entries)
+    }
+
+    /// Remove um arquivo ou, com `recursive`, um diretório e todo o seu conteúdo
+    pub fn rm(&mut self, path: &str, recursive: bool) -> io::Result<RemovalReport> {
+        self.check_writable()?;
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            if rest.is_root() {
+                return Err(busy_error(format!("'{}' is a mount point", target)));
+            }
+            return self.mounted_mut(mount)?.rm(&rest.to_string(), recursive);
+        }
+        if let Some(id) = self.tree.lookup(&target) {
+            if !recursive {
+                return Err(DiscoError::IsADirectory("Is a directory (use -r)".to_string()).into());
+            }
+            if self.contains_mount(id) {
+                return Err(busy_error(format!("'{}' contains a mount point", target)));
+            }
+        }
+
+        let report = remove_path_recursive(
+            &target,
+            &mut self.tree,
+            &mut self.metadata_store,
+            &self.block_manager,
+        )?;
+        self.reset_current_directory();
+        Ok(report)
+    }
+
+    /// Remove um diretório vazio
+    pub fn rmdir(&mut self, path: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            if rest.is_root() {
+                return Err(busy_error(format!("'{}' is a mount point", target)));
+            }
+            return self.mounted_mut(mount)?.rmdir(&rest.to_string());
+        }
+        if target.is_root() {
+            return Err(DiscoError::InvalidArgument(
+                "Cannot remove the root directory".to_string(),
+            )
+            .into());
+        }
+        let (parent, name) = self.parent_and_name(path)?;
+        remove_directory(&name, &mut self.tree, parent)?;
+        self.reset_current_directory();
+        Ok(())
+    }
+
+    /// Renomeia ou move um arquivo ou diretório
+    ///
+    /// Origem e destino precisam estar na mesma imagem; entre imagens montadas
+    /// diferentes o resultado é `CrossDevice`.
+    pub fn rename(&mut self, source: &str, destination: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let source = self.resolve(source)?;
+        let destination = self.resolve(destination)?;
+        match (
+            self.tree.mount_point(&source),
+            self.tree.mount_point(&destination),
+        ) {
+            (None, None) => {}
+            (Some((_, from)), _) if from.is_root() => {
+                return Err(busy_error(format!("'{}' is a mount point", source)));
+            }
+            (Some((mount, from)), Some((destination_mount, to))) if mount == destination_mount => {
+                return self
+                    .mounted_mut(mount)?
+                    .rename(&from.to_string(), &to.to_string());
+            }
+            _ => return Err(cross_device_error("move")),
+        }
+        rename_path(
+            &source,
+            &destination,
+            &mut self.tree,
+            &mut self.metadata_store,
+            &self.block_manager,
+        )?;
+        self.reset_current_directory();
+        Ok(())
+    }
+
+    /// Copia um arquivo ou, com `recursive`, um diretório
+    pub fn copy(
+        &mut self,
+        source: &str,
+        destination: &str,
+        recursive: bool,
+        reflink: bool,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        let source = self.resolve(source)?;
+        let destination = self.resolve(destination)?;
+        match (
+            self.tree.mount_point(&source),
+            self.tree.mount_point(&destination),
+        ) {
+            (None, None) => {}
+            (Some((mount, from)), Some((destination_mount, to))) if mount == destination_mount => {
+                return self.mounted_mut(mount)?.copy(
+                    &from.to_string(),
+                    &to.to_string(),
+                    recursive,
+                    reflink,
+                );
+            }
+            _ => return Err(cross_device_error("copy")),
+        }
+        // A cópia não atravessa montagens: o ponto de montagem viraria um diretório vazio
+        if let Some(id) = self.tree.lookup(&source) {
+            if self.contains_mount(id) {
+                return Err(busy_error(format!("'{}' contains a mount point", source)));
+            }
+        }
+        copy_path(
+            &source,
+            &destination,
+            &mut self.tree,
+            &mut self.metadata_store,
+            &self.block_manager,
+            recursive,
+            reflink,
+        )?;
+        Ok(())
+    }
+
+    /// Monta um arquivo tar, somente leitura, como subdiretório do diretório atual
+    pub fn mount_tar(&mut self, archive_path: &str, mount_name: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let current_directory = self.current_directory;
+        if self
+            .tree
+            .get(current_directory)
+            .is_some_and(|directory| directory.mount.is_some())
+        {
+            return self
+                .mounted_mut(current_directory)?
+                .mount_tar(archive_path, mount_name);
+        }
+        mount_tar(
+            archive_path,
+            mount_name,
+            &mut self.tree,
+            self.current_directory,
+            &mut self.metadata_store,
+        )?;
+        Ok(())
+    }
+
+    pub fn cd(&mut self, path: &str) -> io::Result<()> {
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            self.mounted_mut(mount)?.cd(&rest.to_string())?;
+            self.current_directory = mount;
+            return Ok(());
+        }
+        self.current_directory = self
+            .tree
+            .lookup(&target)
+            .ok_or_else(|| directory_not_found(&target))?;
+        Ok(())
+    }
+
+    pub fn stat(&self, path: &str) -> io::Result<Stat> {
+        let path = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&path) {
+            let mut stat = self.mounted(mount)?.stat(&rest.to_string())?;
+            stat.path = path;
+            return Ok(stat);
+        }
+
+        if let Some(id) = self.tree.lookup(&path) {
+            let directory = self.tree.directory(id)?;
+            let permissions = if directory.read_only {
+                "r-xr-xr-x"
+            } else {
+                "rwxr-xr-x"
+            };
+            return Ok(Stat {
+                kind: EntryKind::Directory,
+                size: (directory.files.len() + directory.subdirectories.len()) as u64,
+                permissions: permissions.to_string(),
+                created_at: directory.created_at.clone(),
+                modified_at: directory.modified_at.clone(),
+                block_indices: vec![],
+                read_only: directory.read_only,
+                path,
+            });
+        }
+
+        let metadata: &FileMetadata = self
+            .metadata_store
+            .get_file_metadata(&path.to_string())
+            .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?;
+        Ok(Stat {
+            kind: EntryKind::File,
+            size: metadata.size,
+            permissions: metadata.permissions.clone(),
+            created_at: metadata.created_at.clone(),
+            modified_at: metadata.modified_at.clone(),
+            block_indices: metadata.block_indices.clone(),
+            read_only: metadata.archive.is_some(),
+            path,
+        })
+    }
+
+    /// Metadados de um arquivo ou diretório
+    pub fn entry_metadata(&self, path: &str) -> io::Result<EntryMetadata<'_>> {
+        let path = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&path) {
+            return self.mounted(mount)?.entry_metadata(&rest.to_string());
+        }
+        if let Some(id) = self.tree.lookup(&path) {
+            return Ok(EntryMetadata::Directory(self.tree.directory(id)?));
+        }
+        self.metadata_store
+            .get_file_metadata(&path.to_string())
+            .map(EntryMetadata::File)
+            .ok_or_else(|| DiscoError::NotFound(format!("'{}' not found", path)).into())
+    }
+
+    /// Ocupação dos blocos e totais de arquivos e diretórios desta imagem
+    ///
+    /// As imagens montadas não entram na conta.
+    pub fn df(&self) -> DiskUsage {
+        let block_refs = self.block_manager.block_refs();
+        let used_blocks = block_refs.iter().filter(|&&refs| refs > 0).count();
+        let bytes = self
+            .metadata_store
+            .paths()
+            .filter_map(|path| self.metadata_store.get_file_metadata(path))
+            .map(|metadata| metadata.size)
+            .sum();
+        DiskUsage {
+            block_size: BLOCK_SIZE,
+            total_blocks: TOTAL_BLOCKS,
+            used_blocks,
+            free_blocks: TOTAL_BLOCKS - used_blocks,
+            shared_blocks: block_refs.iter().filter(|&&refs| refs > 1).count(),
+            files: self.metadata_store.len(),
+            directories: self.tree.len(),
+            bytes,
+        }
+    }
+
+    /// Verifica a consistência da árvore, do índice e do mapa de blocos
+    pub fn fsck(&self) -> io::Result<FsckReport> {
+        check(
+            &self.tree,
+            &self.metadata_store,
+            &self.snapshots,
+            &self.block_manager,
+        )
+    }
+
+    /// Política de versões desta imagem; `None` quando o versionamento está desligado
+    pub fn versioning(&self) -> Option<RetentionPolicy> {
+        self.versioning
+    }
+
+    /// Liga (com a política dada) ou desliga o versionamento desta imagem
+    ///
+    /// As imagens montadas têm a sua própria política. Ao ligar, as versões que a
+    /// política não retém são descartadas em todos os arquivos; depois disso, a
+    /// cada escrita no arquivo. Ao desligar, as versões existentes são mantidas.
+    pub fn set_versioning(&mut self, policy: Option<RetentionPolicy>) -> io::Result<()> {
+        self.check_writable()?;
+        if let Some(policy) = &policy {
+            policy.validate()?;
+        }
+        self.versioning = policy;
+        let Some(policy) = policy else {
+            return Ok(());
+        };
+
+        let now = Utc::now();
+        let paths: Vec<String> = self.metadata_store.paths().map(str::to_string).collect();
+        for path in paths {
+            let Some(metadata) = self.metadata_store.get_file_metadata(&path) else {
+                continue;
+            };
+            let mut updated_metadata = metadata.clone();
+            let expired_blocks = expire_versions(&mut updated_metadata, &policy, now);
+            if updated_metadata.versions.len() == metadata.versions.len() {
+                continue;
+            }
+            self.metadata_store
+                .update_file_metadata(&path, updated_metadata);
+            for block_index in expired_blocks {
+                self.block_manager.free_block(block_index)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Metadados do arquivo em `target`, que não pode estar numa montagem
+    fn file_metadata(&self, target: &VfsPath) -> io::Result<&FileMetadata> {
+        Ok(self
+            .metadata_store
+            .get_file_metadata(&target.to_string())
+            .ok_or_else(|| DiscoError::NotFound("File not found".to_string()))?)
+    }
+
+    /// Versões anteriores de um arquivo, da mais antiga à mais recente
+    pub fn versions(&self, path: &str) -> io::Result<Vec<FileVersion>> {
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            return self.mounted(mount)?.versions(&rest.to_string());
+        }
+        Ok(self.file_metadata(&target)?.versions.clone())
+    }
+
+    /// Lê o conteúdo de uma versão anterior de um arquivo
+    pub fn read_version(&self, path: &str, number: u32) -> io::Result<String> {
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            return self.mounted(mount)?.read_version(&rest.to_string(), number);
+        }
+        let metadata = self.file_metadata(&target)?;
+        let version = metadata.version(number)?;
+        let contents = FileMetadata {
+            size: version.size,
+            block_indices: version.block_indices.clone(),
+            versions: Vec::new(),
+            ..metadata.clone()
+        };
+        Ok(read_contents(&contents, &self.block_manager)?)
+    }
+
+    /// Volta um arquivo ao conteúdo de uma versão anterior
+    ///
+    /// A restauração é uma escrita: a versão continua na lista e, com o
+    /// versionamento ligado, o conteúdo substituído vira uma versão nova.
+    pub fn restore_version(&mut self, path: &str, number: u32) -> io::Result<()> {
+        self.check_writable()?;
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            return self
+                .mounted_mut(mount)?
+                .restore_version(&rest.to_string(), number);
+        }
+        let mut updated_metadata = self.file_metadata(&target)?.clone();
+        let version = updated_metadata.version(number)?.clone();
+
+        // O arquivo passa a compartilhar os blocos da versão
+        share_blocks(&version.block_indices, &self.block_manager)?;
+        let released_blocks = replace_contents(
+            &mut updated_metadata,
+            version.block_indices,
+            version.size,
+            self.versioning.as_ref(),
+        );
+        self.metadata_store
+            .update_file_metadata(&target.to_string(), updated_metadata);
+        for block_index in released_blocks {
+            self.block_manager.free_block(block_index)?;
+        }
+        info!("'{}' restaurado da versão {}", target, number);
+        Ok(())
+    }
+
+    /// Congela o estado atual numa snapshot
+    ///
+    /// Os blocos dos arquivos ganham uma referência a mais, e as escritas
+    /// seguintes vão para blocos novos sem tocar no conteúdo congelado.
+    pub fn snapshot_create(&mut self, name: &str) -> io::Result<()> {
+        self.check_writable()?;
+        self.check_no_mounts()?;
+        validate_name(name)?;
+        if self.snapshots.contains_key(name) {
+            return Err(
+                DiscoError::AlreadyExists(format!("Snapshot '{}' already exists", name)).into(),
+            );
+        }
+
+        reference_blocks(&self.metadata_store, &self.block_manager)?;
+        let snapshot = Snapshot::new(&self.tree, &self.metadata_store);
+        self.snapshots.insert(name.to_string(), snapshot);
+        info!("Snapshot '{}' criada", name);
+        Ok(())
+    }
+
+    /// Snapshots, em ordem de nome
+    pub fn snapshots(&self) -> Vec<SnapshotInfo> {
+        self.snapshots
+            .iter()
+            .map(|(name, snapshot)| snapshot.info(name, &self.block_manager))
+            .collect()
+    }
+
+    /// Apaga uma snapshot; os blocos que só ela usava voltam a ficar livres
+    pub fn snapshot_delete(&mut self, name: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let snapshot = self
+            .snapshots
+            .remove(name)
+            .ok_or_else(|| snapshot_not_found(name))?;
+        release_references(&snapshot.metadata_store, &self.block_manager)?;
+        info!("Snapshot '{}' apagada", name);
+        Ok(())
+    }
+
+    /// Volta o sistema de arquivos ao estado da snapshot, que continua existindo
+    ///
+    /// O diretório atual é mantido se ainda existir na snapshot; senão, vale a raiz.
+    pub fn snapshot_rollback(&mut self, name: &str) -> io::Result<()> {
+        self.check_writable()?;
+        self.check_no_mounts()?;
+        let snapshot = self
+            .snapshots
+            .get(name)
+            .ok_or_else(|| snapshot_not_found(name))?;
+
+        // Os blocos da snapshot passam a ser usados também pelo estado restaurado
+        reference_blocks(&snapshot.metadata_store, &self.block_manager)?;
+        let current_directory = self.tree.path_of(self.current_directory);
+        self.tree = snapshot.tree.clone();
+        let metadata_store =
+            std::mem::replace(&mut self.metadata_store, snapshot.metadata_store.clone());
+        self.current_directory = self
+            .tree
+            .lookup(&current_directory)
+            .unwrap_or(self.tree.root());
+
+        // Como numa escrita, os blocos antigos só são liberados depois da troca
+        release_references(&metadata_store, &self.block_manager)?;
+        info!("Sistema de arquivos restaurado da snapshot '{}'", name);
+        Ok(())
+    }
+
+    /// Imagens montadas, inclusive as montadas dentro de outras montagens
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        let mut mounts = Vec::new();
+        for (id, source) in self.tree.mount_points() {
+            let path = self.tree.path_of(id);
+            mounts.push(MountInfo {
+                path: path.clone(),
+                image: source.image.clone(),
+            });
+            if let Some(mounted) = self.mounts.get(&id) {
+                mounts.extend(mounted.mounts().into_iter().map(|nested| {
+                    MountInfo {
+                        path: nested
+                            .path
+                            .components()
+                            .fold(path.clone(), |path, name| path.child_unchecked(name)),
+                        image: nested.image,
+                    }
+                }));
+            }
+        }
+        mounts.sort_by(|a, b| a.path.cmp(&b.path));
+        mounts
+    }
+
+    /// Imagens e diretórios de estado abertos, desta instância e das montadas
+    fn images_in_use(&self) -> Vec<(PathBuf, PathBuf)> {
+        let state_dir =
+            fs::canonicalize(&self.state_dir).unwrap_or_else(|_| self.state_dir.clone());
+        let mut images = vec![(self.image.clone(), state_dir)];
+        for mounted in self.mounts.values() {
+            images.extend(mounted.images_in_use());
+        }
+        images
+    }
+
+    /// Monta outra imagem em um diretório vazio
+    ///
+    /// O estado da imagem fica em `mounted_state_dir`, criado na primeira
+    /// montagem. O ponto de montagem fica gravado na hierarquia e é reaberto
+    /// junto com ela.
+    pub fn mount(&mut self, image: &str, path: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let image = fs::canonicalize(image)
+            .map_err(|_| DiscoError::NotFound(format!("Image '{}' not found", image)))?;
+        let state_dir = mounted_state_dir(&image);
+
+        let in_use = self.images_in_use();
+        for (open_image, open_state_dir) in &in_use {
+            if *open_image == image {
+                return Err(busy_error(format!(
+                    "Image '{}' is already in use",
+                    image.display()
+                )));
+            }
+            if *open_state_dir == state_dir {
+                return Err(busy_error(format!(
+                    "State directory '{}' is already in use by '{}'",
+                    state_dir.display(),
+                    open_image.display()
+                )));
+            }
+        }
+
+        fs::create_dir_all(&state_dir)?;
+        let mut open_images = in_use.into_iter().map(|(image, _)| image).collect();
+        self.attach(MountSource { image, state_dir }, path, &mut open_images)
+    }
+
+    /// Registra a montagem no diretório indicado, nesta imagem ou em uma montada
+    fn attach(
+        &mut self,
+        source: MountSource,
+        path: &str,
+        open_images: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        let target = self.resolve(path)?;
+        if let Some((mount, rest)) = self.tree.mount_point(&target) {
+            if rest.is_root() {
+                return Err(busy_error(format!("'{}' is already a mount point", target)));
+            }
+            return self
+                .mounted_mut(mount)?
+                .attach(source, &rest.to_string(), open_images);
+        }
+        if target.is_root() {
+            return Err(DiscoError::InvalidArgument(
+                "Cannot mount over the root directory".to_string(),
+            )
+            .into());
+        }
+        let id = self.tree.lookup(&target).ok_or_else(|| directory_not_found(&target))?;
+        let directory = self.tree.directory(id)?;
+        if directory.read_only {
+            return Err(read_only_error().into());
+        }
+        if !directory.files.is_empty() || !directory.subdirectories.is_empty() {
+            return Err(DiscoError::NotEmpty("Mount point is not empty".to_string()).into());
+        }
+
+        let image = source.image.to_string_lossy().into_owned();
+        let mut mounted =
+            Vfs::open_nested(&image, source.state_dir.clone(), self.mode, open_images)?;
+        if mounted.block_manager.read_magic()? != MAGIC_NUMBER {
+            return Err(DiscoError::Corrupt {
+                message: format!("'{}' is not a disco image", image),
+                source: None,
+            }
+            .into());
+        }
+        mounted.current_directory = mounted.tree.root();
+
+        self.tree.directory_mut(id)?.mount = Some(source);
+        self.mounts.insert(id, mounted);
+        info!("Imagem '{}' montada em '{}'", image, target);
+        Ok(())
+    }
+
+    /// Desmonta a imagem montada em `path`, gravando antes o estado dela
+    pub fn umount(&mut self, path: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let target = self.resolve(path)?;
+        let Some((mount, rest)) = self.tree.mount_point(&target) else {
+            return Err(DiscoError::InvalidArgument(
+                format!("'{}' is not a mount point", target),
+            )
+            .into());
+        };
+        if !rest.is_root() {
+            return self.mounted_mut(mount)?.umount(&rest.to_string());
+        }
+
+        if let Some(mounted) = self.mounts.get(&mount) {
+            if mounted.tree.mount_points().next().is_some() {
+                return Err(busy_error(format!(
+                    "Image mounted at '{}' has mounted images",
+                    target
+                )));
+            }
+            mounted.sync()?;
+        }
+        self.mounts.remove(&mount);
+        self.tree.directory_mut(mount)?.mount = None;
+        info!("Imagem desmontada de '{}'", target);
+        Ok(())
+    }
+
+    /// Volta para a raiz se o diretório atual deixou de existir
+    fn reset_current_directory(&mut self) {
+        if self.tree.get(self.current_directory).is_none() {
+            self.current_directory = self.tree.root();
+        }
+    }
+}